@@ -0,0 +1,41 @@
+use std::env;
+use std::fs;
+
+use criterion::criterion_group;
+use criterion::criterion_main;
+use criterion::BenchmarkId;
+use criterion::Criterion;
+use pj::bench::TreeSpec;
+use pj::FinderBuilder;
+
+fn search(c: &mut Criterion) {
+    let root = env::temp_dir().join(format!("pj-bench-{}", std::process::id()));
+    let spec = TreeSpec {
+        breadth: 6,
+        depth: 4,
+        ..TreeSpec::default()
+    };
+    pj::bench::generate(&root, &spec).expect("failed to generate tree");
+
+    let mut group = c.benchmark_group("search");
+    for threads in [1, 2, 4, 8] {
+        for no_prune in [false, true] {
+            let finder = FinderBuilder::new(r"\.git")
+                .root(&root)
+                .threads(Some(threads))
+                .no_prune(no_prune)
+                .build()
+                .unwrap();
+            let name = if no_prune { "no_prune" } else { "prune" };
+            group.bench_with_input(BenchmarkId::new(name, threads), &finder, |b, finder| {
+                b.iter(|| finder.run().count())
+            });
+        }
+    }
+    group.finish();
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+criterion_group!(benches, search);
+criterion_main!(benches);
@@ -0,0 +1,163 @@
+//! Adjusting how many workers search at once to the file system being
+//! searched. On a local SSD a search is CPU-bound and more threads than
+//! CPUs just contend with each other, while on NFS each worker spends
+//! most of its time waiting on the server, and ten times as many keep
+//! it busier.
+//!
+//! Rayon's pools can't be resized, so the pool is built with the most
+//! workers we'd ever want, and jobs wait for a turn to run. Every so
+//! often, the share of their time jobs spent in `read_dir` decides
+//! whether there are more or fewer turns to go around.
+
+use std::cell::Cell;
+use std::sync::Condvar;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+/// How often the number of active workers is reconsidered.
+const ADJUST_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Above this share of their time spent reading directories, workers
+/// are waiting on the file system and more of them can help.
+const IO_BOUND: f64 = 0.5;
+
+/// Below this share, workers are mostly matching and queueing, and the
+/// extra ones are only getting in each other's way.
+const CPU_BOUND: f64 = 0.25;
+
+thread_local! {
+    /// Whether this thread is already running a job, which it might
+    /// run another inside of with --max-queue.
+    static ACTIVE: Cell<bool> = const { Cell::new(false) };
+    /// Time the current job has spent reading directories.
+    static READING: Cell<Duration> = const { Cell::new(Duration::ZERO) };
+}
+
+/// A semaphore for running jobs whose number of permits changes with
+/// how I/O-bound they are.
+pub(crate) struct AdaptiveWorkers {
+    min: usize,
+    max: usize,
+    state: Mutex<State>,
+    changed: Condvar,
+}
+
+struct State {
+    /// How many jobs may run at once.
+    limit: usize,
+    active: usize,
+    /// Jobs waiting for their turn.
+    waiting: usize,
+    /// Since the limit was last reconsidered.
+    since: Instant,
+    busy: Duration,
+    reading: Duration,
+}
+
+impl AdaptiveWorkers {
+    /// Starts out with `min` workers, and never goes above `max`.
+    pub(crate) fn new(min: usize, max: usize) -> Self {
+        AdaptiveWorkers {
+            min,
+            max,
+            state: Mutex::new(State {
+                limit: min,
+                active: 0,
+                waiting: 0,
+                since: Instant::now(),
+                busy: Duration::ZERO,
+                reading: Duration::ZERO,
+            }),
+            changed: Condvar::new(),
+        }
+    }
+
+    /// Waits for a turn to run a job, which lasts until the guard is
+    /// dropped. Returns None if this thread is already running one.
+    pub(crate) fn start(&self) -> Option<ActiveWorker<'_>> {
+        if ACTIVE.get() {
+            return None;
+        }
+        let mut state = self.state.lock().unwrap();
+        state.waiting += 1;
+        while state.active >= state.limit {
+            state = self.changed.wait(state).unwrap();
+        }
+        state.waiting -= 1;
+        state.active += 1;
+        ACTIVE.set(true);
+        READING.set(Duration::ZERO);
+        Some(ActiveWorker {
+            workers: self,
+            started: Instant::now(),
+        })
+    }
+
+    fn finish(&self, busy: Duration, reading: Duration) {
+        let mut state = self.state.lock().unwrap();
+        state.active -= 1;
+        state.busy += busy;
+        state.reading += reading;
+        let limit = state.limit;
+        if state.since.elapsed() >= ADJUST_INTERVAL {
+            self.adjust(&mut state);
+        }
+        let grown = state.limit > limit;
+        drop(state);
+        match grown {
+            true => self.changed.notify_all(),
+            false => self.changed.notify_one(),
+        }
+    }
+
+    /// Grows the limit by a quarter while workers are I/O-bound and
+    /// there are jobs waiting for them, and shrinks it by a quarter
+    /// while they're CPU-bound.
+    fn adjust(&self, state: &mut State) {
+        let io_share = match state.busy.is_zero() {
+            true => 0.0,
+            false => state.reading.as_secs_f64() / state.busy.as_secs_f64(),
+        };
+        let step = (state.limit / 4).max(1);
+        let limit = if io_share > IO_BOUND && state.waiting > 0 {
+            (state.limit + step).min(self.max)
+        } else if io_share < CPU_BOUND {
+            state.limit.saturating_sub(step).max(self.min)
+        } else {
+            state.limit
+        };
+        if limit != state.limit {
+            tracing::debug!(
+                "Running {} workers instead of {}, since they spent {:.0}% of their time reading directories",
+                limit,
+                state.limit,
+                io_share * 100.0
+            );
+            state.limit = limit;
+        }
+        state.since = Instant::now();
+        state.busy = Duration::ZERO;
+        state.reading = Duration::ZERO;
+    }
+}
+
+/// Counts `duration` towards the time this thread's job has spent
+/// reading directories.
+pub(crate) fn add_read_time(duration: Duration) {
+    READING.set(READING.get() + duration);
+}
+
+/// A job's turn to run, from [`AdaptiveWorkers::start`].
+pub(crate) struct ActiveWorker<'a> {
+    workers: &'a AdaptiveWorkers,
+    started: Instant,
+}
+
+impl Drop for ActiveWorker<'_> {
+    fn drop(&mut self) {
+        ACTIVE.set(false);
+        self.workers
+            .finish(self.started.elapsed(), READING.replace(Duration::ZERO));
+    }
+}
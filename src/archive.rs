@@ -0,0 +1,539 @@
+//! Searching inside archives, by showing each one as a directory next
+//! to it whose name ends in `!`. A project inside `snapshots/old.tar`
+//! is searched at `snapshots/old.tar!/project`, and shown as
+//! `snapshots/old.tar!project`.
+
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::collections::VecDeque;
+use std::ffi::OsStr;
+use std::io;
+use std::io::BufReader;
+use std::io::Cursor;
+use std::io::Read;
+use std::path::Component;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::SystemTime;
+
+use flate2::read::GzDecoder;
+
+use crate::file_system::DirEntry;
+use crate::file_system::FileSystem;
+use crate::file_system::FileType;
+use crate::file_system::Metadata;
+use crate::file_system::ReadDir;
+use crate::file_system::ReadSeek;
+
+/// How many archives' listings are kept. Searches which run for a
+/// long time, like watches, shouldn't hold on to every archive they've
+/// come across.
+const MAX_CACHED_LISTINGS: usize = 64;
+
+/// How many compressed archives are kept decompressed.
+const MAX_CACHED_CONTENTS: usize = 4;
+
+/// Compressed archives bigger than this once decompressed aren't kept
+/// in memory, so they're decompressed again for each file read.
+const MAX_CACHED_CONTENTS_LEN: u64 = 64 * 1024 * 1024;
+
+/// Wraps another file system to add the contents of its archives.
+/// Archives inside archives are left as files.
+#[derive(Debug)]
+pub(crate) struct ArchiveFileSystem {
+    inner: Arc<dyn FileSystem>,
+    /// The entries of each archive which has been opened, so that it's
+    /// only read once however many of its directories are searched.
+    listings: Cache<Arc<Listing>>,
+    /// The decompressed contents of compressed archives, so that
+    /// reading each file in one doesn't decompress it all again. None
+    /// if it was too big to keep.
+    contents: Cache<Option<Arc<[u8]>>>,
+}
+
+/// Identifies a version of an archive, so that caches notice when it's
+/// changed.
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct ArchiveKey {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    size: Option<u64>,
+}
+
+/// Values for the archives used most recently, oldest first.
+#[derive(Debug)]
+struct Cache<T> {
+    capacity: usize,
+    entries: Mutex<VecDeque<(ArchiveKey, T)>>,
+}
+
+impl<T: Clone> Cache<T> {
+    fn new(capacity: usize) -> Self {
+        Cache {
+            capacity,
+            entries: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Returns the value for `key`, making it with `make` if it isn't
+    /// cached. Other workers can use the cache while it's being made.
+    fn get_or_make(&self, key: &ArchiveKey, make: impl FnOnce() -> io::Result<T>) -> io::Result<T> {
+        {
+            let mut entries = self.entries.lock().unwrap();
+            if let Some(i) = entries.iter().position(|(cached, _)| cached == key) {
+                let entry = entries.remove(i).unwrap();
+                let value = entry.1.clone();
+                entries.push_back(entry);
+                return Ok(value);
+            }
+        }
+        let value = make()?;
+        let mut entries = self.entries.lock().unwrap();
+        // Anything cached for an older version of the archive is stale.
+        entries.retain(|(cached, _)| cached.path != key.path);
+        if entries.len() >= self.capacity {
+            entries.pop_front();
+        }
+        entries.push_back((key.clone(), value.clone()));
+        Ok(value)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Format {
+    Tar,
+    TarGz,
+    Zip,
+}
+
+impl Format {
+    fn of(file_name: &OsStr) -> Option<Self> {
+        let name = file_name.to_str()?.to_ascii_lowercase();
+        if name.ends_with(".tar") {
+            Some(Format::Tar)
+        } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(Format::TarGz)
+        } else if name.ends_with(".zip") {
+            Some(Format::Zip)
+        } else {
+            None
+        }
+    }
+}
+
+/// Whether `file_name` is the directory standing in for an archive.
+pub(crate) fn is_archive_dir(file_name: &OsStr) -> bool {
+    (file_name.to_str())
+        .and_then(|name| name.strip_suffix('!'))
+        .is_some_and(|stem| Format::of(stem.as_ref()).is_some())
+}
+
+/// Every entry in an archive, by its path inside the archive.
+#[derive(Debug, Default)]
+struct Listing {
+    entries: BTreeMap<PathBuf, ListingEntry>,
+}
+
+#[derive(Debug)]
+struct ListingEntry {
+    file_type: FileType,
+    modified: Option<SystemTime>,
+    /// Uncompressed, for files.
+    size: Option<u64>,
+    permissions: Option<u32>,
+}
+
+/// Where a path points inside an archive.
+struct ArchivePath {
+    archive: PathBuf,
+    format: Format,
+    inner: PathBuf,
+}
+
+impl ArchiveFileSystem {
+    pub(crate) fn new(inner: Arc<dyn FileSystem>) -> Self {
+        ArchiveFileSystem {
+            inner,
+            listings: Cache::new(MAX_CACHED_LISTINGS),
+            contents: Cache::new(MAX_CACHED_CONTENTS),
+        }
+    }
+
+    /// Splits `path` at the first archive it goes into, if any. The
+    /// path inside can follow the `!` straight away, the way paths are
+    /// shown, or after a separator.
+    fn split(path: &Path) -> Option<ArchivePath> {
+        let mut archive = PathBuf::new();
+        let mut components = path.components();
+        for component in components.by_ref() {
+            let split = match component {
+                Component::Normal(name) => name.to_str().and_then(split_archive_name),
+                _ => None,
+            };
+            if let Some((stem, format, rest)) = split {
+                archive.push(stem);
+                let mut inner = PathBuf::from(rest);
+                inner.extend(components);
+                return Some(ArchivePath {
+                    archive,
+                    format,
+                    inner,
+                });
+            }
+            archive.push(component);
+        }
+        None
+    }
+
+    fn key(&self, archive: &Path) -> io::Result<ArchiveKey> {
+        let metadata = self.inner.metadata(archive)?;
+        Ok(ArchiveKey {
+            path: archive.to_path_buf(),
+            modified: metadata.modified,
+            size: metadata.size,
+        })
+    }
+
+    fn listing(&self, archive: &Path, format: Format) -> io::Result<Arc<Listing>> {
+        let key = self.key(archive)?;
+        (self.listings).get_or_make(&key, || Ok(Arc::new(self.read_listing(&key, format)?)))
+    }
+
+    /// Opens a tar archive, decompressing it if need be.
+    fn open_tar(&self, key: &ArchiveKey, format: Format) -> io::Result<Box<dyn Read>> {
+        let file = BufReader::new(self.inner.open(&key.path)?);
+        if format != Format::TarGz {
+            return Ok(Box::new(file));
+        }
+        let contents = self.contents.get_or_make(key, || {
+            let mut contents = Vec::new();
+            (GzDecoder::new(file))
+                .take(MAX_CACHED_CONTENTS_LEN + 1)
+                .read_to_end(&mut contents)?;
+            Ok((contents.len() as u64 <= MAX_CACHED_CONTENTS_LEN).then(|| contents.into()))
+        })?;
+        Ok(match contents {
+            Some(contents) => Box::new(Cursor::new(contents)),
+            None => Box::new(GzDecoder::new(BufReader::new(self.inner.open(&key.path)?))),
+        })
+    }
+
+    fn read_listing(&self, key: &ArchiveKey, format: Format) -> io::Result<Listing> {
+        let mut listing = Listing::default();
+        match format {
+            Format::Tar | Format::TarGz => {
+                let mut tar = tar::Archive::new(self.open_tar(key, format)?);
+                for entry in tar.entries()? {
+                    let entry = entry?;
+                    let header = entry.header();
+                    let file_type = if header.entry_type().is_dir() {
+                        FileType::Dir
+                    } else if header.entry_type().is_symlink() {
+                        FileType::Symlink
+                    } else {
+                        FileType::File
+                    };
+                    let modified = (header.mtime().ok())
+                        .map(|mtime| SystemTime::UNIX_EPOCH + Duration::from_secs(mtime));
+                    let size = header.size().ok();
+                    let permissions = header.mode().ok().map(|mode| mode & 0o7777);
+                    listing.add(
+                        normalize(&entry.path()?),
+                        file_type,
+                        modified,
+                        size,
+                        permissions,
+                    );
+                }
+            }
+            Format::Zip => {
+                let mut zip = zip::ZipArchive::new(BufReader::new(self.inner.open(&key.path)?))?;
+                for i in 0..zip.len() {
+                    let entry = zip.by_index_raw(i)?;
+                    let Some(path) = entry.enclosed_name() else {
+                        continue;
+                    };
+                    let file_type = if entry.is_dir() {
+                        FileType::Dir
+                    } else if entry.is_symlink() {
+                        FileType::Symlink
+                    } else {
+                        FileType::File
+                    };
+                    let permissions = entry.unix_mode().map(|mode| mode & 0o7777);
+                    listing.add(path, file_type, None, Some(entry.size()), permissions);
+                }
+            }
+        }
+        Ok(listing)
+    }
+}
+
+/// Splits the name of an archive's directory, or of something inside
+/// it written without a separator, into the archive's name, its format
+/// and the rest.
+fn split_archive_name(name: &str) -> Option<(&str, Format, &str)> {
+    name.match_indices('!').find_map(|(i, _)| {
+        let stem = &name[..i];
+        Some((stem, Format::of(stem.as_ref())?, &name[i + 1..]))
+    })
+}
+
+impl FileSystem for ArchiveFileSystem {
+    fn read_dir(&self, path: &Path) -> io::Result<ReadDir<'_>> {
+        let Some(ArchivePath {
+            archive,
+            format,
+            inner,
+        }) = Self::split(path)
+        else {
+            // Each archive gets a directory next to it.
+            let entries = self.inner.read_dir(path)?.flat_map(|dir_entry| {
+                let archive_dir = match &dir_entry {
+                    Ok(dir_entry) if dir_entry.file_type == FileType::File => {
+                        Format::of(&dir_entry.file_name).map(|_| {
+                            let mut file_name = dir_entry.file_name.clone();
+                            file_name.push("!");
+                            Ok(DirEntry {
+                                file_name,
+                                file_type: FileType::Dir,
+                            })
+                        })
+                    }
+                    _ => None,
+                };
+                std::iter::once(dir_entry).chain(archive_dir)
+            });
+            return Ok(Box::new(entries));
+        };
+        let listing = self.listing(&archive, format)?;
+        if !is_dir(&listing, &inner) {
+            return Err(io::Error::other("Not a directory"));
+        }
+        let children: Vec<io::Result<DirEntry>> = (listing.entries.range(inner.clone()..))
+            .take_while(|(path, _)| path.starts_with(&inner))
+            .filter(|(path, _)| path.parent() == Some(inner.as_path()))
+            .filter_map(|(path, entry)| {
+                Some(Ok(DirEntry {
+                    file_name: path.file_name()?.to_os_string(),
+                    file_type: entry.file_type,
+                }))
+            })
+            .collect();
+        Ok(Box::new(children.into_iter()))
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        let Some(ArchivePath {
+            archive,
+            format,
+            inner,
+        }) = Self::split(path)
+        else {
+            return self.inner.metadata(path);
+        };
+        // The archive's device is the one its contents are on, but
+        // they don't have inodes of their own.
+        let archive_metadata = self.inner.metadata(&archive)?;
+        let listing = self.listing(&archive, format)?;
+        let (file_type, modified, size, permissions) = match listing.entries.get(&inner) {
+            Some(entry) => (
+                entry.file_type,
+                entry.modified,
+                entry.size,
+                entry.permissions,
+            ),
+            None if is_dir(&listing, &inner) => {
+                (FileType::Dir, archive_metadata.modified, None, None)
+            }
+            None => return Err(io::Error::from(io::ErrorKind::NotFound)),
+        };
+        Ok(Metadata {
+            file_type,
+            modified,
+            device: archive_metadata.device,
+            inode: None,
+            size,
+            permissions,
+            uid: archive_metadata.uid,
+        })
+    }
+
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf> {
+        match Self::split(path) {
+            Some(_) => Err(io::Error::new(io::ErrorKind::InvalidInput, "Not a symlink")),
+            None => self.inner.read_link(path),
+        }
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        let Some(ArchivePath { archive, inner, .. }) = Self::split(path) else {
+            return self.inner.canonicalize(path);
+        };
+        let mut archive_dir = self.inner.canonicalize(&archive)?.into_os_string();
+        archive_dir.push("!");
+        Ok(PathBuf::from(archive_dir).join(inner))
+    }
+
+    /// Joins the archive's directory to the path inside it, e.g.
+    /// `old.tar!project` rather than `old.tar!/project`.
+    fn display<'a>(&self, path: &'a Path) -> Cow<'a, Path> {
+        let mut shown = PathBuf::new();
+        let mut components = path.components();
+        while let Some(component) = components.next() {
+            if let Component::Normal(name) = component {
+                if is_archive_dir(name) {
+                    let Some(first) = components.next() else {
+                        break;
+                    };
+                    let mut joined = name.to_os_string();
+                    joined.push(first);
+                    shown.push(joined);
+                    shown.extend(components);
+                    return Cow::Owned(shown);
+                }
+            }
+            shown.push(component);
+        }
+        Cow::Borrowed(path)
+    }
+
+    fn read(&self, path: &Path, limit: u64) -> io::Result<Vec<u8>> {
+        let Some(ArchivePath {
+            archive,
+            format,
+            inner,
+        }) = Self::split(path)
+        else {
+            return self.inner.read(path, limit);
+        };
+        let mut contents = Vec::new();
+        match format {
+            Format::Tar | Format::TarGz => {
+                let mut tar = tar::Archive::new(self.open_tar(&self.key(&archive)?, format)?);
+                for entry in tar.entries()? {
+                    let entry = entry?;
+                    if normalize(&entry.path()?) == inner {
+                        entry.take(limit).read_to_end(&mut contents)?;
+                        return Ok(contents);
+                    }
+                }
+                Err(io::Error::from(io::ErrorKind::NotFound))
+            }
+            Format::Zip => {
+                let mut zip = zip::ZipArchive::new(BufReader::new(self.inner.open(&archive)?))?;
+                for i in 0..zip.len() {
+                    let entry = zip.by_index(i)?;
+                    let Some(entry_path) = entry.enclosed_name() else {
+                        continue;
+                    };
+                    if entry_path == inner {
+                        entry.take(limit).read_to_end(&mut contents)?;
+                        return Ok(contents);
+                    }
+                }
+                Err(io::Error::from(io::ErrorKind::NotFound))
+            }
+        }
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn ReadSeek>> {
+        if Self::split(path).is_none() {
+            return self.inner.open(path);
+        }
+        Ok(Box::new(Cursor::new(self.read(path, u64::MAX)?)))
+    }
+}
+
+/// Whether `inner` is a directory in the archive, either listed as one
+/// or implied by the entries beneath it.
+fn is_dir(listing: &Listing, inner: &Path) -> bool {
+    if inner.as_os_str().is_empty() {
+        return true;
+    }
+    match listing.entries.get(inner) {
+        Some(entry) => entry.file_type.is_dir(),
+        None => (listing.entries.keys()).any(|path| path.starts_with(inner)),
+    }
+}
+
+/// Drops `.` components and anything which would escape the archive,
+/// like a leading `/` or `..`, so a path can be used to look up an
+/// entry.
+fn normalize(path: &Path) -> PathBuf {
+    path.components()
+        .filter_map(|component| match component {
+            Component::Normal(name) => Some(name),
+            _ => None,
+        })
+        .collect()
+}
+
+impl Listing {
+    fn add(
+        &mut self,
+        path: PathBuf,
+        file_type: FileType,
+        modified: Option<SystemTime>,
+        size: Option<u64>,
+        permissions: Option<u32>,
+    ) {
+        if path.as_os_str().is_empty() {
+            return;
+        }
+        // Archives don't always list directories, so each entry
+        // implies the ones above it.
+        for ancestor in path.ancestors().skip(1) {
+            if ancestor.as_os_str().is_empty() {
+                break;
+            }
+            self.entries
+                .entry(ancestor.to_path_buf())
+                .or_insert(ListingEntry {
+                    file_type: FileType::Dir,
+                    modified: None,
+                    size: None,
+                    permissions: None,
+                });
+        }
+        self.entries.insert(
+            path,
+            ListingEntry {
+                file_type,
+                modified,
+                size: size.filter(|_| !file_type.is_dir()),
+                permissions,
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_system::MemoryFileSystem;
+    use crate::FinderBuilder;
+
+    #[test]
+    fn archives_are_read_through_the_inner_file_system() {
+        let mut tar = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_size(0);
+        header.set_mode(0o644);
+        tar.append_data(&mut header, "project/Cargo.toml", io::empty())
+            .unwrap();
+        let mut file_system = MemoryFileSystem::new();
+        file_system.add_file("/src/old.tar", tar.into_inner().unwrap());
+        let finder = FinderBuilder::new("Cargo.toml")
+            .root("/src")
+            .file_system(file_system)
+            .archives(true)
+            .build()
+            .unwrap();
+        let found: Vec<_> = finder.run().map(|found| found.path).collect();
+        assert_eq!(found, [PathBuf::from("/src/old.tar!/project")]);
+    }
+}
@@ -0,0 +1,83 @@
+use std::pin::Pin;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::task::Context;
+use std::task::Poll;
+use std::thread;
+
+use futures_core::Stream;
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::UnboundedReceiver;
+
+use crate::finder::Finder;
+use crate::finder::Match;
+use crate::finder::Skipped;
+use crate::finder::StatsHandle;
+
+impl Finder {
+    /// Like [`Finder::run`], but returns a [`Stream`] of matches for use
+    /// from async code. The search still runs on the finder's own
+    /// thread pool, so it never blocks the caller's runtime, and it
+    /// works with any executor.
+    pub fn run_async(&self) -> AsyncMatches {
+        let matches = self.run();
+        let cancelled = matches.cancelled.clone();
+        let skipped = matches.skipped.clone();
+        let stats = matches.stats_handle();
+
+        // A thread to wait on the search's channel, so that polling
+        // the stream never has to.
+        let (sender, receiver) = mpsc::unbounded_channel();
+        thread::spawn(move || {
+            for found in matches {
+                if sender.send(found).is_err() {
+                    break;
+                }
+            }
+        });
+
+        AsyncMatches {
+            receiver,
+            cancelled,
+            skipped,
+            stats,
+        }
+    }
+}
+
+/// Stream of the matches of a running search, returned by
+/// [`Finder::run_async`]. Dropping it cancels the rest of the search.
+pub struct AsyncMatches {
+    receiver: UnboundedReceiver<Match>,
+    cancelled: Arc<AtomicBool>,
+    skipped: Arc<Mutex<Vec<Skipped>>>,
+    stats: StatsHandle,
+}
+
+impl AsyncMatches {
+    /// See [`Matches::take_skipped`](crate::Matches::take_skipped).
+    pub fn take_skipped(&self) -> Vec<Skipped> {
+        std::mem::take(&mut self.skipped.lock().unwrap())
+    }
+
+    /// See [`Matches::stats_handle`](crate::Matches::stats_handle).
+    pub fn stats_handle(&self) -> StatsHandle {
+        self.stats.clone()
+    }
+}
+
+impl Stream for AsyncMatches {
+    type Item = Match;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Match>> {
+        self.get_mut().receiver.poll_recv(cx)
+    }
+}
+
+impl Drop for AsyncMatches {
+    fn drop(&mut self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+}
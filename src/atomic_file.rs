@@ -0,0 +1,65 @@
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::BufWriter;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+
+use anyhow::Context;
+
+/// A file which is written to a temporary path next to its real one,
+/// and only moved into place by [`AtomicFile::commit`], so readers
+/// never see it half written. If it's dropped without being committed
+/// the temporary file is removed and the real one is left alone.
+pub struct AtomicFile {
+    path: PathBuf,
+    tmp_path: PathBuf,
+    file: Option<BufWriter<File>>,
+    committed: bool,
+}
+
+impl AtomicFile {
+    pub fn create(path: &Path) -> anyhow::Result<Self> {
+        let tmp_path = path.with_extension(format!("tmp.{}", std::process::id()));
+        let file =
+            File::create(&tmp_path).with_context(|| format!("Failed to create {:?}", tmp_path))?;
+        Ok(AtomicFile {
+            path: path.to_path_buf(),
+            tmp_path,
+            file: Some(BufWriter::new(file)),
+            committed: false,
+        })
+    }
+
+    pub fn commit(mut self) -> anyhow::Result<()> {
+        if let Some(file) = self.file.take() {
+            let file = file.into_inner().map_err(|e| e.into_error())?;
+            file.sync_all()?;
+        }
+        fs::rename(&self.tmp_path, &self.path)
+            .with_context(|| format!("Failed to write {:?}", self.path))?;
+        self.committed = true;
+        Ok(())
+    }
+}
+
+impl Write for AtomicFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file.as_mut().expect("not committed").write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.as_mut().expect("not committed").flush()
+    }
+}
+
+impl Drop for AtomicFile {
+    fn drop(&mut self) {
+        // Close the file first, since Windows can't remove open files.
+        self.file = None;
+        if !self.committed {
+            let _ = fs::remove_file(&self.tmp_path);
+        }
+    }
+}
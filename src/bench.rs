@@ -0,0 +1,100 @@
+//! Synthetic directory trees for benchmarking, so that runs can be
+//! compared on the same shape of tree rather than whatever happens to
+//! be in someone's home directory.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// The shape of a generated tree.
+#[derive(Clone, Debug)]
+pub struct TreeSpec {
+    /// Subdirectories in each directory above the bottom level.
+    pub breadth: usize,
+    /// Levels of directories below the root.
+    pub depth: usize,
+    /// Plain files in each directory, which pj has to look at but
+    /// never match.
+    pub files: usize,
+    /// The chance, from 0 to 1, that a directory contains a sentinel.
+    pub sentinel_density: f64,
+    pub sentinel_name: String,
+    /// Trees generated with the same spec and seed are identical.
+    pub seed: u64,
+}
+
+impl Default for TreeSpec {
+    fn default() -> Self {
+        TreeSpec {
+            breadth: 8,
+            depth: 4,
+            files: 4,
+            sentinel_density: 0.1,
+            sentinel_name: ".git".to_string(),
+            seed: 0,
+        }
+    }
+}
+
+/// What [`generate`] created.
+#[derive(Clone, Debug, Default)]
+pub struct TreeStats {
+    pub dirs: usize,
+    pub sentinels: usize,
+}
+
+/// Builds the tree described by `spec` beneath `root`, which must not
+/// exist yet.
+pub fn generate(root: &Path, spec: &TreeSpec) -> io::Result<TreeStats> {
+    if root.exists() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} already exists", root.display()),
+        ));
+    }
+    let mut rng = Rng(spec.seed);
+    let mut stats = TreeStats::default();
+    generate_dir(root, spec, 0, &mut rng, &mut stats)?;
+    Ok(stats)
+}
+
+fn generate_dir(
+    dir: &Path,
+    spec: &TreeSpec,
+    depth: usize,
+    rng: &mut Rng,
+    stats: &mut TreeStats,
+) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    stats.dirs += 1;
+    for i in 0..spec.files {
+        fs::write(dir.join(format!("file{}.txt", i)), "")?;
+    }
+    // The root is never a project, so that the whole tree is searched.
+    if depth > 0 && rng.next_f64() < spec.sentinel_density {
+        fs::write(dir.join(&spec.sentinel_name), "")?;
+        stats.sentinels += 1;
+    }
+    if depth < spec.depth {
+        for i in 0..spec.breadth {
+            generate_dir(&dir.join(format!("dir{}", i)), spec, depth + 1, rng, stats)?;
+        }
+    }
+    Ok(())
+}
+
+/// A small xorshift generator. We don't need good randomness, just the
+/// same sequence for the same seed everywhere.
+struct Rng(u64);
+
+impl Rng {
+    fn next_f64(&mut self) -> f64 {
+        // xorshift gets stuck at zero, so mix in a constant.
+        let mut x = self.0 ^ 0x9e37_79b9_7f4a_7c15;
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.0 = x;
+        (x >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
@@ -0,0 +1,77 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+
+use anyhow::anyhow;
+use anyhow::Context as _;
+use pj::atomic_file::AtomicFile;
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::config;
+
+/// Named shortcuts to projects, kept in
+/// `~/.local/share/pj/bookmarks.json`. The picker offers them alongside
+/// whatever the search finds, so projects outside the usual roots are
+/// still a few keystrokes away.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Bookmarks {
+    bookmarks: BTreeMap<String, PathBuf>,
+}
+
+impl Bookmarks {
+    /// Loads the bookmarks, returning none if the file doesn't exist.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let contents = match fs::read(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Bookmarks::default()),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {:?}", path)),
+        };
+        serde_json::from_slice(&contents).with_context(|| format!("Failed to parse {:?}", path))
+    }
+
+    /// Writes the bookmarks to `path`, replacing it atomically.
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).with_context(|| format!("Failed to create {:?}", parent))?;
+        }
+        let mut file = AtomicFile::create(path)?;
+        file.write_all(&serde_json::to_vec_pretty(self)?)?;
+        file.commit()
+    }
+
+    /// Bookmarks `dir` as `name`, replacing any bookmark already called
+    /// that.
+    pub fn add(&mut self, name: String, dir: &Path) -> anyhow::Result<()> {
+        if name.is_empty() {
+            return Err(anyhow!("Bookmark name can't be empty"));
+        }
+        if !dir.is_dir() {
+            return Err(anyhow!("{:?} isn't a directory", dir));
+        }
+        self.bookmarks.insert(name, std::path::absolute(dir)?);
+        Ok(())
+    }
+
+    /// Removes the bookmark called `name`.
+    pub fn remove(&mut self, name: &str) -> anyhow::Result<()> {
+        match self.bookmarks.remove(name) {
+            Some(_) => Ok(()),
+            None => Err(anyhow!("No bookmark called {:?}", name)),
+        }
+    }
+
+    /// Each bookmark's name and directory, sorted by name.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Path)> {
+        (self.bookmarks.iter()).map(|(name, dir)| (name.as_str(), dir.as_path()))
+    }
+
+    /// The default location of the bookmarks, under the user's data
+    /// directory.
+    pub fn default_path() -> Option<PathBuf> {
+        Some(config::data_dir()?.join("pj").join("bookmarks.json"))
+    }
+}
@@ -0,0 +1,211 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use anyhow::anyhow;
+use anyhow::Context as _;
+use serde::de::Error as _;
+use serde::Deserialize;
+use serde::Deserializer;
+
+use crate::output::Format;
+use crate::preset::Preset;
+
+/// Defaults loaded from `~/.config/pj/config.toml`. Every field is
+/// optional, and anything passed on the command line takes precedence.
+///
+/// ```toml
+/// sentinel = '\.git'
+/// preset = ["rust", "node"]
+/// roots = ["~/src", "~/work", "/opt:1"]
+/// ignore = ["node_modules", "venv"]
+/// threads = 8
+/// format = "long"
+/// ```
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub sentinel: Option<String>,
+    /// One preset's name, or a list of them.
+    #[serde(deserialize_with = "presets")]
+    pub preset: Vec<&'static Preset>,
+    pub roots: Vec<RootSpec>,
+    pub ignore: Vec<String>,
+    pub depth: Option<usize>,
+    pub threads: Option<usize>,
+    pub max_threads: Option<usize>,
+    #[serde(deserialize_with = "parsed")]
+    pub format: Option<Format>,
+}
+
+impl Config {
+    /// Loads the config file, returning the default config if it
+    /// doesn't exist.
+    pub fn load() -> anyhow::Result<Self> {
+        let Some(path) = config_path() else {
+            return Ok(Config::default());
+        };
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Config::default()),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {:?}", path)),
+        };
+        let mut config: Config =
+            toml::from_str(&contents).with_context(|| format!("Failed to parse {:?}", path))?;
+        for root in config.roots.iter_mut() {
+            root.path = expand_tilde(&root.path);
+        }
+        Ok(config)
+    }
+}
+
+/// Deserializes one preset name or a list of them.
+fn presets<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<&'static Preset>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Names {
+        One(String),
+        Many(Vec<String>),
+    }
+    let names = match Names::deserialize(deserializer)? {
+        Names::One(name) => vec![name],
+        Names::Many(names) => names,
+    };
+    (names.iter())
+        .map(|name| {
+            name.parse()
+                .map_err(|e| D::Error::custom(format!("{:#}", e)))
+        })
+        .collect()
+}
+
+/// Deserializes a string the same way the command line parses it.
+fn parsed<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr<Err = anyhow::Error>,
+{
+    let s = String::deserialize(deserializer)?;
+    s.parse()
+        .map(Some)
+        .map_err(|e| D::Error::custom(format!("{:#}", e)))
+}
+
+/// A root directory, optionally with its own depth limit written after
+/// a colon, e.g. `~/src:4`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(try_from = "String")]
+pub struct RootSpec {
+    pub path: PathBuf,
+    pub max_depth: Option<usize>,
+}
+
+impl FromStr for RootSpec {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        if s.is_empty() {
+            return Err(anyhow!("Root directory can't be empty"));
+        }
+        // Anything after the last colon which isn't a number is part of
+        // the path, e.g. `C:\src`.
+        let (path, max_depth) = match s.rsplit_once(':') {
+            Some((path, depth)) if !path.is_empty() => match depth.parse() {
+                Ok(depth) => (path, Some(depth)),
+                Err(_) => (s, None),
+            },
+            _ => (s, None),
+        };
+        Ok(RootSpec {
+            path: PathBuf::from(path),
+            max_depth,
+        })
+    }
+}
+
+impl TryFrom<String> for RootSpec {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> anyhow::Result<Self> {
+        s.parse()
+    }
+}
+
+/// Reads root directories from `PJ_DEFAULT_ROOTS`, which is a list
+/// separated like `$PATH` (e.g. `~/src:~/work`).
+pub fn env_roots() -> Vec<PathBuf> {
+    let Some(roots) = env::var_os("PJ_DEFAULT_ROOTS") else {
+        return Vec::new();
+    };
+    env::split_paths(&roots)
+        .filter(|root| !root.as_os_str().is_empty())
+        .map(|root| expand_tilde(&root))
+        .collect()
+}
+
+fn config_path() -> Option<PathBuf> {
+    let config_dir = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(config_dir.join("pj").join("config.toml"))
+}
+
+/// Where pj keeps what it remembers between runs, like visits and
+/// bookmarks: `$XDG_DATA_HOME`, or `~/.local/share`.
+pub fn data_dir() -> Option<PathBuf> {
+    match env::var_os("XDG_DATA_HOME") {
+        Some(dir) if !dir.is_empty() => Some(PathBuf::from(dir)),
+        _ => Some(
+            PathBuf::from(env::var_os("HOME")?)
+                .join(".local")
+                .join("share"),
+        ),
+    }
+}
+
+/// Replaces a leading `~` with the user's home directory, since paths
+/// in the config file don't go through the shell.
+pub fn expand_tilde(path: &Path) -> PathBuf {
+    let Ok(rest) = path.strip_prefix("~") else {
+        return path.to_path_buf();
+    };
+    match env::var_os("HOME") {
+        Some(home) => PathBuf::from(home).join(rest),
+        None => path.to_path_buf(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str) -> (PathBuf, Option<usize>) {
+        let root: RootSpec = s.parse().unwrap();
+        (root.path, root.max_depth)
+    }
+
+    #[test]
+    fn root_spec_reads_depth_after_colon() {
+        assert_eq!(parse("~/src:4"), (PathBuf::from("~/src"), Some(4)));
+        assert_eq!(parse("/a:b:0"), (PathBuf::from("/a:b"), Some(0)));
+    }
+
+    #[test]
+    fn root_spec_keeps_other_colons_in_path() {
+        assert_eq!(parse("~/src"), (PathBuf::from("~/src"), None));
+        assert_eq!(parse(r"C:\src"), (PathBuf::from(r"C:\src"), None));
+        assert_eq!(parse("/a:b"), (PathBuf::from("/a:b"), None));
+        assert_eq!(parse("/a:-1"), (PathBuf::from("/a:-1"), None));
+        assert_eq!(parse(":3"), (PathBuf::from(":3"), None));
+        assert_eq!(parse("/src:"), (PathBuf::from("/src:"), None));
+    }
+
+    #[test]
+    fn root_spec_rejects_empty() {
+        assert!("".parse::<RootSpec>().is_err());
+    }
+}
@@ -0,0 +1,194 @@
+//! Serves index queries over a Unix domain socket, so that callers
+//! which run on every keystroke don't pay for loading the index.
+//!
+//! The protocol is a single JSON [`IndexQuery`] from the client,
+//! terminated by shutting down the write half of the socket, followed
+//! by a single JSON [`Response`] from the daemon.
+
+use std::env;
+use std::fs;
+use std::io;
+use std::io::Read;
+use std::io::Write;
+use std::os::unix::net::UnixListener;
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+use anyhow::anyhow;
+use anyhow::Context as _;
+use crossbeam::channel;
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::index::Index;
+use crate::index::IndexCache;
+use crate::index::IndexQuery;
+
+#[derive(Debug, Deserialize, Serialize)]
+enum Response {
+    Projects(Vec<PathBuf>),
+    Error(String),
+}
+
+/// How long a client has to send its whole request, and to take the
+/// response, so that one which stalls doesn't hold a thread forever.
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long a client waits for the daemon to answer before giving up
+/// on it.
+const QUERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Requests longer than this are refused, so that a misbehaving client
+/// can't use up our memory.
+const MAX_REQUEST_LEN: u64 = 64 * 1024;
+
+/// How many connections are handled at once. Any more wait to be
+/// accepted until one finishes.
+const WORKERS: usize = 8;
+
+/// The default location of the daemon's socket.
+pub fn default_socket_path() -> Option<PathBuf> {
+    let dir = match env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir).join("pj"),
+        _ => Index::default_path()?.parent()?.to_path_buf(),
+    };
+    Some(dir.join("daemon.sock"))
+}
+
+/// Asks a running daemon to answer `query`. Returns None if there's no
+/// daemon listening on `socket_path`, in which case the caller should
+/// read the index itself.
+pub fn query(socket_path: &Path, query: &IndexQuery) -> anyhow::Result<Option<Vec<PathBuf>>> {
+    let mut stream = match UnixStream::connect(socket_path) {
+        Ok(stream) => stream,
+        Err(e)
+            if matches!(
+                e.kind(),
+                io::ErrorKind::NotFound | io::ErrorKind::ConnectionRefused
+            ) =>
+        {
+            return Ok(None)
+        }
+        Err(e) => return Err(e).with_context(|| format!("Failed to connect to {:?}", socket_path)),
+    };
+    stream.set_read_timeout(Some(QUERY_TIMEOUT))?;
+    stream.set_write_timeout(Some(QUERY_TIMEOUT))?;
+    serde_json::to_writer(&mut stream, query)?;
+    stream.shutdown(std::net::Shutdown::Write)?;
+    let response = serde_json::from_reader(stream).map_err(|e| match e.io_error_kind() {
+        Some(io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {
+            anyhow!("The daemon didn't answer within {:?}", QUERY_TIMEOUT)
+        }
+        _ => e.into(),
+    })?;
+    match response {
+        Response::Projects(projects) => Ok(Some(projects)),
+        Response::Error(e) => Err(anyhow!(e)),
+    }
+}
+
+/// Listens on `socket_path` forever, answering queries from the index
+/// at `index_path`. The index is reloaded whenever it changes on disk.
+pub fn serve(socket_path: &Path, index_path: &Path) -> anyhow::Result<()> {
+    if let Some(parent) = socket_path.parent() {
+        fs::create_dir_all(parent).with_context(|| format!("Failed to create {:?}", parent))?;
+    }
+    if UnixStream::connect(socket_path).is_ok() {
+        return Err(anyhow!(
+            "A daemon is already listening on {:?}",
+            socket_path
+        ));
+    }
+    // Nothing is listening, so this is left over from a daemon which
+    // didn't shut down cleanly.
+    let _ = fs::remove_file(socket_path);
+    let listener = UnixListener::bind(socket_path)
+        .with_context(|| format!("Failed to listen on {:?}", socket_path))?;
+
+    let cache = Arc::new(IndexCache::new(index_path));
+    cache.get()?;
+
+    // Nothing is queued, so each connection is only accepted once a
+    // worker is ready for it.
+    let (sender, receiver) = channel::bounded::<UnixStream>(0);
+    for _ in 0..WORKERS {
+        let receiver = receiver.clone();
+        let cache = cache.clone();
+        thread::spawn(move || {
+            for stream in receiver {
+                if let Err(e) = handle_client(stream, &cache) {
+                    tracing::warn!("Failed to answer a request: {:#}", e);
+                }
+            }
+        });
+    }
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => sender.send(stream)?,
+            Err(e) => tracing::warn!("Failed to accept a connection: {}", e),
+        }
+    }
+    Ok(())
+}
+
+fn handle_client(mut stream: UnixStream, cache: &IndexCache) -> anyhow::Result<()> {
+    let request = read_request(&mut stream)?;
+    if request.is_empty() {
+        // Another daemon checking whether we're alive.
+        return Ok(());
+    }
+    let response = match answer(&request, cache) {
+        Ok(projects) => Response::Projects(projects),
+        Err(e) => Response::Error(format!("{:#}", e)),
+    };
+    stream.set_write_timeout(Some(CLIENT_TIMEOUT))?;
+    serde_json::to_writer(&mut stream, &response)?;
+    stream.flush()?;
+    Ok(())
+}
+
+/// Reads until the client shuts down its write half, giving up after
+/// [`CLIENT_TIMEOUT`] in total rather than per read, so a client can't
+/// keep us waiting by trickling bytes.
+fn read_request(stream: &mut UnixStream) -> anyhow::Result<Vec<u8>> {
+    let deadline = Instant::now() + CLIENT_TIMEOUT;
+    let mut request = Vec::new();
+    let mut buffer = [0; 4096];
+    loop {
+        if request.len() as u64 > MAX_REQUEST_LEN {
+            return Err(anyhow!("Request too long"));
+        }
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let result = if remaining.is_zero() {
+            Err(io::ErrorKind::TimedOut.into())
+        } else {
+            stream.set_read_timeout(Some(remaining))?;
+            stream.read(&mut buffer)
+        };
+        match result {
+            Ok(0) => return Ok(request),
+            Ok(n) => request.extend_from_slice(&buffer[..n]),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                return Err(anyhow!("Timed out waiting for the request"));
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+fn answer(request: &[u8], cache: &IndexCache) -> anyhow::Result<Vec<PathBuf>> {
+    let query: IndexQuery = serde_json::from_slice(request)?;
+    let index = cache.get()?;
+    Ok(index.query(&query)?.into_iter().cloned().collect())
+}
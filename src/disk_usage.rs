@@ -0,0 +1,47 @@
+//! Adding up the size of everything beneath a directory, for
+//! [`Finder::disk_usage`](crate::Finder::disk_usage).
+
+use std::io;
+use std::path::Path;
+
+use rayon::iter::IntoParallelIterator;
+use rayon::iter::ParallelIterator;
+
+use crate::file_system::FileSystem;
+use crate::file_system::FileType;
+
+/// Adds up the sizes of the files beneath `dir`, reading
+/// subdirectories in parallel on the current rayon pool. With
+/// `skip_archives`, the directories standing in for archives are left
+/// out, since the archives themselves are already counted.
+pub(crate) fn disk_usage(fs: &dyn FileSystem, dir: &Path, skip_archives: bool) -> io::Result<u64> {
+    let entries: Vec<_> = fs.read_dir(dir)?.filter_map(Result::ok).collect();
+    let size = entries
+        .into_par_iter()
+        .map(|entry| {
+            let path = dir.join(&entry.file_name);
+            match entry.file_type {
+                FileType::Dir if skip_archives && is_archive_dir(&entry.file_name) => 0,
+                FileType::Dir => disk_usage(fs, &path, skip_archives).unwrap_or_else(|e| {
+                    tracing::warn!(path = %path.display(), "Not counted: {}", e);
+                    0
+                }),
+                FileType::Symlink => 0,
+                FileType::File => (fs.metadata(&path).ok())
+                    .and_then(|metadata| metadata.size)
+                    .unwrap_or(0),
+            }
+        })
+        .sum();
+    Ok(size)
+}
+
+#[cfg(feature = "archives")]
+fn is_archive_dir(file_name: &std::ffi::OsStr) -> bool {
+    crate::archive::is_archive_dir(file_name)
+}
+
+#[cfg(not(feature = "archives"))]
+fn is_archive_dir(_file_name: &std::ffi::OsStr) -> bool {
+    false
+}
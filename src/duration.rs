@@ -0,0 +1,48 @@
+use std::time::Duration;
+
+use anyhow::anyhow;
+
+/// Parses a human-friendly duration such as `2weeks`, `3d`, `1h30m` or
+/// `500ms`.
+pub fn parse(s: &str) -> anyhow::Result<Duration> {
+    let invalid = || anyhow!("Invalid duration {:?}, expected e.g. 2weeks or 1h30m", s);
+
+    let mut total = Duration::ZERO;
+    let mut rest = s.trim();
+    if rest.is_empty() {
+        return Err(invalid());
+    }
+    while !rest.is_empty() {
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let count: u64 = rest[..digits].parse().map_err(|_| invalid())?;
+        rest = rest[digits..].trim_start();
+
+        let letters = rest
+            .find(|c: char| !c.is_ascii_alphabetic())
+            .unwrap_or(rest.len());
+        let millis = unit_millis(&rest[..letters]).ok_or_else(invalid)?;
+        rest = rest[letters..].trim_start();
+
+        let millis = count.checked_mul(millis).ok_or_else(invalid)?;
+        total = total
+            .checked_add(Duration::from_millis(millis))
+            .ok_or_else(invalid)?;
+    }
+    Ok(total)
+}
+
+fn unit_millis(unit: &str) -> Option<u64> {
+    const SECOND: u64 = 1000;
+    Some(match unit {
+        "ms" | "msec" | "msecs" | "millisecond" | "milliseconds" => 1,
+        "s" | "sec" | "secs" | "second" | "seconds" => SECOND,
+        "m" | "min" | "mins" | "minute" | "minutes" => 60 * SECOND,
+        "h" | "hr" | "hrs" | "hour" | "hours" => 60 * 60 * SECOND,
+        "d" | "day" | "days" => 24 * 60 * 60 * SECOND,
+        "w" | "week" | "weeks" => 7 * 24 * 60 * 60 * SECOND,
+        "y" | "year" | "years" => 365 * 24 * 60 * 60 * SECOND,
+        _ => return None,
+    })
+}
@@ -0,0 +1,213 @@
+//! `pj exec`, which runs a command in each matched directory.
+
+use std::ffi::OsString;
+use std::io;
+use std::io::Write;
+use std::path;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+use std::process::Output;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+use pj::long_path::to_display;
+use pj::Finder;
+
+use crate::log;
+use crate::log::LogFormat;
+
+/// A command which didn't succeed, and why.
+struct Failure {
+    dir: PathBuf,
+    reason: String,
+}
+
+/// Runs `args` in each directory the search finds, as soon as it's
+/// found, one at a time. Returns whether every command succeeded, once
+/// the failures have been summarized.
+pub fn run(finder: &Finder, args: &[String], log_format: LogFormat) -> io::Result<bool> {
+    let mut ran = 0;
+    let mut failures = Vec::new();
+    for found in finder.run() {
+        let dir = to_display(&found.path);
+        println!("==> {} <==", dir.display());
+        io::stdout().flush()?;
+        ran += 1;
+        let reason = match command(&found.path, args).status() {
+            Ok(status) if status.success() => continue,
+            Ok(status) => status.to_string(),
+            Err(e) => format!("failed to start: {}", e),
+        };
+        failures.push(Failure {
+            dir: dir.into_owned(),
+            reason,
+        });
+    }
+    summarize(ran, &failures, log_format);
+    Ok(failures.is_empty())
+}
+
+/// How a command went, for the table printed after `--jobs`.
+struct Outcome {
+    dir: PathBuf,
+    duration: Duration,
+    /// Why it failed, if it did.
+    failure: Option<String>,
+}
+
+/// Runs `args` in each directory the search finds, up to `jobs` at a
+/// time. Each command's output is captured, and printed all at once
+/// when it finishes so that the output of different directories isn't
+/// mixed up. Ends with a table of which commands passed and failed.
+/// Returns whether every command succeeded.
+pub fn run_parallel(
+    finder: &Finder,
+    args: &[String],
+    jobs: usize,
+    log_format: LogFormat,
+) -> io::Result<bool> {
+    let matches = Mutex::new(finder.run());
+    let outcomes = Mutex::new(Vec::new());
+    // Held while a command's output is printed.
+    let print = Mutex::new(());
+    thread::scope(|scope| {
+        let workers: Vec<_> = (0..jobs)
+            .map(|_| {
+                scope.spawn(|| loop {
+                    let Some(found) = matches.lock().unwrap().next() else {
+                        return io::Result::Ok(());
+                    };
+                    let dir = to_display(&found.path).into_owned();
+                    let start = Instant::now();
+                    let output = command(&found.path, args).output();
+                    let duration = start.elapsed();
+                    let failure = match &output {
+                        Ok(output) if output.status.success() => None,
+                        Ok(output) => Some(output.status.to_string()),
+                        Err(e) => Some(format!("failed to start: {}", e)),
+                    };
+                    {
+                        let _print = print.lock().unwrap();
+                        print_output(&dir, output.as_ref().ok())?;
+                    }
+                    outcomes.lock().unwrap().push(Outcome {
+                        dir,
+                        duration,
+                        failure,
+                    });
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .try_for_each(|worker| worker.join().unwrap())
+    })?;
+    let mut outcomes = outcomes.into_inner().unwrap();
+    outcomes.sort_by(|a, b| a.dir.cmp(&b.dir));
+    print_table(&outcomes, log_format);
+    Ok(outcomes.iter().all(|outcome| outcome.failure.is_none()))
+}
+
+/// Prints a command's captured output under a header for its
+/// directory. Its stdout goes to stdout and its stderr to stderr.
+fn print_output(dir: &Path, output: Option<&Output>) -> io::Result<()> {
+    let mut stdout = io::stdout().lock();
+    writeln!(stdout, "==> {} <==", dir.display())?;
+    let Some(output) = output else {
+        return stdout.flush();
+    };
+    stdout.write_all(&output.stdout)?;
+    stdout.flush()?;
+    let mut stderr = io::stderr().lock();
+    stderr.write_all(&output.stderr)?;
+    stderr.flush()
+}
+
+fn print_table(outcomes: &[Outcome], log_format: LogFormat) {
+    let failed = (outcomes.iter())
+        .filter(|outcome| outcome.failure.is_some())
+        .count();
+    if log_format == LogFormat::Json {
+        for outcome in outcomes {
+            let dir = outcome.dir.display();
+            let duration_secs = outcome.duration.as_secs_f64();
+            match &outcome.failure {
+                None => tracing::info!(target: log::SUMMARY, %dir, duration_secs, "Command passed"),
+                Some(reason) => {
+                    tracing::error!(target: log::SUMMARY, %dir, duration_secs, %reason, "Command failed")
+                }
+            }
+        }
+        return;
+    }
+    eprintln!();
+    for outcome in outcomes {
+        let status = if outcome.failure.is_some() {
+            "FAIL"
+        } else {
+            "PASS"
+        };
+        eprint!(
+            "{}  {:>8.2?}  {}",
+            status,
+            outcome.duration,
+            outcome.dir.display()
+        );
+        match &outcome.failure {
+            Some(reason) => eprintln!(" ({})", reason),
+            None => eprintln!(),
+        }
+    }
+    eprintln!("{} passed, {} failed", outcomes.len() - failed, failed);
+}
+
+/// Builds `args` as a command to run in `dir`. The directory is
+/// substituted for `{}` in any argument, and is in `$PJ_DIR`. Both are
+/// absolute, since a path relative to where pj was run from would be
+/// wrong from inside `dir`.
+fn command(dir: &Path, args: &[String]) -> Command {
+    let absolute = path::absolute(dir).unwrap_or_else(|_| dir.to_path_buf());
+    let display = to_display(&absolute);
+    let mut args = args.iter().map(|arg| substitute(arg, &display));
+    // Clap makes sure there's at least one argument.
+    let mut command = Command::new(args.next().unwrap());
+    command.args(args).current_dir(dir).env("PJ_DIR", &*display);
+    command
+}
+
+fn substitute(arg: &str, dir: &Path) -> OsString {
+    let mut parts = arg.split("{}");
+    let mut substituted = OsString::from(parts.next().unwrap_or_default());
+    for part in parts {
+        substituted.push(dir);
+        substituted.push(part);
+    }
+    substituted
+}
+
+fn summarize(ran: usize, failures: &[Failure], log_format: LogFormat) {
+    if failures.is_empty() {
+        return;
+    }
+    match log_format {
+        LogFormat::Text => {
+            eprintln!("{} of {} commands failed:", failures.len(), ran);
+            for failure in failures {
+                eprintln!("  {}: {}", failure.dir.display(), failure.reason);
+            }
+        }
+        LogFormat::Json => {
+            for failure in failures {
+                tracing::error!(
+                    target: log::SUMMARY,
+                    dir = %failure.dir.display(),
+                    reason = %failure.reason,
+                    "Command failed"
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,99 @@
+use std::fmt;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use crate::git::GitStatus;
+
+/// What the search decided about a directory, and why, passed to the
+/// callback from [`FinderBuilder::explain`](crate::FinderBuilder::explain).
+#[derive(Clone, Debug)]
+pub enum Decision {
+    /// It will be searched.
+    Descended,
+    /// It was already searched through another path, e.g. a symlink.
+    AlreadyVisited,
+    /// It matched because of this sentinel.
+    Matched { sentinel: PathBuf },
+    /// It matched, but the same directory was already reported through
+    /// another path, e.g. a bind mount.
+    AlreadyReported,
+    /// It has a sentinel, but it's above the minimum depth.
+    TooShallow,
+    /// It has a sentinel, but it's a git submodule which is skipped.
+    Submodule,
+    /// It has a sentinel, but it isn't a git repository in the state
+    /// asked for.
+    OtherGitStatus { wanted: GitStatus },
+    /// Nothing beneath it is searched, because it matched.
+    Pruned,
+    /// It contains an entry matching this exclusion pattern, so it's
+    /// neither reported nor searched.
+    Excluded { pattern: String },
+    /// Its name matches this prune pattern, so it isn't searched.
+    PrunedByPattern { pattern: String },
+    /// Its name is in the ignore list.
+    Ignored,
+    /// A `.gitignore`, `.ignore` or `.fdignore` file covers it.
+    IgnoredByFile,
+    /// It's hidden.
+    Hidden,
+    /// It's deeper than the maximum depth.
+    TooDeep,
+    /// It's on a different file system from its root.
+    OtherFileSystem,
+    /// It's a network mount of this type, like `nfs`.
+    NetworkFileSystem { fs_type: String },
+    /// It's a symlink, and symlinks aren't followed.
+    Symlink,
+}
+
+impl fmt::Display for Decision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Decision::Descended => write!(f, "searching"),
+            Decision::AlreadyVisited => write!(f, "skipped, already searched"),
+            Decision::Matched { sentinel } => write!(f, "matched {}", sentinel.display()),
+            Decision::AlreadyReported => write!(f, "not reported, already reported elsewhere"),
+            Decision::TooShallow => write!(f, "not reported, above the minimum depth"),
+            Decision::Submodule => write!(f, "not reported, a git submodule"),
+            Decision::OtherGitStatus { wanted } => write!(f, "not reported, not {}", wanted),
+            Decision::Pruned => write!(f, "not searching beneath, it matched"),
+            Decision::Excluded { pattern } => {
+                write!(f, "skipped, contains an entry matching {:?}", pattern)
+            }
+            Decision::PrunedByPattern { pattern } => write!(f, "skipped, matches {:?}", pattern),
+            Decision::Ignored => write!(f, "skipped, in the ignore list"),
+            Decision::IgnoredByFile => write!(f, "skipped, covered by an ignore file"),
+            Decision::Hidden => write!(f, "skipped, hidden"),
+            Decision::TooDeep => write!(f, "skipped, below the maximum depth"),
+            Decision::OtherFileSystem => write!(f, "skipped, on another file system"),
+            Decision::NetworkFileSystem { fs_type } => {
+                write!(f, "skipped, a network file system ({})", fs_type)
+            }
+            Decision::Symlink => write!(f, "skipped, a symlink"),
+        }
+    }
+}
+
+type ExplainFn = dyn Fn(&Path, &Decision) + Send + Sync;
+
+/// The callback from [`FinderBuilder::explain`](crate::FinderBuilder::explain).
+#[derive(Clone)]
+pub(crate) struct Explainer(Arc<ExplainFn>);
+
+impl Explainer {
+    pub(crate) fn new(explain: impl Fn(&Path, &Decision) + Send + Sync + 'static) -> Self {
+        Explainer(Arc::new(explain))
+    }
+
+    pub(crate) fn explain(&self, path: &Path, decision: &Decision) {
+        (self.0)(path, decision)
+    }
+}
+
+impl fmt::Debug for Explainer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Explainer").finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,411 @@
+//! The file system a search runs against. Searches use the real one
+//! unless [`FinderBuilder::file_system`](crate::FinderBuilder::file_system)
+//! says otherwise, e.g. [`MemoryFileSystem`] for checking how a search
+//! treats a particular tree without creating it on disk.
+
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fmt::Debug;
+use std::fs;
+use std::io;
+use std::io::Cursor;
+use std::io::Read;
+use std::io::Seek;
+use std::path::Component;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+/// The operations a search needs from a file system. Symlinks are
+/// followed by everything except [`FileSystem::read_dir`], which
+/// reports them as they are.
+pub trait FileSystem: Debug + Send + Sync {
+    /// Lists the entries of the directory at `path`.
+    fn read_dir(&self, path: &Path) -> io::Result<ReadDir<'_>>;
+
+    fn metadata(&self, path: &Path) -> io::Result<Metadata>;
+
+    /// Reads where the symlink at `path` points.
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf>;
+
+    /// Resolves `path` to an absolute path without symlinks.
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf>;
+
+    /// Reads at most `limit` bytes from the start of the file at `path`.
+    /// Anything other than a regular file is an error, since reading a
+    /// FIFO or a device could block forever.
+    fn read(&self, path: &Path, limit: u64) -> io::Result<Vec<u8>>;
+
+    /// Opens the regular file at `path` to read from anywhere in it, as
+    /// archives need. By default it's read into memory whole.
+    fn open(&self, path: &Path) -> io::Result<Box<dyn ReadSeek>> {
+        Ok(Box::new(Cursor::new(self.read(path, u64::MAX)?)))
+    }
+
+    /// How `path` is shown to the user. Most file systems show paths
+    /// as they are.
+    fn display<'a>(&self, path: &'a Path) -> Cow<'a, Path> {
+        Cow::Borrowed(path)
+    }
+}
+
+/// A file opened with [`FileSystem::open`].
+pub trait ReadSeek: Read + Seek {}
+
+impl<T: Read + Seek> ReadSeek for T {}
+
+/// The entries of a directory, from [`FileSystem::read_dir`].
+pub type ReadDir<'a> = Box<dyn Iterator<Item = io::Result<DirEntry>> + 'a>;
+
+#[derive(Clone, Debug)]
+pub struct DirEntry {
+    pub file_name: OsString,
+    /// The type of the entry itself, so a symlink is a symlink whatever
+    /// it points at.
+    pub file_type: FileType,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum FileType {
+    Dir,
+    Symlink,
+    /// Anything else, including devices and sockets.
+    File,
+}
+
+impl FileType {
+    pub fn is_dir(self) -> bool {
+        self == FileType::Dir
+    }
+
+    pub fn is_symlink(self) -> bool {
+        self == FileType::Symlink
+    }
+}
+
+impl From<fs::FileType> for FileType {
+    fn from(file_type: fs::FileType) -> Self {
+        if file_type.is_dir() {
+            FileType::Dir
+        } else if file_type.is_symlink() {
+            FileType::Symlink
+        } else {
+            FileType::File
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Metadata {
+    pub file_type: FileType,
+    pub modified: Option<SystemTime>,
+    /// Identifies the device (file system) the entry is on, where
+    /// that's known.
+    pub device: Option<u64>,
+    /// Identifies the entry within its device, where that's known.
+    pub inode: Option<u64>,
+    /// How many bytes long a file is, where that's known. Directories
+    /// don't have one.
+    pub size: Option<u64>,
+    /// The Unix permission bits, e.g. `0o755`, where they're known.
+    pub permissions: Option<u32>,
+    /// The ID of the user who owns the entry, where it means something
+    /// on this machine.
+    pub uid: Option<u32>,
+}
+
+impl From<fs::Metadata> for Metadata {
+    #[cfg(unix)]
+    fn from(metadata: fs::Metadata) -> Self {
+        use std::os::unix::fs::MetadataExt;
+
+        Metadata {
+            file_type: metadata.file_type().into(),
+            modified: metadata.modified().ok(),
+            device: Some(metadata.dev()),
+            inode: Some(metadata.ino()),
+            size: (!metadata.is_dir()).then_some(metadata.len()),
+            permissions: Some(metadata.mode() & 0o7777),
+            uid: Some(metadata.uid()),
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn from(metadata: fs::Metadata) -> Self {
+        Metadata {
+            file_type: metadata.file_type().into(),
+            modified: metadata.modified().ok(),
+            device: None,
+            inode: None,
+            size: (!metadata.is_dir()).then_some(metadata.len()),
+            permissions: None,
+            uid: None,
+        }
+    }
+}
+
+/// The file system the operating system provides, through `std::fs`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RealFileSystem;
+
+impl FileSystem for RealFileSystem {
+    fn read_dir(&self, path: &Path) -> io::Result<ReadDir<'_>> {
+        Ok(Box::new(fs::read_dir(path)?.map(|dir_entry| {
+            let dir_entry = dir_entry?;
+            Ok(DirEntry {
+                // Usually free, since most file systems report each
+                // entry's type as the directory is read. Otherwise
+                // it's a stat.
+                file_type: dir_entry.file_type()?.into(),
+                file_name: dir_entry.file_name(),
+            })
+        })))
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        Ok(fs::metadata(path)?.into())
+    }
+
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf> {
+        fs::read_link(path)
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        fs::canonicalize(path)
+    }
+
+    fn read(&self, path: &Path, limit: u64) -> io::Result<Vec<u8>> {
+        // Opening a FIFO blocks until something writes to it, so it's
+        // too late to check once it's open.
+        if !fs::metadata(path)?.is_file() {
+            return Err(io::Error::other("Not a regular file"));
+        }
+        let mut contents = Vec::new();
+        fs::File::open(path)?
+            .take(limit)
+            .read_to_end(&mut contents)?;
+        Ok(contents)
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn ReadSeek>> {
+        if !fs::metadata(path)?.is_file() {
+            return Err(io::Error::other("Not a regular file"));
+        }
+        Ok(Box::new(fs::File::open(path)?))
+    }
+}
+
+/// A file system which only exists in memory. Paths are absolute, and
+/// adding an entry adds any missing directories above it.
+///
+/// ```
+/// use std::path::Path;
+///
+/// use pj::file_system::MemoryFileSystem;
+/// use pj::FinderBuilder;
+///
+/// let mut file_system = MemoryFileSystem::new();
+/// file_system.add_dir("/src/pj/.git");
+/// file_system.add_file("/src/notes.txt", "not a project");
+/// let finder = FinderBuilder::new(r"\.git")
+///     .root("/src")
+///     .file_system(file_system)
+///     .build()?;
+/// let found: Vec<_> = finder.run().map(|found| found.path).collect();
+/// assert_eq!(found, [Path::new("/src/pj")]);
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+#[derive(Clone, Debug)]
+pub struct MemoryFileSystem {
+    entries: BTreeMap<PathBuf, MemoryEntry>,
+    /// Each entry's inode, which is how many entries there were when
+    /// it was added, since nothing is ever removed.
+    inodes: HashMap<PathBuf, u64>,
+}
+
+#[derive(Clone, Debug)]
+enum MemoryEntry {
+    Dir,
+    File(Vec<u8>),
+    Symlink(PathBuf),
+}
+
+/// How many symlinks we follow before deciding there's a loop, like
+/// Linux's `ELOOP`.
+const MAX_SYMLINKS: usize = 40;
+
+impl MemoryFileSystem {
+    /// Creates a file system with nothing but the root directory.
+    pub fn new() -> Self {
+        MemoryFileSystem {
+            entries: BTreeMap::from([(PathBuf::from("/"), MemoryEntry::Dir)]),
+            inodes: HashMap::from([(PathBuf::from("/"), 0)]),
+        }
+    }
+
+    pub fn add_dir(&mut self, path: impl Into<PathBuf>) -> &mut Self {
+        self.add(path.into(), MemoryEntry::Dir)
+    }
+
+    pub fn add_file(
+        &mut self,
+        path: impl Into<PathBuf>,
+        contents: impl Into<Vec<u8>>,
+    ) -> &mut Self {
+        self.add(path.into(), MemoryEntry::File(contents.into()))
+    }
+
+    /// Adds a symlink pointing at `target`, which is relative to the
+    /// directory containing the link unless it's absolute.
+    pub fn add_symlink(
+        &mut self,
+        path: impl Into<PathBuf>,
+        target: impl Into<PathBuf>,
+    ) -> &mut Self {
+        self.add(path.into(), MemoryEntry::Symlink(target.into()))
+    }
+
+    fn add(&mut self, path: PathBuf, entry: MemoryEntry) -> &mut Self {
+        for ancestor in path.ancestors().skip(1) {
+            if !self.entries.contains_key(ancestor) {
+                self.insert(ancestor.to_path_buf(), MemoryEntry::Dir);
+            }
+        }
+        self.insert(path, entry);
+        self
+    }
+
+    /// Adds or replaces the entry at `path`. A replaced entry keeps its
+    /// inode.
+    fn insert(&mut self, path: PathBuf, entry: MemoryEntry) {
+        let inode = self.entries.len() as u64;
+        self.inodes.entry(path.clone()).or_insert(inode);
+        self.entries.insert(path, entry);
+    }
+
+    /// Finds the entry at `path`, following symlinks anywhere in it.
+    /// The path returned is where the entry really is.
+    fn resolve(&self, path: &Path) -> io::Result<(PathBuf, &MemoryEntry)> {
+        let mut resolved = PathBuf::from("/");
+        let mut remaining: Vec<Component> = path.components().rev().collect();
+        let mut followed = 0;
+        while let Some(component) = remaining.pop() {
+            match component {
+                Component::Prefix(_) | Component::RootDir | Component::CurDir => continue,
+                Component::ParentDir => {
+                    resolved.pop();
+                    continue;
+                }
+                Component::Normal(name) => resolved.push(name),
+            }
+            let Some(MemoryEntry::Symlink(target)) = self.entries.get(&resolved) else {
+                continue;
+            };
+            followed += 1;
+            if followed > MAX_SYMLINKS {
+                return Err(io::Error::other("Too many levels of symlinks"));
+            }
+            resolved.pop();
+            remaining.extend(target.components().rev());
+            if target.is_absolute() {
+                resolved = PathBuf::from("/");
+            }
+        }
+        let entry = self.entries.get(&resolved).ok_or_else(not_found)?;
+        Ok((resolved, entry))
+    }
+
+    /// Finds the entry at `path` without following it if it's a symlink.
+    fn entry(&self, path: &Path) -> io::Result<&MemoryEntry> {
+        let parent = match path.parent() {
+            Some(parent) => self.resolve(parent)?.0,
+            None => PathBuf::new(),
+        };
+        let path = match path.file_name() {
+            Some(file_name) => parent.join(file_name),
+            None => PathBuf::from("/"),
+        };
+        self.entries.get(&path).ok_or_else(not_found)
+    }
+}
+
+impl Default for MemoryFileSystem {
+    fn default() -> Self {
+        MemoryFileSystem::new()
+    }
+}
+
+impl FileSystem for MemoryFileSystem {
+    fn read_dir(&self, path: &Path) -> io::Result<ReadDir<'_>> {
+        let (dir, entry) = self.resolve(path)?;
+        if !matches!(entry, MemoryEntry::Dir) {
+            return Err(io::Error::other("Not a directory"));
+        }
+        let children: Vec<_> = (self.entries.range(dir.clone()..))
+            .skip(1)
+            .take_while(|(path, _)| path.starts_with(&dir))
+            .filter(|(path, _)| path.parent() == Some(dir.as_path()))
+            .filter_map(|(path, entry)| {
+                Some(Ok(DirEntry {
+                    file_name: path.file_name()?.to_os_string(),
+                    file_type: entry.file_type(),
+                }))
+            })
+            .collect();
+        Ok(Box::new(children.into_iter()))
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        let (resolved, entry) = self.resolve(path)?;
+        let inode = self.inodes[&resolved];
+        Ok(Metadata {
+            file_type: entry.file_type(),
+            modified: None,
+            device: Some(0),
+            inode: Some(inode),
+            size: match entry {
+                MemoryEntry::File(contents) => Some(contents.len() as u64),
+                _ => None,
+            },
+            permissions: None,
+            uid: None,
+        })
+    }
+
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf> {
+        match self.entry(path)? {
+            MemoryEntry::Symlink(target) => Ok(target.clone()),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidInput, "Not a symlink")),
+        }
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        Ok(self.resolve(path)?.0)
+    }
+
+    fn read(&self, path: &Path, limit: u64) -> io::Result<Vec<u8>> {
+        match self.resolve(path)?.1 {
+            MemoryEntry::File(contents) => {
+                let len = contents.len().min(limit.try_into().unwrap_or(usize::MAX));
+                Ok(contents[..len].to_vec())
+            }
+            _ => Err(io::Error::other("Is a directory")),
+        }
+    }
+}
+
+impl MemoryEntry {
+    fn file_type(&self) -> FileType {
+        match self {
+            MemoryEntry::Dir => FileType::Dir,
+            MemoryEntry::File(_) => FileType::File,
+            MemoryEntry::Symlink(_) => FileType::Symlink,
+        }
+    }
+}
+
+fn not_found() -> io::Error {
+    io::Error::from(io::ErrorKind::NotFound)
+}
@@ -0,0 +1,1995 @@
+use std::cmp::Ordering as CmpOrdering;
+use std::collections::BinaryHeap;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::ffi::OsStr;
+use std::io;
+use std::ops::ControlFlow;
+use std::path::Path;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+use anyhow::anyhow;
+use crossbeam::channel;
+use crossbeam::channel::Receiver;
+use crossbeam::channel::Sender;
+use rayon::ThreadPool;
+use rayon::ThreadPoolBuilder;
+use serde::Deserialize;
+use serde::Serialize;
+use tracing::Level;
+
+use crate::adaptive;
+use crate::adaptive::AdaptiveWorkers;
+#[cfg(feature = "archives")]
+use crate::archive::ArchiveFileSystem;
+use crate::disk_usage::disk_usage;
+use crate::explain::Decision;
+use crate::explain::Explainer;
+use crate::file_system::DirEntry;
+use crate::file_system::FileSystem;
+use crate::file_system::RealFileSystem;
+use crate::git::GitStatus;
+use crate::ignore_file::IgnoreStack;
+use crate::ignore_file::GIT_DIR;
+use crate::ignore_file::IGNORE_FILES;
+use crate::long_path::to_display;
+use crate::long_path::to_search_root;
+use crate::matcher::Matcher;
+use crate::matcher::SentinelKind;
+use crate::network_fs::NetworkMounts;
+use crate::open_files::OpenDirLimit;
+use crate::project_type::ProjectType;
+use crate::stats::Counters;
+use crate::stats::CpuTimer;
+use crate::stats::Stats;
+use crate::stats::WorkerCounters;
+
+/// Configures a [`Finder`].
+///
+/// ```no_run
+/// let finder = pj::FinderBuilder::new(r"\.git")
+///     .root("/home/me/src")
+///     .ignore(["node_modules", "venv"])
+///     .max_depth(Some(4))
+///     .build()?;
+/// for found in finder.run() {
+///     println!("{}", found.path.display());
+/// }
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct FinderBuilder {
+    pub(crate) patterns: Vec<String>,
+    #[serde(default)]
+    pub(crate) require_all: bool,
+    #[serde(default)]
+    pub(crate) excludes: Vec<String>,
+    #[serde(default)]
+    pub(crate) prunes: Vec<String>,
+    #[serde(default)]
+    pub(crate) ignore_case: bool,
+    #[serde(default = "default_normalize_unicode")]
+    pub(crate) normalize_unicode: bool,
+    #[serde(default)]
+    pub(crate) sentinel_kind: SentinelKind,
+    #[serde(default)]
+    pub(crate) types: Vec<ProjectType>,
+    #[serde(default)]
+    pub(crate) contains: Option<String>,
+    #[serde(default)]
+    pub(crate) changed_within: Option<Duration>,
+    roots: Vec<PathBuf>,
+    /// Roots with their own depth limit, overriding `max_depth`.
+    #[serde(default)]
+    root_max_depths: Vec<(PathBuf, usize)>,
+    threads: Option<usize>,
+    /// With adaptive workers, the most threads to run at once.
+    #[serde(default)]
+    max_threads: Option<usize>,
+    options: Options,
+    #[serde(skip)]
+    explainer: Option<Explainer>,
+    #[serde(skip)]
+    file_system: Option<Arc<dyn FileSystem>>,
+    #[cfg(feature = "archives")]
+    #[serde(default)]
+    archives: bool,
+}
+
+impl FinderBuilder {
+    /// Creates a builder which looks for directories containing an
+    /// entry whose name fully matches the regex `pattern`. Anything
+    /// after a `/` in the pattern is a path which has to exist beneath
+    /// that entry, so `.git/HEAD` only matches a `.git` containing a
+    /// `HEAD`.
+    pub fn new(pattern: impl Into<String>) -> Self {
+        FinderBuilder {
+            patterns: vec![pattern.into()],
+            require_all: false,
+            excludes: Vec::new(),
+            prunes: Vec::new(),
+            ignore_case: false,
+            normalize_unicode: default_normalize_unicode(),
+            sentinel_kind: SentinelKind::Any,
+            types: Vec::new(),
+            contains: None,
+            changed_within: None,
+            roots: Vec::new(),
+            root_max_depths: Vec::new(),
+            threads: None,
+            max_threads: None,
+            options: Options::default(),
+            explainer: None,
+            file_system: None,
+            #[cfg(feature = "archives")]
+            archives: false,
+        }
+    }
+
+    /// Adds another sentinel pattern. By default a directory matches if
+    /// it contains a sentinel for any of the patterns.
+    pub fn pattern(mut self, pattern: impl Into<String>) -> Self {
+        self.patterns.push(pattern.into());
+        self
+    }
+
+    /// Only matches directories which contain a sentinel for every
+    /// pattern.
+    pub fn require_all(mut self, require_all: bool) -> Self {
+        self.require_all = require_all;
+        self
+    }
+
+    /// Skips directories containing an entry whose name fully matches
+    /// any of these regexes, along with everything beneath them.
+    pub fn excludes<I>(mut self, patterns: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        self.excludes.extend(patterns.into_iter().map(Into::into));
+        self
+    }
+
+    /// Doesn't descend into directories whose name fully matches any
+    /// of these regexes. Unlike [`FinderBuilder::excludes`], their
+    /// parents can still match, and so can they, if they're sentinels.
+    pub fn prunes<I>(mut self, patterns: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        self.prunes.extend(patterns.into_iter().map(Into::into));
+        self
+    }
+
+    /// Matches the sentinel patterns case-insensitively.
+    pub fn ignore_case(mut self, ignore_case: bool) -> Self {
+        self.ignore_case = ignore_case;
+        self
+    }
+
+    /// Converts the sentinel patterns and the names they're matched
+    /// against to Unicode NFC first, so that `é` matches however it's
+    /// stored. This is the default on macOS, whose file systems may
+    /// store names decomposed (NFD).
+    pub fn normalize_unicode(mut self, normalize_unicode: bool) -> Self {
+        self.normalize_unicode = normalize_unicode;
+        self
+    }
+
+    /// Restricts which kinds of entries may be sentinels.
+    pub fn sentinel_kind(mut self, sentinel_kind: SentinelKind) -> Self {
+        self.sentinel_kind = sentinel_kind;
+        self
+    }
+
+    /// Only counts well-known sentinels for these types of project,
+    /// e.g. `Cargo.toml` for [`ProjectType::Rust`]. An empty list
+    /// allows any sentinel.
+    pub fn types<I>(mut self, types: I) -> Self
+    where
+        I: IntoIterator<Item = ProjectType>,
+    {
+        self.types.extend(types);
+        self
+    }
+
+    /// Only counts an entry as a sentinel if its contents match the
+    /// regex `pattern`. Only the first megabyte of each candidate is
+    /// read, and directories never match.
+    pub fn contains(mut self, pattern: Option<String>) -> Self {
+        self.contains = pattern;
+        self
+    }
+
+    /// Only counts an entry as a sentinel if it was modified within
+    /// this long before it's checked.
+    pub fn changed_within(mut self, changed_within: Option<Duration>) -> Self {
+        self.changed_within = changed_within;
+        self
+    }
+
+    /// Adds a root directory to search beneath.
+    pub fn root(mut self, root: impl Into<PathBuf>) -> Self {
+        self.roots.push(root.into());
+        self
+    }
+
+    /// Adds several root directories to search beneath.
+    pub fn roots<I>(mut self, roots: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<PathBuf>,
+    {
+        self.roots.extend(roots.into_iter().map(Into::into));
+        self
+    }
+
+    /// Adds a root directory with its own limit on how many levels
+    /// below it are searched, in place of [`FinderBuilder::max_depth`].
+    pub fn root_with_max_depth(mut self, root: impl Into<PathBuf>, max_depth: usize) -> Self {
+        let root = root.into();
+        self.root_max_depths.push((root.clone(), max_depth));
+        self.roots.push(root);
+        self
+    }
+
+    /// Limits how many levels below a root are searched.
+    pub fn max_depth(mut self, max_depth: Option<usize>) -> Self {
+        self.options.max_depth = max_depth;
+        self
+    }
+
+    /// Suppresses matches fewer than this many levels below a root,
+    /// while still traversing through them.
+    pub fn min_depth(mut self, min_depth: Option<usize>) -> Self {
+        self.options.min_depth = min_depth;
+        self
+    }
+
+    /// Skips entries with any of these exact names.
+    pub fn ignore<I>(mut self, names: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        self.options
+            .ignore
+            .extend(names.into_iter().map(Into::into));
+        self
+    }
+
+    /// Sets the number of worker threads. Defaults to the number of CPUs.
+    pub fn threads(mut self, threads: Option<usize>) -> Self {
+        self.threads = threads;
+        self
+    }
+
+    /// Lets the number of workers running at once adapt to the file
+    /// system, between `threads` and `max_threads`. More are added
+    /// while they spend most of their time waiting on `read_dir`, as on
+    /// a network file system, and taken away again while they don't.
+    pub fn max_threads(mut self, max_threads: Option<usize>) -> Self {
+        self.max_threads = max_threads;
+        self
+    }
+
+    /// Stops the search once this many matches have been found.
+    pub fn max_results(mut self, max_results: Option<usize>) -> Self {
+        self.options.max_results = max_results;
+        self
+    }
+
+    /// Reports each match by its canonical path, with symlinks and `..`
+    /// resolved, rather than the path it was found through.
+    pub fn canonical_paths(mut self, canonical_paths: bool) -> Self {
+        self.options.canonical_paths = canonical_paths;
+        self
+    }
+
+    /// Stops searching each root once something beneath it has matched,
+    /// so there's at most one match per root.
+    pub fn first_per_root(mut self, first_per_root: bool) -> Self {
+        self.options.first_per_root = first_per_root;
+        self
+    }
+
+    /// Stops the search once it has been running this long, ending
+    /// the iterator with whatever was found. See [`Matches::timed_out`].
+    pub fn timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.options.timeout = timeout;
+        self
+    }
+
+    /// Keeps searching beneath directories which matched.
+    pub fn no_prune(mut self, no_prune: bool) -> Self {
+        self.options.no_prune = no_prune;
+        self
+    }
+
+    /// Descends into hidden directories, whose names start with `.`.
+    /// They can still contain sentinels either way.
+    pub fn hidden(mut self, hidden: bool) -> Self {
+        self.options.hidden = hidden;
+        self
+    }
+
+    /// Doesn't descend into directories on a different filesystem from
+    /// their root, like network mounts or backup volumes. Only
+    /// supported on Unix.
+    pub fn same_file_system(mut self, same_file_system: bool) -> Self {
+        self.options.same_file_system = same_file_system;
+        self
+    }
+
+    /// Doesn't descend into network mounts, like NFS or SMB shares and
+    /// FUSE file systems, which can hang the search for minutes when
+    /// they're slow to respond. They're found from the local mount
+    /// table when the search starts, so none of them are touched. The
+    /// roots themselves are searched wherever they are. Only supported
+    /// on Unix.
+    pub fn skip_network_fs(mut self, skip_network_fs: bool) -> Self {
+        self.options.skip_network_fs = skip_network_fs;
+        self
+    }
+
+    /// Stops the search at the first directory which can't be read,
+    /// rather than skipping it. See [`Matches::take_skipped`].
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.options.strict = strict;
+        self
+    }
+
+    /// Stops honoring `.gitignore`, `.ignore` and `.fdignore` files.
+    pub fn no_ignore_files(mut self, no_ignore_files: bool) -> Self {
+        self.options.no_ignore_files = no_ignore_files;
+        self
+    }
+
+    /// Descends into symlinked directories. Roots are always followed.
+    pub fn follow(mut self, follow: bool) -> Self {
+        self.options.follow = follow;
+        self
+    }
+
+    /// Reads each directory's entries in name order, rather than
+    /// whatever order the filesystem returns them in, so that the
+    /// sentinel reported for a directory is always the same one.
+    pub fn sort_entries(mut self, sort_entries: bool) -> Self {
+        self.options.sort_entries = sort_entries;
+        self
+    }
+
+    /// Keeps searching beneath directories which matched, but only this
+    /// many levels further, e.g. to find the packages in a monorepo.
+    /// Each nested match allows as many levels again below it.
+    pub fn nested(mut self, nested: Option<usize>) -> Self {
+        self.options.nested = nested;
+        self
+    }
+
+    /// Skips git submodules, which aren't reported. Like any other
+    /// match, what's beneath them is only searched with
+    /// [`FinderBuilder::no_prune`].
+    pub fn no_submodules(mut self, no_submodules: bool) -> Self {
+        self.options.no_submodules = no_submodules;
+        self
+    }
+
+    /// Only reports directories which are the top of a git repository
+    /// in this state, e.g. [`GitStatus::Dirty`] for those with
+    /// uncommitted changes. This runs git for each match, so it's
+    /// slower, and needs the real file system.
+    pub fn git_status(mut self, git_status: Option<GitStatus>) -> Self {
+        self.options.git_status = git_status;
+        self
+    }
+
+    /// Sets the order directories are scanned in. See [`Order`].
+    pub fn order(mut self, order: Order) -> Self {
+        self.options.order = order;
+        self
+    }
+
+    /// Caps how many directories can be waiting to be scanned. Beyond
+    /// that, workers scan the directories they find themselves, depth
+    /// first, which keeps memory bounded on huge trees at the cost of
+    /// some parallelism and of [`Order`]. Unlimited by default.
+    pub fn max_queue(mut self, max_queue: Option<usize>) -> Self {
+        self.options.max_queue = max_queue;
+        self
+    }
+
+    /// Calls `explain` with each decision the search makes about a
+    /// directory: whether it's searched, skipped or reported, and why.
+    /// It's called from the worker threads, so it should be quick.
+    pub fn explain(mut self, explain: impl Fn(&Path, &Decision) + Send + Sync + 'static) -> Self {
+        self.explainer = Some(Explainer::new(explain));
+        self
+    }
+
+    /// Searches `file_system` rather than the real file system. See
+    /// [`crate::file_system`].
+    pub fn file_system(mut self, file_system: impl FileSystem + 'static) -> Self {
+        self.file_system = Some(Arc::new(file_system));
+        self
+    }
+
+    /// Searches inside `.tar`, `.tar.gz` and `.zip` files too. Each
+    /// archive's contents appear in a directory next to it whose name
+    /// ends in `!`, so a project inside `old.tar` is found at
+    /// `old.tar!/project`, which is shown as `old.tar!project` (see
+    /// [`FileSystem::display`]).
+    #[cfg(feature = "archives")]
+    pub fn archives(mut self, archives: bool) -> Self {
+        self.archives = archives;
+        self
+    }
+
+    pub fn build(self) -> anyhow::Result<Finder> {
+        let mut pool = ThreadPoolBuilder::new();
+        if let Some(threads) = self.threads {
+            if threads == 0 {
+                return Err(anyhow!("Thread count must be at least 1"));
+            }
+            pool = pool.num_threads(threads);
+        }
+        let mut adaptive = None;
+        if let Some(max_threads) = self.max_threads {
+            let min_threads = match self.threads {
+                Some(threads) => threads,
+                None => thread::available_parallelism().map_or(1, usize::from),
+            };
+            if max_threads < min_threads {
+                return Err(anyhow!(
+                    "The maximum thread count ({}) can't be less than the thread count ({})",
+                    max_threads,
+                    min_threads
+                ));
+            }
+            pool = pool.num_threads(max_threads);
+            adaptive = Some(Arc::new(AdaptiveWorkers::new(min_threads, max_threads)));
+        }
+
+        let fs = (self.file_system.clone()).unwrap_or_else(|| Arc::new(RealFileSystem));
+        #[cfg(feature = "archives")]
+        let fs: Arc<dyn FileSystem> = match self.archives {
+            true => Arc::new(ArchiveFileSystem::new(fs)),
+            false => fs,
+        };
+        let pool = pool.build()?;
+        let mut finder = Finder {
+            open_dirs: OpenDirLimit::for_threads(pool.current_num_threads()).map(Arc::new),
+            pool: Arc::new(pool),
+            adaptive,
+            matcher: Arc::new(Matcher::new(&self)?),
+            roots: self.roots.iter().map(|root| to_search_root(root)).collect(),
+            max_depths: (self.root_max_depths.iter())
+                .map(|(root, max_depth)| (to_search_root(root), *max_depth))
+                .collect(),
+            options: Arc::new(self.options),
+            explainer: self.explainer,
+            fs,
+            merged_roots: Vec::new(),
+            invalid_roots: Vec::new(),
+            #[cfg(feature = "archives")]
+            archives: self.archives,
+        };
+        finder.check_roots()?;
+        finder.merge_nested_roots();
+        Ok(finder)
+    }
+}
+
+fn default_normalize_unicode() -> bool {
+    cfg!(target_os = "macos")
+}
+
+/// Searches for directories containing a sentinel file.
+pub struct Finder {
+    pool: Arc<ThreadPool>,
+    /// Set if there are more threads than directories we can have open.
+    open_dirs: Option<Arc<OpenDirLimit>>,
+    /// Set if the number of workers adapts to the file system.
+    adaptive: Option<Arc<AdaptiveWorkers>>,
+    matcher: Arc<Matcher>,
+    pub(crate) roots: Vec<PathBuf>,
+    /// Depth limits for particular roots.
+    max_depths: HashMap<PathBuf, usize>,
+    pub(crate) options: Arc<Options>,
+    explainer: Option<Explainer>,
+    pub(crate) fs: Arc<dyn FileSystem>,
+    merged_roots: Vec<MergedRoot>,
+    invalid_roots: Vec<Skipped>,
+    #[cfg(feature = "archives")]
+    archives: bool,
+}
+
+impl Finder {
+    /// Starts searching every root in the background, returning an
+    /// iterator over matches as they're found. Dropping the iterator
+    /// early cancels the rest of the search.
+    pub fn run(&self) -> Matches {
+        let mut matches = self.search(self.roots.iter().map(|root| (root.clone(), 0)));
+        matches.deadline =
+            (self.options.timeout).and_then(|timeout| Instant::now().checked_add(timeout));
+        matches
+    }
+
+    /// Searches beneath each directory, treating it as though it were
+    /// already the given depth below a root.
+    pub(crate) fn search<I>(&self, dirs: I) -> Matches
+    where
+        I: IntoIterator<Item = (PathBuf, usize)>,
+    {
+        let (sender, receiver) = channel::unbounded();
+        let (close, closed) = channel::bounded(1);
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let counters = Arc::new(Counters::new(self.pool.current_num_threads()));
+        let skipped = Arc::new(Mutex::new(Vec::new()));
+        let ctx = Arc::new(Context {
+            pool: self.pool.clone(),
+            open_dirs: self.open_dirs.clone(),
+            adaptive: self.adaptive.clone(),
+            matcher: self.matcher.clone(),
+            options: self.options.clone(),
+            explainer: self.explainer.clone(),
+            fs: self.fs.clone(),
+            network_mounts: (self.options.skip_network_fs).then(NetworkMounts::load),
+            sender,
+            visited: Mutex::new(HashSet::new()),
+            reported: Mutex::new(HashSet::new()),
+            match_count: AtomicUsize::new(0),
+            sent_count: AtomicUsize::new(0),
+            queued: AtomicUsize::new(0),
+            shallowest: Mutex::new(BinaryHeap::new()),
+            cancelled: cancelled.clone(),
+            close: close.clone(),
+            counters: counters.clone(),
+            skipped: skipped.clone(),
+        });
+
+        // Every job holds a reference to the context, so once the last
+        // job finishes the sender is dropped and the iterator ends.
+        for (dir, depth) in dirs {
+            let ignores = self.ancestor_ignores(&dir, depth);
+            let root = dir.ancestors().nth(depth);
+            let root_device = if self.options.same_file_system {
+                root.and_then(|root| device(&*self.fs, root))
+            } else {
+                None
+            };
+            let max_depth = root.map_or(self.options.max_depth, |root| self.max_depth_of(root));
+            let work_item = Job {
+                ctx: ctx.clone(),
+                // Root directories are always followed, even without
+                // --follow, because read_dir() works through symlinks.
+                path: dir,
+                depth,
+                ignores,
+                root_device,
+                max_depth,
+                nested_limit: None,
+                root_matched: Arc::new(AtomicBool::new(false)),
+            };
+            ctx.explain(&work_item.path, || Decision::Descended);
+            ctx.queue(work_item);
+        }
+
+        Matches {
+            receiver,
+            deadline: None,
+            timed_out: false,
+            cancelled,
+            close,
+            closed,
+            is_closed: false,
+            counters,
+            skipped,
+        }
+    }
+
+    /// The file system being searched, for finding out more about the
+    /// matches.
+    pub fn file_system(&self) -> Arc<dyn FileSystem> {
+        self.fs.clone()
+    }
+
+    /// The roots which will be searched, after dropping any which
+    /// can't be or don't need to be.
+    pub fn roots(&self) -> &[PathBuf] {
+        &self.roots
+    }
+
+    /// Roots which were dropped because they're inside another root,
+    /// which already searches them.
+    pub fn merged_roots(&self) -> &[MergedRoot] {
+        &self.merged_roots
+    }
+
+    /// Roots which were dropped because they aren't directories we can
+    /// read. With [`FinderBuilder::strict`], building fails instead.
+    pub fn invalid_roots(&self) -> &[Skipped] {
+        &self.invalid_roots
+    }
+
+    /// Searches every root, calling `visitor` with each match as it's
+    /// found. Returning [`ControlFlow::Break`] stops the search.
+    ///
+    /// ```no_run
+    /// use std::ops::ControlFlow;
+    ///
+    /// let finder = pj::FinderBuilder::new("Cargo.toml").root(".").build()?;
+    /// finder.visit(|found| {
+    ///     println!("{}", found.path.display());
+    ///     if found.path.ends_with("pj") {
+    ///         ControlFlow::Break(())
+    ///     } else {
+    ///         ControlFlow::Continue(())
+    ///     }
+    /// });
+    /// # Ok::<(), anyhow::Error>(())
+    /// ```
+    pub fn visit<F>(&self, mut visitor: F)
+    where
+        F: FnMut(Match) -> ControlFlow<()>,
+    {
+        for found in self.run() {
+            if visitor(found).is_break() {
+                break;
+            }
+        }
+    }
+
+    /// Adds up the size in bytes of every file beneath `dir`, e.g. a
+    /// match, like `du --apparent-size`. Subdirectories are read in
+    /// parallel on the same threads as searches. Symlinks aren't
+    /// followed, and anything which can't be read is left out.
+    pub fn disk_usage(&self, dir: &Path) -> io::Result<u64> {
+        #[cfg(feature = "archives")]
+        let skip_archives = self.archives;
+        #[cfg(not(feature = "archives"))]
+        let skip_archives = false;
+        (self.pool).install(|| disk_usage(&*self.fs, dir, skip_archives))
+    }
+
+    /// Finds the nearest directory containing a sentinel, starting at
+    /// `dir` and working up to the root of the file system, like
+    /// `git rev-parse --show-toplevel` does for `.git`. The match's
+    /// depth is how many levels above `dir` it is.
+    pub fn find_up(&self, dir: &Path) -> io::Result<Option<Match>> {
+        let dir = self.fs.canonicalize(dir)?;
+        Ok(dir.ancestors().enumerate().find_map(|(depth, ancestor)| {
+            let (sentinel, patterns) = self.sentinel_in(ancestor)?;
+            Some(Match {
+                path: ancestor.to_path_buf(),
+                sentinel,
+                patterns,
+                depth,
+            })
+        }))
+    }
+
+    /// How many levels below `root` are searched.
+    fn max_depth_of(&self, root: &Path) -> Option<usize> {
+        self.max_depths
+            .get(root)
+            .copied()
+            .or(self.options.max_depth)
+    }
+
+    /// Whether `dir`, `depth` levels below its root, is too deep to be
+    /// searched.
+    pub(crate) fn exceeds_max_depth(&self, dir: &Path, depth: usize) -> bool {
+        let max_depth = match dir.ancestors().nth(depth) {
+            Some(root) => self.max_depth_of(root),
+            None => self.options.max_depth,
+        };
+        max_depth.is_some_and(|max_depth| depth >= max_depth)
+    }
+
+    /// Whether an entry with this name could mark its parent as a match.
+    pub(crate) fn is_sentinel_name(&self, file_name: &OsStr) -> bool {
+        !self.options.should_ignore(file_name) && self.matcher.is_match_name(file_name)
+    }
+
+    /// Whether `dir` currently contains the sentinels to be a match,
+    /// ignoring its depth.
+    pub(crate) fn dir_matches(&self, dir: &Path) -> bool {
+        self.sentinel_in(dir).is_some()
+    }
+
+    /// The first sentinel in `dir` and the patterns it had sentinels
+    /// for, if it currently contains the sentinels to be a match,
+    /// ignoring its depth.
+    pub(crate) fn sentinel_in(&self, dir: &Path) -> Option<(PathBuf, Vec<String>)> {
+        let entries = self.fs.read_dir(dir).ok()?;
+        let mut dir_matcher = self.matcher.dir_matcher(&*self.fs);
+        let mut sentinel = None;
+        for dir_entry in entries.filter_map(Result::ok) {
+            let file_name = &dir_entry.file_name;
+            if self.options.should_ignore(file_name) {
+                continue;
+            }
+            if self.matcher.is_excluded(file_name) {
+                return None;
+            }
+            if let Some(found) = dir_matcher.add(dir, &dir_entry) {
+                sentinel.get_or_insert(found);
+                if dir_matcher.is_match() && !self.matcher.has_excludes() {
+                    break;
+                }
+            }
+        }
+        let sentinel = sentinel.filter(|_| dir_matcher.is_match())?;
+        Some((sentinel, dir_matcher.patterns()))
+    }
+
+    /// Loads the ignore files which apply to `dir`, `depth` levels
+    /// below its root, from the directories above it.
+    fn ancestor_ignores(&self, dir: &Path, depth: usize) -> Option<Arc<IgnoreStack>> {
+        match (dir.parent(), depth.checked_sub(1)) {
+            (Some(parent), Some(parent_depth)) if !self.options.no_ignore_files => {
+                IgnoreStack::load(&*self.fs, parent, parent_depth)
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether `path`, `depth` levels below its root, or any directory
+    /// between them is excluded by an ignore file.
+    pub(crate) fn is_ignored_by_files(&self, path: &Path, depth: usize) -> bool {
+        self.ancestor_ignores(path, depth)
+            .is_some_and(|ignores| ignores.is_ignored_with_parents(path, self.is_dir(path)))
+    }
+
+    /// Whether an entry with this name stops its parent from being
+    /// reported or searched.
+    pub(crate) fn is_excluded_name(&self, file_name: &OsStr) -> bool {
+        !self.options.should_ignore(file_name) && self.matcher.is_excluded(file_name)
+    }
+
+    /// Whether a directory with this name shouldn't be searched, from
+    /// `--prune` or `--hidden`.
+    pub(crate) fn skips_dir_name(&self, file_name: &OsStr) -> bool {
+        self.options.skips_hidden(file_name) || self.matcher.is_pruned(file_name)
+    }
+
+    /// Whether `dir` currently contains an entry which excludes it.
+    pub(crate) fn is_excluded_dir(&self, dir: &Path) -> bool {
+        if !self.matcher.has_excludes() {
+            return false;
+        }
+        let Ok(entries) = self.fs.read_dir(dir) else {
+            return false;
+        };
+        entries
+            .filter_map(Result::ok)
+            .any(|dir_entry| self.is_excluded_name(&dir_entry.file_name))
+    }
+
+    /// Drops roots which don't exist, aren't directories, or can't be
+    /// read, so that each gets a clear error up front rather than
+    /// whatever the first job to touch it runs into.
+    fn check_roots(&mut self) -> anyhow::Result<()> {
+        let mut valid = Vec::new();
+        for root in std::mem::take(&mut self.roots) {
+            let error = match self.fs.metadata(&root) {
+                Err(e) if e.kind() == io::ErrorKind::NotFound => anyhow!("No such directory"),
+                Err(e) => e.into(),
+                Ok(metadata) if !metadata.file_type.is_dir() => anyhow!("Not a directory"),
+                Ok(_) => match self.fs.read_dir(&root) {
+                    Err(e) => e.into(),
+                    Ok(_) => {
+                        valid.push(root);
+                        continue;
+                    }
+                },
+            };
+            if self.options.strict {
+                let display = to_display(&root).display().to_string();
+                return Err(error.context(format!("Can't search {}", display)));
+            }
+            self.invalid_roots.push(Skipped { path: root, error });
+        }
+        self.roots = valid;
+        Ok(())
+    }
+
+    /// Drops roots which another root's search would cover anyway, so
+    /// that nothing gets scanned or reported twice. Runs after
+    /// [`Finder::check_roots`], so any root which can't be
+    /// canonicalized went away since, and is kept for the search to
+    /// report.
+    fn merge_nested_roots(&mut self) {
+        let canonical: Vec<Option<PathBuf>> = (self.roots.iter())
+            .map(|root| self.fs.canonicalize(root).ok())
+            .collect();
+        // Outer roots need to be kept before we look at the roots
+        // inside them.
+        let mut by_length: Vec<usize> = (0..self.roots.len()).collect();
+        by_length.sort_by_key(|&i| canonical[i].as_ref().map(|path| path.components().count()));
+
+        let mut kept = Vec::new();
+        for i in by_length {
+            let outer = canonical[i].as_ref().and_then(|inner| {
+                kept.iter().copied().find(|&j: &usize| {
+                    // A depth limit would count from the wrong root.
+                    let has_max_depth = self.max_depth_of(&self.roots[i]).is_some()
+                        || self.max_depth_of(&self.roots[j]).is_some();
+                    !has_max_depth
+                        && (canonical[j].as_ref()).is_some_and(|outer| self.covers(outer, inner))
+                })
+            });
+            match outer {
+                Some(j) => self.merged_roots.push(MergedRoot {
+                    root: self.roots[i].clone(),
+                    into: self.roots[j].clone(),
+                }),
+                None => kept.push(i),
+            }
+        }
+        kept.sort();
+        self.roots = kept.into_iter().map(|i| self.roots[i].clone()).collect();
+    }
+
+    /// Whether `path` is a directory, or a symlink to one.
+    pub(crate) fn is_dir(&self, path: &Path) -> bool {
+        (self.fs.metadata(path)).is_ok_and(|metadata| metadata.file_type.is_dir())
+    }
+
+    /// Whether the search beneath `outer` would search `inner` just as
+    /// if it were a root of its own. Both must be canonical.
+    fn covers(&self, outer: &Path, inner: &Path) -> bool {
+        let Ok(relative) = inner.strip_prefix(outer) else {
+            return false;
+        };
+        let depth = relative.components().count();
+        if depth == 0 {
+            return true;
+        }
+        let options = &self.options;
+        let is_skipped = relative.components().any(|component| {
+            options.should_ignore(component.as_os_str())
+                || self.skips_dir_name(component.as_os_str())
+        });
+        // A match or an exclusion on the way down would stop the
+        // outer search before it got there.
+        let is_cut_off = inner
+            .ancestors()
+            .skip(1)
+            .take(depth)
+            .zip((0..depth).rev())
+            .any(|(dir, dir_depth)| {
+                let is_pruned = !options.no_prune
+                    && !options.below_min_depth(dir_depth)
+                    && self.dir_matches(dir);
+                is_pruned || self.is_excluded_dir(dir)
+            });
+        let crosses_device =
+            options.same_file_system && device(&*self.fs, outer) != device(&*self.fs, inner);
+        !is_skipped && !is_cut_off && !crosses_device && !self.is_ignored_by_files(inner, depth)
+    }
+}
+
+/// A root which was dropped because it's inside another, from
+/// [`Finder::merged_roots`].
+#[derive(Clone, Debug)]
+pub struct MergedRoot {
+    pub root: PathBuf,
+    /// The root it's searched as part of.
+    pub into: PathBuf,
+}
+
+/// A directory which contains the sentinel.
+#[derive(Clone, Debug)]
+pub struct Match {
+    pub path: PathBuf,
+    /// The first sentinel found in the directory.
+    pub sentinel: PathBuf,
+    /// The sentinel patterns which the directory had sentinels for.
+    /// Unless all of them are required, reading the directory stops at
+    /// the first sentinel, so this may not be all of them.
+    pub patterns: Vec<String>,
+    /// How many levels below its root the directory is.
+    pub depth: usize,
+}
+
+impl Match {
+    /// Classifies the project by its sentinel, if it's a well-known one.
+    /// For a sentinel like `.git/HEAD`, that's the entry in the project
+    /// directory, `.git`.
+    pub fn project_type(&self) -> Option<ProjectType> {
+        let name = match self.sentinel.strip_prefix(&self.path) {
+            Ok(relative) => relative.iter().next()?,
+            Err(_) => self.sentinel.file_name()?,
+        };
+        ProjectType::of(name.to_str()?)
+    }
+}
+
+/// Iterator over the matches of a running search, returned by
+/// [`Finder::run`].
+pub struct Matches {
+    receiver: Receiver<Match>,
+    /// When the search gives up, with [`FinderBuilder::timeout`].
+    deadline: Option<Instant>,
+    timed_out: bool,
+    pub(crate) cancelled: Arc<AtomicBool>,
+    /// Ends the iterator early, from [`CancelHandle::cancel`] or once
+    /// `--max-results` matches have been sent.
+    close: Sender<()>,
+    closed: Receiver<()>,
+    is_closed: bool,
+    counters: Arc<Counters>,
+    pub(crate) skipped: Arc<Mutex<Vec<Skipped>>>,
+}
+
+impl Matches {
+    /// Takes the errors which have made the search skip something
+    /// since the last call. With [`FinderBuilder::strict`], the search
+    /// stops at the first one.
+    pub fn take_skipped(&self) -> Vec<Skipped> {
+        std::mem::take(&mut self.skipped.lock().unwrap())
+    }
+
+    /// A handle for stopping the search from another thread, e.g. a
+    /// signal handler. The iterator then ends as soon as it has
+    /// returned the matches already found, without waiting for the
+    /// directories still being read.
+    pub fn cancel_handle(&self) -> CancelHandle {
+        CancelHandle {
+            cancelled: self.cancelled.clone(),
+            close: self.close.clone(),
+        }
+    }
+
+    /// Whether the search was stopped by [`FinderBuilder::timeout`]
+    /// before it finished, so some matches may be missing.
+    pub fn timed_out(&self) -> bool {
+        self.timed_out
+    }
+
+    /// What the search has done so far. Once the iterator is
+    /// exhausted, this covers the whole search.
+    pub fn stats(&self) -> Stats {
+        self.counters.stats()
+    }
+
+    /// A handle for checking on the search's stats from another thread
+    /// while this one consumes the matches.
+    pub fn stats_handle(&self) -> StatsHandle {
+        StatsHandle {
+            counters: self.counters.clone(),
+        }
+    }
+}
+
+/// A directory, or part of one, which couldn't be searched.
+#[derive(Debug)]
+pub struct Skipped {
+    pub path: PathBuf,
+    pub error: anyhow::Error,
+}
+
+impl Skipped {
+    /// Whether we weren't allowed to read it (`EACCES` or `EPERM`),
+    /// rather than something going wrong while reading it.
+    pub fn is_permission_denied(&self) -> bool {
+        self.error.chain().any(|cause| {
+            (cause.downcast_ref::<io::Error>())
+                .is_some_and(|e| e.kind() == io::ErrorKind::PermissionDenied)
+        })
+    }
+}
+
+/// Reads a search's stats, from [`Matches::stats_handle`].
+#[derive(Clone)]
+pub struct StatsHandle {
+    counters: Arc<Counters>,
+}
+
+impl StatsHandle {
+    pub fn stats(&self) -> Stats {
+        self.counters.stats()
+    }
+}
+
+/// Stops a search, from [`Matches::cancel_handle`].
+#[derive(Clone)]
+pub struct CancelHandle {
+    cancelled: Arc<AtomicBool>,
+    close: Sender<()>,
+}
+
+impl CancelHandle {
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+        // If it's full, the iterator has already been told.
+        let _ = self.close.try_send(());
+    }
+}
+
+impl Iterator for Matches {
+    type Item = Match;
+
+    fn next(&mut self) -> Option<Match> {
+        if self.is_closed {
+            return self.receiver.try_recv().ok();
+        }
+        let deadline = self.deadline.map_or_else(channel::never, channel::at);
+        channel::select! {
+            recv(self.receiver) -> found => found.ok(),
+            recv(self.closed) -> _ => {
+                self.is_closed = true;
+                self.receiver.try_recv().ok()
+            }
+            recv(deadline) -> _ => {
+                self.timed_out = true;
+                self.cancelled.store(true, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+}
+
+impl Drop for Matches {
+    fn drop(&mut self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+}
+
+/// The order directories are scanned in, from [`FinderBuilder::order`].
+/// Each worker follows it for the directories it finds itself, but
+/// idle workers steal from the others, so it's never exact.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Order {
+    /// Newest directories first, which keeps the backlog small.
+    #[default]
+    Dfs,
+    /// Oldest directories first, which finds shallow matches sooner.
+    Bfs,
+    /// The shallowest waiting directory first, across every worker.
+    /// This is the closest to reporting top-level projects first, but
+    /// the shared queue costs some speed. Once [`FinderBuilder::max_queue`]
+    /// is reached, workers scan depth first regardless.
+    Shallowest,
+}
+
+impl Order {
+    pub const NAMES: &'static [&'static str] = &["dfs", "bfs", "shallowest"];
+}
+
+impl FromStr for Order {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "dfs" => Ok(Order::Dfs),
+            "bfs" => Ok(Order::Bfs),
+            "shallowest" => Ok(Order::Shallowest),
+            _ => Err(anyhow!("Invalid traversal order {:?}", s)),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub(crate) struct Options {
+    max_depth: Option<usize>,
+    min_depth: Option<usize>,
+    ignore: Vec<String>,
+    max_results: Option<usize>,
+    pub(crate) first_per_root: bool,
+    canonical_paths: bool,
+    pub(crate) no_prune: bool,
+    pub(crate) no_ignore_files: bool,
+    hidden: bool,
+    same_file_system: bool,
+    skip_network_fs: bool,
+    strict: bool,
+    pub(crate) follow: bool,
+    max_queue: Option<usize>,
+    order: Order,
+    sort_entries: bool,
+    no_submodules: bool,
+    pub(crate) nested: Option<usize>,
+    timeout: Option<Duration>,
+    git_status: Option<GitStatus>,
+}
+
+impl Options {
+    pub(crate) fn below_min_depth(&self, depth: usize) -> bool {
+        if let Some(min_depth) = self.min_depth {
+            depth < min_depth
+        } else {
+            false
+        }
+    }
+
+    /// Whether we shouldn't descend into a directory with this name
+    /// because it's hidden.
+    pub(crate) fn skips_hidden(&self, file_name: &OsStr) -> bool {
+        !self.hidden && file_name.as_encoded_bytes().starts_with(b".")
+    }
+
+    /// Why `dir`, which has its sentinels, still isn't reported: it's a
+    /// skipped git submodule, or not in the `--git-status` asked for.
+    /// None if it should be reported.
+    pub(crate) fn unreported_because(&self, fs: &dyn FileSystem, dir: &Path) -> Option<Decision> {
+        if self.no_submodules && is_submodule(fs, dir) {
+            return Some(Decision::Submodule);
+        }
+        match self.git_status {
+            Some(git_status) if !git_status.matches(fs, dir) => {
+                Some(Decision::OtherGitStatus { wanted: git_status })
+            }
+            _ => None,
+        }
+    }
+
+    pub(crate) fn should_ignore(&self, file_name: &OsStr) -> bool {
+        for candidate in self.ignore.iter() {
+            if file_name == candidate.as_str() {
+                return true;
+            }
+        }
+        false
+    }
+}
+
+/// State shared between every job in a single run.
+struct Context {
+    pool: Arc<ThreadPool>,
+    open_dirs: Option<Arc<OpenDirLimit>>,
+    adaptive: Option<Arc<AdaptiveWorkers>>,
+    matcher: Arc<Matcher>,
+    options: Arc<Options>,
+    explainer: Option<Explainer>,
+    fs: Arc<dyn FileSystem>,
+    /// With --skip-network-fs, the directories not to descend into.
+    network_mounts: Option<NetworkMounts>,
+    sender: Sender<Match>,
+    visited: Mutex<HashSet<DirKey>>,
+    /// The directories which have matched, so that one reachable
+    /// through several paths is only reported once.
+    reported: Mutex<HashSet<DirKey>>,
+    match_count: AtomicUsize,
+    /// Matches sent so far, which only catches up with `match_count`
+    /// once the workers which claimed a slot have sent their match.
+    sent_count: AtomicUsize,
+    /// Jobs which have been spawned but haven't started yet.
+    queued: AtomicUsize,
+    /// Jobs waiting to be picked up with [`Order::Shallowest`].
+    shallowest: Mutex<BinaryHeap<ByDepth>>,
+    cancelled: Arc<AtomicBool>,
+    close: Sender<()>,
+    counters: Arc<Counters>,
+    skipped: Arc<Mutex<Vec<Skipped>>>,
+}
+
+impl Context {
+    /// Records that we're about to scan `path`, returning false if the
+    /// same directory has already been scanned through another path.
+    /// Without --follow the tree can't contain cycles, so we skip the
+    /// extra stat per directory.
+    fn first_visit(&self, path: &Path) -> anyhow::Result<bool> {
+        if !self.options.follow {
+            return Ok(true);
+        }
+        let key = dir_key(&*self.fs, path)?;
+        Ok(self.visited.lock().unwrap().insert(key))
+    }
+
+    /// Records that `path` matched, returning false if the same
+    /// directory has already been reported through another path. If we
+    /// can't tell, it's reported.
+    fn first_report(&self, path: &Path) -> bool {
+        match dir_key(&*self.fs, path) {
+            Ok(key) => self.reported.lock().unwrap().insert(key),
+            Err(_) => true,
+        }
+    }
+
+    /// Spawns `job` on the pool.
+    fn queue(&self, job: Job) {
+        let queued = self.queued.fetch_add(1, Ordering::Relaxed) + 1;
+        self.counters.local().add_queued(queued as u64);
+        let queued_at = Instant::now();
+        let run_one = |job: Job, queued_at: Instant| {
+            job.ctx.queued.fetch_sub(1, Ordering::Relaxed);
+            job.job(Some(queued_at));
+        };
+        match self.options.order {
+            Order::Dfs => self.pool.spawn(move || run_one(job, queued_at)),
+            Order::Bfs => self.pool.spawn_fifo(move || run_one(job, queued_at)),
+            // Every job pushed pairs with a task which pops one, so
+            // they all get run, just not necessarily by that task.
+            Order::Shallowest => {
+                let ctx = job.ctx.clone();
+                self.shallowest
+                    .lock()
+                    .unwrap()
+                    .push(ByDepth(job, queued_at));
+                self.pool.spawn(move || {
+                    let next = ctx.shallowest.lock().unwrap().pop();
+                    if let Some(ByDepth(job, queued_at)) = next {
+                        run_one(job, queued_at);
+                    }
+                });
+            }
+        }
+    }
+
+    /// Spawns `job` on the pool, or runs it straight away if
+    /// `--max-queue` jobs are already waiting. Only call this from a
+    /// worker, since it can block for as long as the job takes.
+    fn queue_or_run(&self, job: Job) {
+        let is_full = (self.options.max_queue)
+            .is_some_and(|max_queue| self.queued.load(Ordering::Relaxed) >= max_queue);
+        if is_full {
+            job.job(None);
+        } else {
+            self.queue(job);
+        }
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed)
+    }
+
+    /// Passes a decision about `path` to the explainer, if there is
+    /// one, and logs it at debug level. The decision is only built if
+    /// it's needed.
+    fn explain(&self, path: &Path, decision: impl FnOnce() -> Decision) {
+        if self.explainer.is_none() && !tracing::enabled!(Level::DEBUG) {
+            return;
+        }
+        let decision = decision();
+        tracing::debug!(path = %path.display(), "{}", decision);
+        if let Some(explainer) = &self.explainer {
+            explainer.explain(path, &decision);
+        }
+    }
+
+    /// Records that `path` couldn't be searched. With --strict, that
+    /// ends the whole search.
+    fn skip(&self, path: &Path, error: anyhow::Error) {
+        WorkerCounters::add(&self.counters.local().errors, 1);
+        tracing::warn!(path = %path.display(), "Skipped: {:#}", error);
+        self.skipped.lock().unwrap().push(Skipped {
+            path: path.to_path_buf(),
+            error,
+        });
+        if self.options.strict {
+            self.cancel();
+        }
+    }
+
+    /// Hands a match to the caller, cancelling the search if they've
+    /// stopped listening.
+    fn send_match(&self, found: Match) {
+        WorkerCounters::add(&self.counters.local().matches, 1);
+        if self.sender.send(found).is_err() {
+            self.cancel();
+        }
+        // With every slot filled, there's no need to wait for the jobs
+        // still running before ending the iterator.
+        let sent = self.sent_count.fetch_add(1, Ordering::Relaxed) + 1;
+        if (self.options.max_results).is_some_and(|max_results| sent >= max_results) {
+            let _ = self.close.try_send(());
+        }
+    }
+
+    /// Reserves a slot for a new match, returning false if
+    /// `--max-results` has already been reached. Once the last slot is
+    /// taken the scan is cancelled so no new work gets picked up.
+    fn claim_match(&self) -> bool {
+        let Some(max_results) = self.options.max_results else {
+            return true;
+        };
+        let count = self.match_count.fetch_add(1, Ordering::Relaxed) + 1;
+        if count >= max_results {
+            self.cancel();
+        }
+        count <= max_results
+    }
+}
+
+/// Identifies a directory independent of the path used to reach it:
+/// its device and inode, or its canonical path where those aren't known.
+#[derive(Eq, Hash, PartialEq)]
+enum DirKey {
+    Inode(u64, u64),
+    Path(PathBuf),
+}
+
+fn dir_key(fs: &dyn FileSystem, path: &Path) -> anyhow::Result<DirKey> {
+    let metadata = fs.metadata(path)?;
+    Ok(match (metadata.device, metadata.inode) {
+        (Some(device), Some(inode)) => DirKey::Inode(device, inode),
+        _ => DirKey::Path(fs.canonicalize(path)?),
+    })
+}
+
+/// How much of a `.git` file is read to check where it points.
+const MAX_GIT_FILE_LEN: u64 = 4096;
+
+/// Whether `dir` is a git submodule. A submodule's `.git` is a file
+/// pointing at its repository inside the parent's `.git/modules`,
+/// unlike a worktree's, which points into `.git/worktrees`.
+fn is_submodule(fs: &dyn FileSystem, dir: &Path) -> bool {
+    let Ok(contents) = fs.read(&dir.join(".git"), MAX_GIT_FILE_LEN) else {
+        return false;
+    };
+    let contents = String::from_utf8_lossy(&contents);
+    contents.strip_prefix("gitdir:").is_some_and(|gitdir| {
+        let components: Vec<_> = Path::new(gitdir.trim()).components().collect();
+        // Just `modules` could be any directory, like one holding
+        // worktrees.
+        (components.windows(2))
+            .any(|pair| pair[0].as_os_str() == ".git" && pair[1].as_os_str() == "modules")
+    })
+}
+
+/// Finds which device (filesystem) `path` is on.
+fn device(fs: &dyn FileSystem, path: &Path) -> Option<u64> {
+    fs.metadata(path).ok()?.device
+}
+
+struct Job {
+    ctx: Arc<Context>,
+    path: PathBuf,
+    depth: usize,
+    /// Ignore files from the directories above this one.
+    ignores: Option<Arc<IgnoreStack>>,
+    /// The device of our root, with --same-file-system.
+    root_device: Option<u64>,
+    /// How many levels below our root are searched.
+    max_depth: Option<usize>,
+    /// With --nested, the deepest we can go below the last match.
+    nested_limit: Option<usize>,
+    /// Set once something beneath our root has matched, which ends the
+    /// search of that root with --first-per-root.
+    root_matched: Arc<AtomicBool>,
+}
+
+/// A job waiting for --order shallowest, with when it was queued.
+/// Ordered so that a [`BinaryHeap`] pops the shallowest first.
+struct ByDepth(Job, Instant);
+
+impl PartialEq for ByDepth {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.depth == other.0.depth
+    }
+}
+
+impl Eq for ByDepth {}
+
+impl PartialOrd for ByDepth {
+    fn partial_cmp(&self, other: &Self) -> Option<CmpOrdering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for ByDepth {
+    fn cmp(&self, other: &Self) -> CmpOrdering {
+        other.0.depth.cmp(&self.0.depth)
+    }
+}
+
+impl Job {
+    fn child(&self, new_path: PathBuf, ignores: Option<Arc<IgnoreStack>>) -> Self {
+        Job {
+            ctx: self.ctx.clone(),
+            path: new_path,
+            depth: self.depth + 1,
+            ignores,
+            root_device: self.root_device,
+            max_depth: self.max_depth,
+            nested_limit: self.nested_limit,
+            root_matched: self.root_matched.clone(),
+        }
+    }
+
+    /// Whether --first-per-root has already found our root's match.
+    fn is_root_done(&self) -> bool {
+        self.ctx.options.first_per_root && self.root_matched.load(Ordering::Relaxed)
+    }
+
+    /// Runs the job. `queued_at` is when it was queued, if it went
+    /// through the queue rather than being run in place.
+    fn job(self, queued_at: Option<Instant>) {
+        let skipped = self.ctx.is_cancelled() || self.is_root_done();
+        // Waiting for an adaptive worker's turn counts as queue wait,
+        // since the job is no nearer to running until it gets one.
+        let _active = (self.ctx.adaptive.as_deref())
+            .filter(|_| !skipped)
+            .and_then(AdaptiveWorkers::start);
+        if let Some(queued_at) = queued_at {
+            (self.ctx.counters.local()).add_queue_wait(queued_at.elapsed());
+        }
+        if !skipped {
+            let timer = CpuTimer::start();
+            let result = self.job_impl();
+            if let Some(timer) = timer {
+                timer.finish(self.ctx.counters.local());
+            }
+            if let Err(e) = result {
+                self.ctx.skip(&self.path, e);
+            }
+        }
+    }
+
+    /// Decides whether we should descend into `dir_entry`. Symlinks
+    /// are only traversed when `--follow` is passed, in which case we
+    /// descend if the link (eventually) points at a directory.
+    fn is_traversable(&self, dir_entry: &DirEntry) -> bool {
+        if dir_entry.file_type.is_symlink() {
+            // metadata() follows the whole chain of links for us, and
+            // a dangling link just isn't a directory.
+            self.ctx.options.follow && self.is_dir(&self.entry_path(dir_entry))
+        } else {
+            dir_entry.file_type.is_dir()
+        }
+    }
+
+    fn entry_path(&self, dir_entry: &DirEntry) -> PathBuf {
+        self.path.join(&dir_entry.file_name)
+    }
+
+    /// Whether `path` is a directory, or a symlink to one.
+    fn is_dir(&self, path: &Path) -> bool {
+        (self.ctx.fs.metadata(path)).is_ok_and(|metadata| metadata.file_type.is_dir())
+    }
+
+    /// Passes a decision about a child of this directory to the
+    /// explainer, so long as the child is a directory or a link to one.
+    /// Decisions about other entries are just noise.
+    fn explain_entry(&self, dir_entry: &DirEntry, decision: impl FnOnce() -> Decision) {
+        if self.ctx.explainer.is_none() && !tracing::enabled!(Level::DEBUG) {
+            return;
+        }
+        let path = self.entry_path(dir_entry);
+        if self.is_dir(&path) {
+            self.ctx.explain(&path, decision);
+        }
+    }
+
+    /// Checks --same-file-system, which is only set if we know the
+    /// root's device.
+    fn is_on_root_device(&self, dir_entry: &DirEntry) -> bool {
+        self.root_device.is_none_or(|root_device| {
+            device(&*self.ctx.fs, &self.entry_path(dir_entry)) == Some(root_device)
+        })
+    }
+
+    /// Checks --skip-network-fs, without touching the entry itself.
+    fn network_fs_type(&self, dir_entry: &DirEntry) -> Option<&str> {
+        (self.ctx.network_mounts.as_ref())?.fs_type(&self.entry_path(dir_entry))
+    }
+
+    fn job_impl(&self) -> anyhow::Result<()> {
+        if !self.ctx.first_visit(&self.path)? {
+            self.ctx.explain(&self.path, || Decision::AlreadyVisited);
+            return Ok(());
+        }
+
+        let should_enqueue = self
+            .max_depth
+            .is_none_or(|max_depth| self.depth + 1 < max_depth);
+
+        let mut found_paths = Vec::new();
+        let mut found_sentinel = false;
+        let mut sentinel = None;
+        let fs = &*self.ctx.fs;
+        let mut dir_matcher = self.ctx.matcher.dir_matcher(fs);
+        let counters = self.ctx.counters.local();
+        let mut dir_entries = Vec::new();
+        {
+            let _open_dir = self.ctx.open_dirs.as_deref().map(OpenDirLimit::acquire);
+            // Waiting for a directory to open can take a while, and
+            // reading a big one even longer on a slow mount, so the
+            // search may well have been cancelled in the meantime.
+            if self.ctx.is_cancelled() {
+                return Ok(());
+            }
+            let started = Instant::now();
+            for dir_entry in fs.read_dir(&self.path)? {
+                if self.ctx.is_cancelled() {
+                    return Ok(());
+                }
+                match dir_entry {
+                    Ok(dir_entry) => dir_entries.push(dir_entry),
+                    Err(e) => self.ctx.skip(&self.path, e.into()),
+                }
+            }
+            if self.ctx.adaptive.is_some() {
+                adaptive::add_read_time(started.elapsed());
+            }
+        }
+        if self.ctx.options.sort_entries {
+            dir_entries.sort_by(|a, b| a.file_name.cmp(&b.file_name));
+        }
+        WorkerCounters::add(&counters.dirs_scanned, 1);
+        WorkerCounters::add(&counters.entries_examined, dir_entries.len() as u64);
+        let ignores = if self.ctx.options.no_ignore_files {
+            None
+        } else {
+            let present: Vec<&str> = (IGNORE_FILES.iter().copied())
+                .chain([GIT_DIR])
+                .filter(|name| dir_entries.iter().any(|entry| entry.file_name == **name))
+                .collect();
+            IgnoreStack::push(fs, &self.ignores, &self.path, &present)
+        };
+        for dir_entry in dir_entries {
+            // Checking an entry can mean statting it, which adds up in
+            // big directories.
+            if self.ctx.is_cancelled() {
+                return Ok(());
+            }
+            let file_name = dir_entry.file_name.as_os_str();
+            let file_type = dir_entry.file_type;
+
+            if self.ctx.options.should_ignore(file_name) {
+                self.explain_entry(&dir_entry, || Decision::Ignored);
+                continue;
+            }
+            if (ignores.as_ref()).is_some_and(|ignores| {
+                ignores.is_ignored(&self.entry_path(&dir_entry), file_type.is_dir())
+            }) {
+                self.explain_entry(&dir_entry, || Decision::IgnoredByFile);
+                continue;
+            }
+
+            if let Some(pattern) = self.ctx.matcher.excluded_by(file_name) {
+                // Excluded directories are neither reported nor
+                // searched, even if we've already seen a sentinel.
+                self.ctx.explain(&self.path, || Decision::Excluded {
+                    pattern: pattern.to_string(),
+                });
+                return Ok(());
+            }
+
+            let found = dir_matcher.add(&self.path, &dir_entry);
+            tracing::trace!(
+                path = %self.entry_path(&dir_entry).display(),
+                is_sentinel = found.is_some(),
+                "Checked entry"
+            );
+            if let Some(found) = found {
+                if self.ctx.options.below_min_depth(self.depth) {
+                    // Too shallow to report, but we still want to
+                    // traverse through this directory.
+                    self.ctx.explain(&self.path, || Decision::TooShallow);
+                    continue;
+                }
+                sentinel.get_or_insert(found);
+                found_sentinel = dir_matcher.is_match();
+                if found_sentinel
+                    && !self.ctx.options.no_prune
+                    && self.ctx.options.nested.is_none()
+                    && !self.ctx.matcher.has_excludes()
+                {
+                    break;
+                }
+                // The sentinel itself is never a project container,
+                // so there's no point descending into it.
+                continue;
+            }
+
+            if !should_enqueue {
+                self.explain_entry(&dir_entry, || Decision::TooDeep);
+                continue;
+            }
+            if self.ctx.options.skips_hidden(file_name) {
+                self.explain_entry(&dir_entry, || Decision::Hidden);
+                continue;
+            }
+            if let Some(pattern) = self.ctx.matcher.pruned_by(file_name) {
+                self.explain_entry(&dir_entry, || Decision::PrunedByPattern {
+                    pattern: pattern.to_string(),
+                });
+                continue;
+            }
+
+            if !self.is_traversable(&dir_entry) {
+                if file_type.is_symlink() {
+                    self.explain_entry(&dir_entry, || Decision::Symlink);
+                }
+            } else if let Some(fs_type) = self.network_fs_type(&dir_entry) {
+                let path = self.entry_path(&dir_entry);
+                tracing::warn!(path = %path.display(), "Not searching {} mount", fs_type);
+                self.ctx.explain(&path, || Decision::NetworkFileSystem {
+                    fs_type: fs_type.to_string(),
+                });
+            } else if !self.is_on_root_device(&dir_entry) {
+                self.explain_entry(&dir_entry, || Decision::OtherFileSystem);
+            } else {
+                found_paths.push(self.entry_path(&dir_entry));
+            }
+        }
+
+        if self.ctx.is_cancelled() {
+            return Ok(());
+        }
+        // Submodules and repositories in another state are pruned like
+        // any other match, just not reported.
+        let unreported = (found_sentinel)
+            .then(|| self.ctx.options.unreported_because(fs, &self.path))
+            .flatten();
+        let is_reported = found_sentinel && unreported.is_none();
+        if let Some(decision) = unreported {
+            self.ctx.explain(&self.path, || decision);
+        }
+        if let Some(sentinel) = sentinel.filter(|_| is_reported) {
+            if !self.ctx.first_report(&self.path) {
+                self.ctx.explain(&self.path, || Decision::AlreadyReported);
+                return Ok(());
+            }
+            // Another worker may have beaten us to our root's match.
+            if self.ctx.options.first_per_root && self.root_matched.swap(true, Ordering::Relaxed) {
+                return Ok(());
+            }
+            if !self.ctx.claim_match() {
+                return Ok(());
+            }
+            self.ctx.explain(&self.path, || Decision::Matched {
+                sentinel: sentinel.clone(),
+            });
+            let (path, sentinel) = match self.ctx.options.canonical_paths {
+                true => match fs.canonicalize(&self.path) {
+                    Ok(path) => {
+                        let sentinel = match sentinel.strip_prefix(&self.path) {
+                            Ok(relative) => path.join(relative),
+                            Err(_) => sentinel,
+                        };
+                        (path, sentinel)
+                    }
+                    Err(_) => (self.path.clone(), sentinel),
+                },
+                false => (self.path.clone(), sentinel),
+            };
+            self.ctx.send_match(Match {
+                path,
+                sentinel,
+                patterns: dir_matcher.patterns(),
+                depth: self.depth,
+            });
+        }
+
+        let nested_limit = match self.ctx.options.nested {
+            Some(nested) if found_sentinel => Some(self.depth + nested),
+            _ => self.nested_limit,
+        };
+        let is_pruned = if self.ctx.options.no_prune {
+            false
+        } else if let Some(nested_limit) = nested_limit {
+            self.depth >= nested_limit
+        } else {
+            found_sentinel
+        };
+        if is_pruned {
+            self.ctx.explain(&self.path, || Decision::Pruned);
+        }
+        if !is_pruned && !self.ctx.is_cancelled() && !self.is_root_done() {
+            for found_path in found_paths {
+                self.ctx.explain(&found_path, || Decision::Descended);
+                let child = Job {
+                    nested_limit,
+                    ..self.child(found_path, ignores.clone())
+                };
+                self.ctx.queue_or_run(child);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_system::MemoryFileSystem;
+
+    fn file_system(files: &[&str]) -> MemoryFileSystem {
+        let mut file_system = MemoryFileSystem::new();
+        for file in files {
+            file_system.add_file(*file, "");
+        }
+        file_system
+    }
+
+    fn find(builder: FinderBuilder, files: &[&str]) -> Vec<PathBuf> {
+        let finder = builder.file_system(file_system(files)).build().unwrap();
+        let mut found: Vec<_> = finder.run().map(|found| found.path).collect();
+        found.sort();
+        found
+    }
+
+    fn paths(paths: &[&str]) -> Vec<PathBuf> {
+        paths.iter().map(PathBuf::from).collect()
+    }
+
+    const PROJECTS: &[&str] = &[
+        "/src/a/Cargo.toml",
+        "/src/a/vendor/b/Cargo.toml",
+        "/src/c/d/Cargo.toml",
+        "/src/c/d/e/f/Cargo.toml",
+        "/src/g/README.md",
+    ];
+
+    #[test]
+    fn prunes_beneath_matches() {
+        let found = find(FinderBuilder::new("Cargo.toml").root("/src"), PROJECTS);
+        assert_eq!(found, paths(&["/src/a", "/src/c/d"]));
+    }
+
+    #[test]
+    fn no_prune_searches_beneath_matches() {
+        let builder = FinderBuilder::new("Cargo.toml").root("/src").no_prune(true);
+        let found = find(builder, PROJECTS);
+        assert_eq!(
+            found,
+            paths(&["/src/a", "/src/a/vendor/b", "/src/c/d", "/src/c/d/e/f"])
+        );
+    }
+
+    #[test]
+    fn max_depth_limits_levels_searched() {
+        // Sentinels count as a level, so this finds projects up to two
+        // levels down.
+        let builder = (FinderBuilder::new("Cargo.toml").root("/src"))
+            .no_prune(true)
+            .max_depth(Some(3));
+        assert_eq!(find(builder, PROJECTS), paths(&["/src/a", "/src/c/d"]));
+    }
+
+    #[test]
+    fn root_max_depth_overrides_max_depth() {
+        let builder =
+            (FinderBuilder::new("Cargo.toml").root_with_max_depth("/src", 2)).max_depth(Some(5));
+        assert_eq!(find(builder, PROJECTS), paths(&["/src/a"]));
+    }
+
+    #[test]
+    fn reports_depth_below_root() {
+        let finder = (FinderBuilder::new("Cargo.toml").root("/src"))
+            .file_system(file_system(PROJECTS))
+            .build()
+            .unwrap();
+        let mut found: Vec<_> = finder
+            .run()
+            .map(|found| (found.path, found.depth))
+            .collect();
+        found.sort();
+        assert_eq!(
+            found,
+            [(PathBuf::from("/src/a"), 1), (PathBuf::from("/src/c/d"), 2)]
+        );
+    }
+
+    #[test]
+    fn nested_searches_a_few_levels_beneath_matches() {
+        let builder = FinderBuilder::new("Cargo.toml")
+            .root("/src")
+            .nested(Some(1));
+        assert_eq!(find(builder, PROJECTS), paths(&["/src/a", "/src/c/d"]));
+        let builder = FinderBuilder::new("Cargo.toml")
+            .root("/src")
+            .nested(Some(2));
+        assert_eq!(
+            find(builder, PROJECTS),
+            paths(&["/src/a", "/src/a/vendor/b", "/src/c/d", "/src/c/d/e/f"])
+        );
+    }
+
+    #[test]
+    fn merges_nested_roots() {
+        let finder = (FinderBuilder::new("Cargo.toml").roots(["/src/c", "/src"]))
+            .file_system(file_system(PROJECTS))
+            .build()
+            .unwrap();
+        assert_eq!(finder.roots(), paths(&["/src"]));
+        let merged = finder.merged_roots();
+        assert_eq!(merged.len(), 1);
+        assert_eq!(merged[0].root, Path::new("/src/c"));
+        assert_eq!(merged[0].into, Path::new("/src"));
+        let found: Vec<_> = finder.run().map(|found| found.path).collect();
+        assert_eq!(found.len(), 2);
+    }
+
+    #[test]
+    fn reports_symlinked_projects_once() {
+        let mut file_system = file_system(PROJECTS);
+        file_system.add_symlink("/src/link", "a");
+        let finder = (FinderBuilder::new("Cargo.toml").root("/src"))
+            .follow(true)
+            .file_system(file_system)
+            .build()
+            .unwrap();
+        let found: Vec<_> = finder.run().map(|found| found.path).collect();
+        assert_eq!(found.len(), 2, "{:?}", found);
+    }
+
+    #[test]
+    fn reports_invalid_roots() {
+        let finder = (FinderBuilder::new("Cargo.toml").roots(["/src", "/missing"]))
+            .file_system(file_system(PROJECTS))
+            .build()
+            .unwrap();
+        assert_eq!(finder.roots(), paths(&["/src"]));
+        assert_eq!(finder.invalid_roots().len(), 1);
+        assert_eq!(finder.invalid_roots()[0].path, Path::new("/missing"));
+    }
+
+    #[test]
+    fn first_per_root_finds_one_match_per_root() {
+        let files = [
+            "/one/a/Cargo.toml",
+            "/one/b/Cargo.toml",
+            "/one/c/Cargo.toml",
+            "/two/a/Cargo.toml",
+            "/two/b/Cargo.toml",
+            "/three/README.md",
+        ];
+        for threads in [1, 4] {
+            let builder = (FinderBuilder::new("Cargo.toml").roots(["/one", "/two", "/three"]))
+                .threads(Some(threads))
+                .first_per_root(true);
+            let found = find(builder, &files);
+            assert_eq!(found.len(), 2, "{:?}", found);
+            assert!(found[0].starts_with("/one"));
+            assert!(found[1].starts_with("/two"));
+        }
+    }
+
+    #[test]
+    fn max_results_stops_the_search() {
+        let files: Vec<String> = (0..50).map(|i| format!("/src/{}/Cargo.toml", i)).collect();
+        let files: Vec<&str> = files.iter().map(String::as_str).collect();
+        for max_results in [0, 1, 7, 50, 100] {
+            let builder = (FinderBuilder::new("Cargo.toml").root("/src"))
+                .threads(Some(4))
+                .max_results(Some(max_results));
+            assert_eq!(find(builder, &files).len(), max_results.min(50));
+        }
+    }
+
+    /// A tree `breadth` wide and `depth` deep, with a sentinel in every
+    /// leaf and in every directory named `0` above them, for searches
+    /// big enough to keep every worker busy. Returns the number of
+    /// projects in it.
+    fn big_tree(breadth: usize, depth: usize) -> (MemoryFileSystem, usize) {
+        fn add(fs: &mut MemoryFileSystem, dir: PathBuf, breadth: usize, depth: usize) -> usize {
+            let is_project = depth == 0 || dir.ends_with("0");
+            if is_project {
+                fs.add_file(dir.join("Cargo.toml"), "");
+            }
+            let mut projects = usize::from(is_project);
+            if depth > 0 {
+                for i in 0..breadth {
+                    projects += add(fs, dir.join(i.to_string()), breadth, depth - 1);
+                }
+            }
+            projects
+        }
+        let mut fs = MemoryFileSystem::new();
+        let projects = (0..breadth)
+            .map(|i| {
+                add(
+                    &mut fs,
+                    PathBuf::from(format!("/src/{}", i)),
+                    breadth,
+                    depth - 1,
+                )
+            })
+            .sum();
+        (fs, projects)
+    }
+
+    fn big_search(fs: &MemoryFileSystem, order: Order) -> FinderBuilder {
+        (FinderBuilder::new("Cargo.toml").root("/src"))
+            .no_prune(true)
+            .threads(Some(8))
+            .order(order)
+            .file_system(fs.clone())
+    }
+
+    const ORDERS: [Order; 3] = [Order::Dfs, Order::Bfs, Order::Shallowest];
+
+    #[test]
+    fn stress_finds_everything_once() {
+        let (fs, projects) = big_tree(6, 5);
+        for order in ORDERS {
+            let finder = big_search(&fs, order).build().unwrap();
+            let found: Vec<_> = finder.run().map(|found| found.path).collect();
+            let unique: HashSet<_> = found.iter().collect();
+            assert_eq!(found.len(), projects, "{:?}", order);
+            assert_eq!(unique.len(), projects, "{:?}", order);
+        }
+    }
+
+    #[test]
+    fn stress_cancel() {
+        let (fs, projects) = big_tree(6, 5);
+        for order in ORDERS {
+            let finder = big_search(&fs, order).build().unwrap();
+            for take in [0, 1, 100] {
+                let mut matches = finder.run();
+                let mut found: Vec<_> = matches.by_ref().take(take).collect();
+                matches.cancel_handle().cancel();
+                // The iterator has to end, with no more than it would
+                // have found anyway.
+                found.extend(matches);
+                let unique: HashSet<_> = found.iter().map(|found| &found.path).collect();
+                assert!(found.len() <= projects, "{:?}", order);
+                assert_eq!(unique.len(), found.len(), "{:?}", order);
+            }
+            // Cancelling one search doesn't affect the next.
+            assert_eq!(finder.run().count(), projects, "{:?}", order);
+        }
+    }
+
+    #[test]
+    fn stress_max_results() {
+        let (fs, _) = big_tree(6, 5);
+        for order in ORDERS {
+            for max_results in [1, 10, 500] {
+                let finder = (big_search(&fs, order))
+                    .max_results(Some(max_results))
+                    .build()
+                    .unwrap();
+                for _ in 0..3 {
+                    assert_eq!(finder.run().count(), max_results, "{:?}", order);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn stress_max_queue() {
+        let (fs, projects) = big_tree(6, 5);
+        for order in ORDERS {
+            for max_queue in [1, 4, 64] {
+                let finder = (big_search(&fs, order))
+                    .max_queue(Some(max_queue))
+                    .build()
+                    .unwrap();
+                let mut matches = finder.run();
+                assert_eq!(matches.by_ref().count(), projects, "{:?}", order);
+                // Workers check the queue's length without a lock, so
+                // each can overshoot it by one.
+                let max_queued = matches.stats().max_queued as usize;
+                assert!(max_queued <= max_queue + 8, "{:?}: {}", order, max_queued);
+            }
+        }
+    }
+
+    #[test]
+    fn shallowest_order_reports_shallow_matches_first() {
+        let (fs, projects) = big_tree(6, 5);
+        let finder = (big_search(&fs, Order::Shallowest))
+            .threads(Some(1))
+            .build()
+            .unwrap();
+        let depths: Vec<usize> = finder.run().map(|found| found.depth).collect();
+        assert_eq!(depths.len(), projects);
+        assert!(depths.is_sorted(), "{:?}", depths);
+    }
+}
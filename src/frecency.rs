@@ -0,0 +1,131 @@
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use anyhow::anyhow;
+use anyhow::Context as _;
+use pj::atomic_file::AtomicFile;
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::config;
+
+const HOUR: u64 = 60 * 60;
+const DAY: u64 = 24 * HOUR;
+const WEEK: u64 = 7 * DAY;
+
+/// How to order results, from `--rank`.
+#[derive(Clone, Copy, Debug)]
+pub enum Rank {
+    /// Most often and most recently visited first, see [`Frecency`].
+    Frecency,
+}
+
+impl Rank {
+    pub const NAMES: &'static [&'static str] = &["frecency"];
+}
+
+impl FromStr for Rank {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "frecency" => Ok(Rank::Frecency),
+            _ => Err(anyhow!("Invalid ranking {:?}", s)),
+        }
+    }
+}
+
+/// Which projects have been visited, how often and how recently, kept
+/// in `~/.local/share/pj/frecency.json`. Visits are recorded by `pj
+/// visit` and by choosing a project in the picker.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Frecency {
+    visits: Vec<Visits>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct Visits {
+    path: PathBuf,
+    count: u32,
+    /// In seconds since the Unix epoch.
+    last: u64,
+}
+
+impl Frecency {
+    /// Loads the database, returning an empty one if it doesn't exist.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let contents = match fs::read(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Frecency::default()),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {:?}", path)),
+        };
+        serde_json::from_slice(&contents).with_context(|| format!("Failed to parse {:?}", path))
+    }
+
+    /// Writes the database to `path`, replacing it atomically.
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).with_context(|| format!("Failed to create {:?}", parent))?;
+        }
+        let mut file = AtomicFile::create(path)?;
+        file.write_all(&serde_json::to_vec(self)?)?;
+        file.commit()
+    }
+
+    pub fn visit(&mut self, path: &Path) -> anyhow::Result<()> {
+        let path = std::path::absolute(path)?;
+        let now = now();
+        match self.visits.iter_mut().find(|visits| visits.path == path) {
+            Some(visits) => {
+                visits.count = visits.count.saturating_add(1);
+                visits.last = now;
+            }
+            None => self.visits.push(Visits {
+                path,
+                count: 1,
+                last: now,
+            }),
+        }
+        Ok(())
+    }
+
+    /// Scores `path` by how often it's been visited, weighted towards
+    /// recent visits. Projects which have never been visited score 0.
+    pub fn score(&self, path: &Path) -> f64 {
+        let Ok(path) = std::path::absolute(path) else {
+            return 0.0;
+        };
+        let Some(visits) = self.visits.iter().find(|visits| visits.path == path) else {
+            return 0.0;
+        };
+        let age = now().saturating_sub(visits.last);
+        let weight = if age < HOUR {
+            4.0
+        } else if age < DAY {
+            2.0
+        } else if age < WEEK {
+            0.5
+        } else {
+            0.25
+        };
+        f64::from(visits.count) * weight
+    }
+
+    /// The default location of the database, under the user's data
+    /// directory.
+    pub fn default_path() -> Option<PathBuf> {
+        Some(config::data_dir()?.join("pj").join("frecency.json"))
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since_epoch| since_epoch.as_secs())
+}
@@ -0,0 +1,70 @@
+use std::io;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::Command;
+use std::process::Stdio;
+use std::thread;
+
+use anyhow::anyhow;
+use pj::long_path::to_display;
+
+use crate::output::path_bytes;
+
+/// Streams `candidates` into fzf as they're found, returning the one
+/// the user picked, or None if they cancelled or nothing matched.
+pub fn pick<I>(candidates: I) -> anyhow::Result<Option<PathBuf>>
+where
+    I: IntoIterator<Item = PathBuf>,
+    I::IntoIter: Send + 'static,
+{
+    let mut child = Command::new("fzf")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => anyhow!("fzf isn't installed, or isn't on PATH"),
+            _ => e.into(),
+        })?;
+
+    // Feed fzf from another thread, so that we can return as soon as
+    // the user picks something even if the search is still running.
+    // Once fzf exits, the next write fails and the search is dropped.
+    let mut stdin = child.stdin.take().unwrap();
+    let candidates = candidates.into_iter();
+    thread::spawn(move || {
+        for candidate in candidates {
+            let written = stdin
+                .write_all(&path_bytes(&to_display(&candidate)))
+                .and_then(|()| stdin.write_all(b"\n"));
+            if written.is_err() {
+                break;
+            }
+        }
+    });
+
+    let output = child.wait_with_output()?;
+    match output.status.code() {
+        Some(0) => {}
+        // Nothing matched the query, or the user pressed Esc or Ctrl-C.
+        Some(1) | Some(130) => return Ok(None),
+        _ => return Err(anyhow!("fzf failed with {}", output.status)),
+    }
+    let mut selected = output.stdout;
+    if selected.last() == Some(&b'\n') {
+        selected.pop();
+    }
+    Ok(Some(path_from_bytes(selected)))
+}
+
+#[cfg(unix)]
+fn path_from_bytes(bytes: Vec<u8>) -> PathBuf {
+    use std::ffi::OsString;
+    use std::os::unix::ffi::OsStringExt;
+
+    PathBuf::from(OsString::from_vec(bytes))
+}
+
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: Vec<u8>) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(&bytes).into_owned())
+}
@@ -0,0 +1,167 @@
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+use std::process::Stdio;
+use std::str::FromStr;
+
+use anyhow::anyhow;
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::file_system::FileSystem;
+
+/// The state a git repository must be in to be reported, from
+/// [`FinderBuilder::git_status`](crate::FinderBuilder::git_status).
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GitStatus {
+    /// It has uncommitted changes, including untracked files.
+    Dirty,
+    /// It has no uncommitted changes.
+    Clean,
+    /// A local branch has commits which aren't on any remote.
+    Unpushed,
+}
+
+impl GitStatus {
+    pub const NAMES: &'static [&'static str] = &["dirty", "clean", "unpushed"];
+
+    /// Whether `dir` is the top of a git repository in this state. This
+    /// runs git, so it only works on the real file system.
+    pub(crate) fn matches(self, fs: &dyn FileSystem, dir: &Path) -> bool {
+        // Without this, git would look at whichever repository the
+        // directory is inside.
+        if fs.metadata(&dir.join(".git")).is_err() {
+            return false;
+        }
+        let output = match self {
+            GitStatus::Dirty | GitStatus::Clean => git(dir, &["status", "--porcelain"]),
+            GitStatus::Unpushed => git(
+                dir,
+                &["rev-list", "-n1", "--branches", "--not", "--remotes"],
+            ),
+        };
+        let Some(output) = output else {
+            return false;
+        };
+        match self {
+            GitStatus::Clean => output.is_empty(),
+            GitStatus::Dirty | GitStatus::Unpushed => !output.is_empty(),
+        }
+    }
+}
+
+impl fmt::Display for GitStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GitStatus::Dirty => write!(f, "dirty"),
+            GitStatus::Clean => write!(f, "clean"),
+            GitStatus::Unpushed => write!(f, "unpushed"),
+        }
+    }
+}
+
+impl FromStr for GitStatus {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "dirty" => Ok(GitStatus::Dirty),
+            "clean" => Ok(GitStatus::Clean),
+            "unpushed" => Ok(GitStatus::Unpushed),
+            _ => Err(anyhow!("Invalid git status {:?}", s)),
+        }
+    }
+}
+
+/// What's checked out in a git repository, and where it came from.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct GitInfo {
+    /// The current branch, unless the `HEAD` is detached.
+    pub branch: Option<String>,
+    /// The URL of the `origin` remote.
+    pub origin: Option<String>,
+}
+
+impl GitInfo {
+    /// Reads about the repository whose top is `dir`, or returns `None`
+    /// if it isn't one. This only reads a couple of small files rather
+    /// than running git, so it's cheap enough to do for every match.
+    pub fn read(dir: &Path) -> Option<Self> {
+        let git_dir = git_dir(dir)?;
+        let head = fs::read_to_string(git_dir.join("HEAD")).ok()?;
+        let branch = (head.trim_end().strip_prefix("ref: "))
+            .map(|head| head.strip_prefix("refs/heads/").unwrap_or(head).to_string());
+        // Worktrees share their config with the main repository.
+        let common_dir = match fs::read_to_string(git_dir.join("commondir")) {
+            Ok(common_dir) => git_dir.join(common_dir.trim_end()),
+            Err(_) => git_dir,
+        };
+        let origin = fs::read_to_string(common_dir.join("config"))
+            .ok()
+            .and_then(|config| origin_url(&config));
+        Some(GitInfo { branch, origin })
+    }
+}
+
+/// Finds the git directory of the repository whose top is `dir`. It's
+/// usually `.git`, but worktrees and submodules have a `.git` file
+/// pointing somewhere else.
+fn git_dir(dir: &Path) -> Option<PathBuf> {
+    let dot_git = dir.join(".git");
+    if dot_git.is_dir() {
+        return Some(dot_git);
+    }
+    let contents = fs::read_to_string(&dot_git).ok()?;
+    let git_dir = contents.trim_end().strip_prefix("gitdir: ")?;
+    Some(dir.join(git_dir))
+}
+
+/// Finds the URL of the `origin` remote in a git config file.
+fn origin_url(config: &str) -> Option<String> {
+    let mut in_origin = false;
+    for line in config.lines() {
+        let line = line.trim();
+        if line.starts_with('[') {
+            in_origin = line.replace(char::is_whitespace, "") == r#"[remote"origin"]"#;
+            continue;
+        }
+        if !in_origin {
+            continue;
+        }
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        if key.trim().eq_ignore_ascii_case("url") {
+            return Some(value.trim().trim_matches('"').to_string());
+        }
+    }
+    None
+}
+
+/// Runs git in `dir`, returning what it printed if it succeeded.
+fn git(dir: &Path, args: &[&str]) -> Option<Vec<u8>> {
+    // --no-optional-locks stops `git status` from refreshing the index,
+    // which would get in the way of anything else using the repository.
+    let output = Command::new("git")
+        .arg("--no-optional-locks")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .stdin(Stdio::null())
+        .output();
+    match output {
+        Ok(output) if output.status.success() => Some(output.stdout),
+        Ok(output) => {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            tracing::warn!(path = %dir.display(), "git failed: {}", stderr.trim_end());
+            None
+        }
+        Err(e) => {
+            tracing::warn!(path = %dir.display(), "Failed to run git: {}", e);
+            None
+        }
+    }
+}
@@ -0,0 +1,131 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use ignore::gitignore::Gitignore;
+use ignore::gitignore::GitignoreBuilder;
+
+use crate::file_system::FileSystem;
+
+/// The ignore files we honor, shared with git and tools like ripgrep and
+/// fd. Later files take precedence over earlier ones in the same
+/// directory.
+pub(crate) const IGNORE_FILES: &[&str] = &[GITIGNORE, ".ignore", ".fdignore"];
+
+/// Only honored inside a git repository, as git itself does.
+const GITIGNORE: &str = ".gitignore";
+
+/// What makes a directory the root of a git repository.
+pub(crate) const GIT_DIR: &str = ".git";
+
+/// Ignore files bigger than this are cut short, since no real one
+/// comes close.
+const MAX_IGNORE_FILE_LEN: u64 = 1024 * 1024;
+
+/// The rules from every ignore file between a root and the directory
+/// being searched. Rules in deeper directories take precedence.
+pub(crate) struct IgnoreStack {
+    parent: Option<Arc<IgnoreStack>>,
+    rules: Gitignore,
+    /// Whether this directory is in a git repository, so `.gitignore`
+    /// files apply.
+    in_git_repo: bool,
+}
+
+impl IgnoreStack {
+    /// Adds the rules from `dir`'s ignore files on top of `parent`.
+    /// `present` names the ignore files which exist in `dir`, along
+    /// with [`GIT_DIR`] if it does, since the caller has usually just
+    /// listed it.
+    pub(crate) fn push(
+        fs: &dyn FileSystem,
+        parent: &Option<Arc<IgnoreStack>>,
+        dir: &Path,
+        present: &[&str],
+    ) -> Option<Arc<IgnoreStack>> {
+        let is_repo_root = present.contains(&GIT_DIR);
+        let in_git_repo = is_repo_root || parent.as_ref().is_some_and(|parent| parent.in_git_repo);
+        let names: Vec<&str> = (IGNORE_FILES.iter().copied())
+            .filter(|name| present.contains(name))
+            .filter(|&name| in_git_repo || name != GITIGNORE)
+            .collect();
+        if names.is_empty() && !is_repo_root {
+            return parent.clone();
+        }
+        let mut builder = GitignoreBuilder::new(dir);
+        for name in names {
+            let path = dir.join(name);
+            let contents = match fs.read(&path, MAX_IGNORE_FILE_LEN) {
+                Ok(contents) => contents,
+                Err(e) => {
+                    tracing::warn!(path = %path.display(), "Failed to read ignore file: {}", e);
+                    continue;
+                }
+            };
+            // Bad lines are reported but don't stop the rest of the
+            // file from applying.
+            for line in String::from_utf8_lossy(&contents).lines() {
+                if let Err(e) = builder.add_line(Some(path.clone()), line) {
+                    tracing::warn!("{}", e);
+                }
+            }
+        }
+        match builder.build() {
+            Ok(rules) => Some(Arc::new(IgnoreStack {
+                parent: parent.clone(),
+                rules,
+                in_git_repo,
+            })),
+            Err(e) => {
+                tracing::warn!("{}", e);
+                parent.clone()
+            }
+        }
+    }
+
+    /// Builds the stack for `dir` from scratch by checking each of its
+    /// ancestors up to `depth` levels above it.
+    pub(crate) fn load(fs: &dyn FileSystem, dir: &Path, depth: usize) -> Option<Arc<IgnoreStack>> {
+        let mut dirs: Vec<&Path> = dir.ancestors().take(depth + 1).collect();
+        dirs.reverse();
+        dirs.into_iter().fold(None, |stack, dir| {
+            let present: Vec<&str> = (IGNORE_FILES.iter().copied())
+                .filter(|name| {
+                    (fs.metadata(&dir.join(name)))
+                        .is_ok_and(|metadata| !metadata.file_type.is_dir())
+                })
+                .chain(fs.metadata(&dir.join(GIT_DIR)).ok().map(|_| GIT_DIR))
+                .collect();
+            IgnoreStack::push(fs, &stack, dir, &present)
+        })
+    }
+
+    /// Whether the entry at `path` is ignored. The deepest rule which
+    /// mentions it wins, so a `!pattern` can re-include something an
+    /// ancestor ignored.
+    pub(crate) fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        let mut stack = Some(self);
+        while let Some(current) = stack {
+            let matched = current.rules.matched(path, is_dir);
+            if !matched.is_none() {
+                return matched.is_ignore();
+            }
+            stack = current.parent.as_deref();
+        }
+        false
+    }
+
+    /// Like [`IgnoreStack::is_ignored`], but also checks the directories
+    /// between `path` and the ignore files, for when we haven't
+    /// searched our way down to it.
+    pub(crate) fn is_ignored_with_parents(&self, path: &Path, is_dir: bool) -> bool {
+        let mut stack = Some(self);
+        while let Some(current) = stack {
+            let matched = current.rules.matched_path_or_any_parents(path, is_dir);
+            if !matched.is_none() {
+                return matched.is_ignore();
+            }
+            stack = current.parent.as_deref();
+        }
+        false
+    }
+}
@@ -0,0 +1,217 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use anyhow::anyhow;
+use anyhow::Context as _;
+use regex::Regex;
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::atomic_file::AtomicFile;
+use crate::finder::FinderBuilder;
+use crate::watch::WatchEvent;
+
+/// How long [`Index::watch`] waits after a change before saving, so a
+/// burst of changes, like a `git clone`, is saved once.
+const SAVE_DELAY: Duration = Duration::from_secs(1);
+
+/// Matches from a previous search saved to disk, so they can be queried
+/// without walking the tree again.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Index {
+    /// The search which produced this index, so it can be rebuilt.
+    pub params: FinderBuilder,
+    /// When the search finished, in seconds since the Unix epoch.
+    pub built_at: u64,
+    pub projects: Vec<PathBuf>,
+}
+
+/// Narrows down which projects [`Index::query`] returns.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct IndexQuery {
+    /// Only return projects whose path matches this regex.
+    pub filter: Option<String>,
+    /// Only return projects beneath this directory. Projects in the
+    /// index have absolute paths, so this should be absolute too.
+    pub root: Option<PathBuf>,
+}
+
+impl Index {
+    /// Runs the search described by `params` and indexes every match.
+    pub fn build(params: FinderBuilder) -> anyhow::Result<Self> {
+        let finder = params.clone().build()?;
+        let mut matches = finder.run();
+        let mut projects: Vec<PathBuf> = matches.by_ref().map(|found| found.path).collect();
+        if matches.timed_out() {
+            return Err(anyhow!("Timed out before the scan finished"));
+        }
+        projects.sort();
+        let built_at = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        Ok(Index {
+            params,
+            built_at,
+            projects,
+        })
+    }
+
+    /// Keeps the index, which was loaded from `path`, up to date by
+    /// watching its roots, passing each change to `on_change`. Changes
+    /// are saved to `path` a second after the first unsaved one. Fails
+    /// rather than save over an index something else wrote since, e.g.
+    /// `pj index build`. Only returns if the watcher shuts down.
+    /// Anything which changed while nothing was watching is only picked
+    /// up by the next build.
+    pub fn watch<F>(&mut self, path: &Path, mut on_change: F) -> anyhow::Result<()>
+    where
+        F: FnMut(&WatchEvent) -> anyhow::Result<()>,
+    {
+        let finder = self.params.clone().build()?;
+        let mut watch = finder.watch_changes(self.projects.clone())?;
+        let mut loaded = modified(path);
+        let mut save_at = None;
+        loop {
+            let event = match watch.next_before(save_at) {
+                Ok(event) => event,
+                Err(RecvTimeoutError::Timeout) => {
+                    loaded = self.save_over(path, loaded)?;
+                    save_at = None;
+                    continue;
+                }
+                Err(RecvTimeoutError::Disconnected) => break,
+            };
+            match &event {
+                WatchEvent::Added(found) => match self.projects.binary_search(&found.path) {
+                    Ok(_) => continue,
+                    Err(i) => self.projects.insert(i, found.path.clone()),
+                },
+                WatchEvent::Removed(removed) => match self.projects.binary_search(removed) {
+                    Ok(i) => {
+                        self.projects.remove(i);
+                    }
+                    Err(_) => continue,
+                },
+            }
+            save_at.get_or_insert_with(|| Instant::now() + SAVE_DELAY);
+            on_change(&event)?;
+        }
+        if save_at.is_some() {
+            self.save_over(path, loaded)?;
+        }
+        Ok(())
+    }
+
+    /// Saves the index to `path` if the file there was last modified at
+    /// `loaded`, returning when it's been modified now.
+    fn save_over(
+        &self,
+        path: &Path,
+        loaded: Option<SystemTime>,
+    ) -> anyhow::Result<Option<SystemTime>> {
+        if modified(path) != loaded {
+            return Err(anyhow!(
+                "{:?} was replaced while it was being watched, restart the watch to pick it up",
+                path
+            ));
+        }
+        self.save(path)?;
+        Ok(modified(path))
+    }
+
+    /// Finds the indexed projects which match `query`.
+    pub fn query(&self, query: &IndexQuery) -> anyhow::Result<Vec<&PathBuf>> {
+        let filter = query.filter.as_deref().map(Regex::new).transpose()?;
+        Ok(self
+            .projects
+            .iter()
+            .filter(|project| match &query.root {
+                Some(root) => project.starts_with(root),
+                None => true,
+            })
+            .filter(|project| match &filter {
+                Some(filter) => filter.is_match(&project.to_string_lossy()),
+                None => true,
+            })
+            .collect())
+    }
+
+    /// The default location of the index, under the user's cache
+    /// directory.
+    pub fn default_path() -> Option<PathBuf> {
+        let cache_dir = match env::var_os("XDG_CACHE_HOME") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => PathBuf::from(env::var_os("HOME")?).join(".cache"),
+        };
+        Some(cache_dir.join("pj").join("index.json"))
+    }
+
+    /// Loads an index, returning None if it hasn't been built yet.
+    pub fn load(path: &Path) -> anyhow::Result<Option<Self>> {
+        let contents = match fs::read(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {:?}", path)),
+        };
+        let index = serde_json::from_slice(&contents)
+            .with_context(|| format!("Failed to parse {:?}", path))?;
+        Ok(Some(index))
+    }
+
+    /// Writes the index to `path`. The index is written to a temporary
+    /// file first, so concurrent readers never see a partial index.
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).with_context(|| format!("Failed to create {:?}", parent))?;
+        }
+        let mut file = AtomicFile::create(path)?;
+        serde_json::to_writer(&mut file, self)?;
+        file.commit()
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).ok()?.modified().ok()
+}
+
+/// Keeps the index in memory, reloading it when the file on disk is
+/// replaced by `pj index build` or `pj index watch`.
+pub(crate) struct IndexCache {
+    path: PathBuf,
+    loaded: Mutex<Option<(SystemTime, Arc<Index>)>>,
+}
+
+impl IndexCache {
+    pub(crate) fn new(path: &Path) -> Self {
+        IndexCache {
+            path: path.to_path_buf(),
+            loaded: Mutex::new(None),
+        }
+    }
+
+    pub(crate) fn get(&self) -> anyhow::Result<Arc<Index>> {
+        let modified = fs::metadata(&self.path)
+            .and_then(|metadata| metadata.modified())
+            .with_context(|| format!("No index found at {:?}", self.path))?;
+
+        let mut loaded = self.loaded.lock().unwrap();
+        if let Some((loaded_modified, index)) = loaded.as_ref() {
+            if *loaded_modified == modified {
+                return Ok(index.clone());
+            }
+        }
+        let index =
+            Index::load(&self.path)?.ok_or_else(|| anyhow!("No index found at {:?}", self.path))?;
+        let index = Arc::new(index);
+        *loaded = Some((modified, index.clone()));
+        Ok(index)
+    }
+}
@@ -0,0 +1,55 @@
+//! A fast sentinel file finder.
+//!
+//! pj searches directory trees in parallel for directories which
+//! contain an entry matching a "sentinel" pattern, like `.git` or
+//! `Cargo.toml`. See [`FinderBuilder`] to get started.
+//!
+//! With the `async` feature, `Finder::run_async` returns the matches as
+//! a `Stream` instead, for use from async code.
+
+mod adaptive;
+#[cfg(feature = "archives")]
+mod archive;
+#[cfg(feature = "async")]
+mod async_matches;
+pub mod atomic_file;
+pub mod bench;
+#[cfg(unix)]
+pub mod daemon;
+mod disk_usage;
+mod explain;
+pub mod file_system;
+mod finder;
+mod git;
+mod ignore_file;
+mod index;
+pub mod long_path;
+mod matcher;
+mod network_fs;
+mod open_files;
+mod project_type;
+pub mod server;
+mod stats;
+mod watch;
+
+#[cfg(feature = "async")]
+pub use async_matches::AsyncMatches;
+pub use explain::Decision;
+pub use finder::CancelHandle;
+pub use finder::Finder;
+pub use finder::FinderBuilder;
+pub use finder::Match;
+pub use finder::Matches;
+pub use finder::MergedRoot;
+pub use finder::Order;
+pub use finder::Skipped;
+pub use finder::StatsHandle;
+pub use git::GitInfo;
+pub use git::GitStatus;
+pub use index::Index;
+pub use index::IndexQuery;
+pub use matcher::SentinelKind;
+pub use project_type::ProjectType;
+pub use stats::Stats;
+pub use watch::Watch;
+pub use watch::WatchEvent;
@@ -0,0 +1,71 @@
+use std::io;
+use std::io::IsTerminal;
+use std::str::FromStr;
+
+use anyhow::anyhow;
+use tracing::Level;
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::fmt;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::Layer;
+
+/// The target of events which sum up a search, like its stats. With
+/// `--log-format json` these are logged whatever the verbosity, since
+/// they stand in for what's printed as text otherwise.
+pub const SUMMARY: &str = "pj::summary";
+
+/// How to log to stderr, from `--log-format`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum LogFormat {
+    Text,
+    /// One JSON object per line, for other programs to read.
+    Json,
+}
+
+impl LogFormat {
+    pub const NAMES: &'static [&'static str] = &["text", "json"];
+}
+
+impl FromStr for LogFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(anyhow!("Invalid log format {:?}", s)),
+        }
+    }
+}
+
+/// Logs to stderr, with more detail for each `-v`.
+pub fn init(verbose: u8, format: LogFormat) {
+    let level = match verbose {
+        0 => Level::ERROR,
+        1 => Level::WARN,
+        2 => Level::DEBUG,
+        _ => Level::TRACE,
+    };
+    let filter = Targets::new()
+        .with_target(SUMMARY, Level::INFO)
+        .with_default(level);
+    let layer = fmt::layer().with_writer(io::stderr);
+    match format {
+        LogFormat::Text => {
+            let layer = layer
+                .with_ansi(io::stderr().is_terminal())
+                .with_target(false)
+                .without_time();
+            tracing_subscriber::registry()
+                .with(layer.with_filter(filter))
+                .init();
+        }
+        LogFormat::Json => {
+            let layer = layer.json().with_current_span(false);
+            tracing_subscriber::registry()
+                .with(layer.with_filter(filter))
+                .init();
+        }
+    }
+}
@@ -0,0 +1,99 @@
+//! Windows paths are limited to `MAX_PATH` (260) characters unless
+//! they're in the `\\?\` "verbatim" form, which deep trees like
+//! `node_modules` easily exceed. We search from verbatim roots, so
+//! every path below them works, and only show the prefix when a path
+//! needs it. Elsewhere these are no-ops.
+
+use std::borrow::Cow;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// Paths this long or longer only work in the verbatim form.
+#[cfg(windows)]
+const MAX_PATH: usize = 260;
+
+/// Converts a root to the form we search from. On Windows that's an
+/// absolute verbatim path, and a bare drive like `D:` means the root
+/// of that drive rather than its current directory. Shares like
+/// `\\server\share` become `\\?\UNC\server\share`.
+#[cfg(windows)]
+pub fn to_search_root(root: &Path) -> PathBuf {
+    use std::path::Component;
+    use std::path::Prefix;
+
+    // Verbatim paths are taken literally, so a `/` or `..` in one
+    // wouldn't work. Roots which are already verbatim are tidied up
+    // like any other path, then made verbatim again.
+    let root = match root.to_str().and_then(strip_verbatim) {
+        Some(stripped) => PathBuf::from(stripped),
+        None => root.to_path_buf(),
+    };
+    let mut components = root.components();
+    let root = match (components.next(), components.next()) {
+        (Some(Component::Prefix(prefix)), None) if matches!(prefix.kind(), Prefix::Disk(_)) => {
+            root.join(r"\")
+        }
+        _ => root,
+    };
+    // absolute() also turns `/` into `\` and resolves `..`, neither of
+    // which happen for verbatim paths.
+    let Ok(absolute) = std::path::absolute(&root) else {
+        return root;
+    };
+    let Some(Component::Prefix(prefix)) = absolute.components().next() else {
+        return absolute;
+    };
+    let Some(path) = absolute.to_str() else {
+        return absolute;
+    };
+    match prefix.kind() {
+        Prefix::Disk(_) => PathBuf::from(format!(r"\\?\{}", path)),
+        Prefix::UNC(..) => PathBuf::from(format!(r"\\?\UNC\{}", &path[2..])),
+        _ => absolute,
+    }
+}
+
+#[cfg(not(windows))]
+pub fn to_search_root(root: &Path) -> PathBuf {
+    root.to_path_buf()
+}
+
+/// Converts a path we found into the form to show the user: without
+/// the verbatim prefix if it's short enough to work without it, and
+/// with consistent separators. Other verbatim paths, like
+/// `\\?\Volume{...}\`, have no other form, so they're shown as they
+/// are.
+#[cfg(windows)]
+pub fn to_display(path: &Path) -> Cow<'_, Path> {
+    let Some(text) = path.to_str() else {
+        return Cow::Borrowed(path);
+    };
+    let text = text.replace('/', r"\");
+    match strip_verbatim(&text) {
+        Some(short) if short.len() < MAX_PATH => Cow::Owned(PathBuf::from(short)),
+        _ => Cow::Owned(PathBuf::from(text)),
+    }
+}
+
+/// Converts a verbatim path to a drive or share to the usual form,
+/// e.g. `\\?\C:\src` to `C:\src` and `\\?\UNC\server\share` to
+/// `\\server\share`, or returns None for any other path.
+#[cfg(windows)]
+fn strip_verbatim(path: &str) -> Option<String> {
+    use std::path::Component;
+    use std::path::Prefix;
+
+    let Some(Component::Prefix(prefix)) = Path::new(path).components().next() else {
+        return None;
+    };
+    match prefix.kind() {
+        Prefix::VerbatimDisk(_) => Some(path[r"\\?\".len()..].to_string()),
+        Prefix::VerbatimUNC(..) => Some(format!(r"\\{}", &path[r"\\?\UNC\".len()..])),
+        _ => None,
+    }
+}
+
+#[cfg(not(windows))]
+pub fn to_display(path: &Path) -> Cow<'_, Path> {
+    Cow::Borrowed(path)
+}
@@ -1,183 +1,1441 @@
-use std::fs;
+use std::cmp::Reverse;
+use std::collections::HashSet;
+use std::io;
+use std::num::NonZeroUsize;
+use std::path;
+use std::path::Path;
 use std::path::PathBuf;
+use std::process;
+use std::str::FromStr;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
+use std::time::Duration;
+use std::time::SystemTime;
 
 use anyhow::anyhow;
-use crossbeam::sync::WaitGroup;
-use rayon::ThreadPool;
-use rayon::ThreadPoolBuilder;
-use regex::Regex;
-use structopt::StructOpt;
+use clap::builder::PossibleValuesParser;
+use clap::builder::TypedValueParser;
+use clap::ArgAction;
+use clap::ArgGroup;
+use clap::Args;
+use clap::Parser;
+use clap::Subcommand;
+use pj::bench::TreeSpec;
+use pj::file_system::FileSystem;
+use pj::long_path::to_display;
+use pj::long_path::to_search_root;
+use pj::Finder;
+use pj::FinderBuilder;
+use pj::GitStatus;
+use pj::Index;
+use pj::IndexQuery;
+use pj::Match;
+use pj::Matches;
+use pj::Order;
+use pj::ProjectType;
+use pj::SentinelKind;
+use pj::Skipped;
+use pj::Stats;
+use pj::WatchEvent;
 
-// TODO: add the option to ignore certain directories like
-// - node_modules
-// - venv
-// - go (for your $GOPATH)
+use crate::bookmark::Bookmarks;
+use crate::config::Config;
+use crate::config::RootSpec;
+use crate::frecency::Frecency;
+use crate::frecency::Rank;
+use crate::log::LogFormat;
+use crate::output::ColorChoice;
+use crate::output::Format;
+use crate::output::Printer;
+use crate::output::Sort;
+use crate::preset::Preset;
+use crate::progress::Progress;
+use crate::remote::Remote;
+use crate::remote::RemoteSearch;
+use crate::shell::Shell;
+
+mod bookmark;
+mod config;
+mod duration;
+mod exec;
+mod frecency;
+mod fzf;
+mod log;
+mod output;
+mod pick;
+mod preset;
+mod progress;
+mod remote;
+#[cfg(unix)]
+mod sftp;
+mod shell;
+
+/// The exit status when `--timeout` stopped the search early, the same
+/// as timeout(1) uses.
+const TIMED_OUT: i32 = 124;
+
+/// The exit status after Ctrl-C, as the shell would report it.
+const INTERRUPTED: i32 = 130;
 
 fn main() -> anyhow::Result<()> {
-    let args = Opt::from_args();
-    let wait_group = WaitGroup::new();
-
-    let ctx = Arc::new(Context {
-	pool: ThreadPoolBuilder::new().build()?,
-	max_depth: args.depth,
-	sentinel: args.make_sentinel_regex()?,
-	ignore: args.ignore,
-    });
+    let args = Opt::parse();
+    log::init(args.global.verbose, args.global.log_format);
+    let log_format = args.global.log_format;
+    let result = run(args);
+    if let (Err(e), LogFormat::Json) = (&result, log_format) {
+        tracing::error!(target: log::SUMMARY, "{:#}", e);
+        process::exit(1);
+    }
+    result
+}
 
-    for root_dir in args.root_dirs.into_iter() {
-        let work_item = Job {
-	    ctx: ctx.clone(),
-	    wait_group: wait_group.clone(),
-            // TODO: resolve symlinks for original directories(?)
-            // I'm not sure if this is needed, because read_dir()
-            // might just work through symlinks :)
-            path: root_dir,
-            depth: 0,
-        };
-        ctx.pool.spawn(move || work_item.job());
+fn run(args: Opt) -> anyhow::Result<()> {
+    let config = Config::load()?;
+    let mut global = args.global;
+    global.format = global.format.or(config.format);
+    match args.command.unwrap_or(Command::Find(args.find)) {
+        Command::Find(mut args) if args.interactive || args.fzf => {
+            args.find.apply_config(config)?;
+            args.find.default_to_current_dir();
+            pick(args.find, args.fzf, args.rank)
+        }
+        Command::Find(mut args) => {
+            args.find.apply_config(config)?;
+            // `--remote` on its own only searches the remotes.
+            if args.remotes.is_empty() {
+                args.find.default_to_current_dir();
+            }
+            find(&global, args)
+        }
+        Command::Index(IndexCommand::Build { mut find }) => {
+            find.apply_config(config)?;
+            find.default_to_current_dir();
+            index_build(find)
+        }
+        Command::Index(IndexCommand::Watch) => {
+            let printer = Printer::new(
+                global.color,
+                global.format.unwrap_or_default(),
+                global.output.as_deref(),
+            )?;
+            index_watch(printer)
+        }
+        Command::Index(IndexCommand::Query { filter, root }) => {
+            let printer = Printer::new(
+                global.color,
+                global.format.unwrap_or_default(),
+                global.output.as_deref(),
+            )?;
+            index_query(printer, filter, root)
+        }
+        #[cfg(unix)]
+        Command::Daemon => pj::daemon::serve(&socket_path()?, &index_path()?),
+        Command::Serve { listen, timeout } => pj::server::serve(&listen, &index_path()?, timeout),
+        Command::RemoteSearch => remote::serve(),
+        Command::Bench(BenchCommand::Generate {
+            dir,
+            breadth,
+            depth,
+            files,
+            density,
+            sentinel,
+            seed,
+        }) => {
+            let spec = TreeSpec {
+                breadth,
+                depth,
+                files,
+                sentinel_density: density,
+                sentinel_name: sentinel,
+                seed,
+            };
+            let stats = pj::bench::generate(&dir, &spec)?;
+            eprintln!(
+                "Generated {} directories, {} with sentinels",
+                stats.dirs, stats.sentinels
+            );
+            Ok(())
+        }
+        Command::Presets => {
+            print_presets();
+            Ok(())
+        }
+        Command::ShellInit { shell, name } => {
+            print!("{}", shell::init_script(shell, &name)?);
+            Ok(())
+        }
+        Command::Pick {
+            mut find,
+            fzf,
+            rank,
+        } => {
+            find.apply_config(config)?;
+            find.default_to_current_dir();
+            pick(find, fzf, rank)
+        }
+        Command::Exec {
+            mut find,
+            jobs,
+            command,
+        } => {
+            find.apply_config(config)?;
+            find.default_to_current_dir();
+            let finder = build_finder(find.make_builder()?)?;
+            let succeeded = match jobs {
+                Some(jobs) => exec::run_parallel(&finder, &command, jobs.get(), global.log_format)?,
+                None => exec::run(&finder, &command, global.log_format)?,
+            };
+            if !succeeded {
+                process::exit(1);
+            }
+            Ok(())
+        }
+        Command::Bookmark(command) => {
+            let path = bookmarks_path()?;
+            let mut bookmarks = Bookmarks::load(&path)?;
+            match command {
+                BookmarkCommand::Add { name, dir } => bookmarks.add(name, &dir)?,
+                BookmarkCommand::Remove { name } => bookmarks.remove(&name)?,
+                BookmarkCommand::List => {
+                    for (name, dir) in bookmarks.iter() {
+                        println!("{:16} {}", name, dir.display());
+                    }
+                    return Ok(());
+                }
+            }
+            bookmarks.save(&path)
+        }
+        Command::Visit { dir } => {
+            let path = frecency_path()?;
+            let mut frecency = Frecency::load(&path)?;
+            frecency.visit(&dir)?;
+            frecency.save(&path)
+        }
     }
+}
 
-    wait_group.wait();
-    Ok(())
+fn find(global: &GlobalArgs, mut args: FindCommand) -> anyhow::Result<()> {
+    let mut printer = Printer::new(
+        global.color,
+        global.format.unwrap_or_default(),
+        global.output.as_deref(),
+    )?;
+    printer.set_git_info(args.git_info);
+    let roots = args.find.root_paths();
+    let strict = args.find.strict;
+    let timeout = args.find.timeout;
+    // Which matches are found first depends on timing, so the limit
+    // has to wait until they've all been found and sorted.
+    let max_results =
+        if (args.deterministic || args.rank.is_some() || args.sort.is_some()) && !args.count {
+            args.find.max_results.take()
+        } else {
+            None
+        };
+    // The progress line would get in the way of the explanations.
+    let no_progress = args.no_progress || args.find.explain || global.verbose > 0;
+    let remote_searches = (args.remotes.iter())
+        .map(|remote| {
+            let mut find = args.find.clone();
+            find.root_dirs = vec![remote.path.clone()];
+            find.roots.clear();
+            Ok((remote.clone(), find.make_builder()?))
+        })
+        .collect::<anyhow::Result<_>>()?;
+    let builder = args.find.make_builder()?;
+    let finder = build_finder(builder.sort_entries(args.deterministic))?;
+    printer.set_file_system(finder.file_system());
+    let mut timed_out = false;
+    if args.up {
+        let mut found_any = false;
+        for root in &roots {
+            let Some(found) = finder.find_up(root)? else {
+                continue;
+            };
+            found_any = true;
+            if !args.quiet {
+                printer.found(&found)?;
+            }
+        }
+        printer.finish()?;
+        if !found_any {
+            process::exit(1);
+        }
+        return Ok(());
+    } else if args.quiet {
+        // Dropping the matches cancels whatever is still being searched.
+        let mut matches = finder.run();
+        let found = matches.next().is_some();
+        process::exit(match found {
+            true => 0,
+            false if matches.timed_out() => TIMED_OUT,
+            false => 1,
+        });
+    } else if args.watch || args.watch_removals {
+        for event in finder.watch()? {
+            match event {
+                WatchEvent::Added(found) if args.watch_removals => printer.added(&found)?,
+                WatchEvent::Added(found) => printer.found(&found)?,
+                WatchEvent::Removed(path) if args.watch_removals => printer.removed(&path)?,
+                WatchEvent::Removed(_) => {}
+            }
+        }
+    } else {
+        let mut matches = finder.run();
+        let mut remote_matches = RemoteSearch::start(remote_searches, timeout);
+        let file_system = remote_matches.file_system(finder.file_system());
+        printer.set_file_system(file_system.clone());
+        let search = matches.cancel_handle();
+        let remote_search = remote_matches.cancel_handle();
+        let interrupted = on_interrupt(move || {
+            search.cancel();
+            remote_search.cancel();
+        })?;
+        let progress = if no_progress {
+            None
+        } else {
+            Progress::start(matches.stats_handle())
+        };
+        printer.set_progress(progress.as_ref().map(Progress::line));
+        printer.set_lists_results(!args.count);
+        let mut collected = Vec::new();
+        let counts = if args.count {
+            Some(count(&mut matches, &roots, args.per_root))
+        } else if args.group_by_root
+            || args.deterministic
+            || args.rank.is_some()
+            || args.sort.is_some()
+        {
+            // Each root's matches are printed together, or in order,
+            // so nothing can be printed until they've all been found.
+            collected.extend(&mut matches);
+            collected.extend(&mut remote_matches);
+            None
+        } else {
+            for found in (&mut matches).chain(&mut remote_matches) {
+                if args.du {
+                    printer.found_with_size(&found, disk_usage(&finder, &found.path))?;
+                } else {
+                    printer.found(&found)?;
+                }
+            }
+            None
+        };
+        drop(progress);
+        if let Some(counts) = counts {
+            printer.text(&counts)?;
+        }
+        if args.deterministic {
+            collected.sort_by(|a, b| a.path.cmp(&b.path));
+        }
+        if let Some(rank) = args.rank {
+            sort_by_rank(&mut collected, rank, |found| &found.path)?;
+        }
+        match args.sort {
+            Some(Sort::Path) => collected.sort_by(|a, b| a.path.cmp(&b.path)),
+            // Anything whose time can't be read goes last.
+            Some(Sort::Mtime) => {
+                collected.sort_by_cached_key(|found| Reverse(modified(&*file_system, found)))
+            }
+            Some(Sort::Size) | None => {}
+        }
+        // Sizes aren't known until the matches are measured below.
+        if args.sort != Some(Sort::Size) {
+            collected.truncate(max_results.unwrap_or(usize::MAX));
+        }
+        if args.group_by_root {
+            print_grouped(&printer, finder.roots(), collected)?;
+        } else if args.du {
+            let mut sized: Vec<_> = (collected.into_iter())
+                .map(|found| {
+                    let size = disk_usage(&finder, &found.path);
+                    (found, size)
+                })
+                .collect();
+            if args.sort == Some(Sort::Size) {
+                // Anything which couldn't be sized goes last.
+                sized.sort_by(|(_, a), (_, b)| b.cmp(a));
+                sized.truncate(max_results.unwrap_or(usize::MAX));
+            }
+            for (found, size) in &sized {
+                printer.found_with_size(found, *size)?;
+            }
+        } else {
+            for found in &collected {
+                printer.found(found)?;
+            }
+        }
+        let log_format = global.log_format;
+        report_skipped(
+            matches.take_skipped(),
+            strict,
+            global.verbose > 0,
+            log_format,
+        )?;
+        let remote_timed_out = remote_matches.timed_out();
+        report_remote_failures(remote_matches.finish(), strict, log_format)?;
+        if args.stats {
+            print_stats(&matches.stats(), log_format);
+        }
+        if interrupted.load(Ordering::Relaxed) {
+            let stats = matches.stats();
+            match log_format {
+                LogFormat::Text => eprintln!(
+                    "Interrupted after scanning {} directories and finding {} matches",
+                    stats.dirs_scanned, stats.matches
+                ),
+                LogFormat::Json => tracing::warn!(
+                    target: log::SUMMARY,
+                    dirs_scanned = stats.dirs_scanned,
+                    matches = stats.matches,
+                    "Interrupted"
+                ),
+            }
+            // The --output file is only replaced by a complete search,
+            // and exiting skips the destructor which removes the
+            // temporary one.
+            drop(printer);
+            process::exit(INTERRUPTED);
+        }
+        timed_out = matches.timed_out() || remote_timed_out;
+    }
+    if timed_out {
+        // Like an interrupted search, a search which timed out doesn't
+        // replace the --output file.
+        drop(printer);
+        match global.log_format {
+            LogFormat::Text => {
+                eprintln!("Timed out before the search finished, so some matches may be missing")
+            }
+            LogFormat::Json => tracing::warn!(target: log::SUMMARY, "Timed out"),
+        }
+        process::exit(TIMED_OUT);
+    }
+    printer.finish()
 }
 
-struct Context {
-    pool: ThreadPool,
-    max_depth: Option<usize>,
-    sentinel: Regex,
-    ignore: Vec<String>,
+/// Stops the search on Ctrl-C, so that whatever was found still gets
+/// printed in full. A second Ctrl-C exits straight away. Returns
+/// whether Ctrl-C has been pressed.
+fn on_interrupt(cancel: impl Fn() + Send + 'static) -> anyhow::Result<Arc<AtomicBool>> {
+    let interrupted = Arc::new(AtomicBool::new(false));
+    let handler_interrupted = interrupted.clone();
+    ctrlc::set_handler(move || {
+        if handler_interrupted.swap(true, Ordering::Relaxed) {
+            process::exit(INTERRUPTED);
+        }
+        cancel();
+    })?;
+    Ok(interrupted)
 }
 
-impl Context {
-    fn is_match(&self, file_name: &str) -> bool {
-	self.sentinel.is_match(file_name)
+/// Builds the finder, warning about any roots which can't be searched
+/// or are already covered by another.
+fn build_finder(builder: FinderBuilder) -> anyhow::Result<Finder> {
+    let finder = builder.build()?;
+    for invalid in finder.invalid_roots() {
+        tracing::warn!(
+            target: log::SUMMARY,
+            "Can't search {}: {:#}",
+            to_display(&invalid.path).display(),
+            invalid.error
+        );
     }
+    for merged in finder.merged_roots() {
+        tracing::warn!(
+            target: log::SUMMARY,
+            "{} is already searched as part of {}",
+            to_display(&merged.root).display(),
+            to_display(&merged.into).display()
+        );
+    }
+    Ok(finder)
+}
 
-    fn exceeds_max_depth(&self, depth: usize) -> bool {
-	if let Some(max_depth) = self.max_depth {
-	    depth >= max_depth
-	} else {
-	    false
-	}
+/// Counts the matches, formatted for printing once the search is done.
+fn count(matches: &mut Matches, roots: &[PathBuf], per_root: bool) -> String {
+    if !per_root {
+        return format!("{}\n", matches.count());
     }
 
-    fn should_ignore(&self, file_name: &str) -> bool {
-	for candidate in self.ignore.iter() {
-	    if candidate == file_name {
-		return true;
-	    }
-	}
-	false
+    let roots: Vec<PathBuf> = roots.iter().map(|root| to_search_root(root)).collect();
+    let mut counts = vec![0; roots.len()];
+    for found in matches {
+        // Roots can be nested, in which case the innermost one is
+        // the root the match was found beneath.
+        let root = (0..roots.len())
+            .filter(|&i| found.path.starts_with(&roots[i]))
+            .max_by_key(|&i| roots[i].components().count());
+        if let Some(root) = root {
+            counts[root] += 1;
+        }
     }
+    roots
+        .iter()
+        .zip(counts)
+        .map(|(root, count)| format!("{}\t{}\n", count, to_display(root).display()))
+        .collect()
 }
 
-struct Job {
-    ctx: Arc<Context>,
-    wait_group: WaitGroup,
-    path: PathBuf,
-    depth: usize,
+/// Adds up the size of everything beneath `dir` for `--du`, or returns
+/// `None` if it can't be read.
+fn disk_usage(finder: &Finder, dir: &Path) -> Option<u64> {
+    (finder.disk_usage(dir))
+        .inspect_err(|e| tracing::warn!(path = %dir.display(), "Can't add up the size: {}", e))
+        .ok()
 }
 
-impl Job {
-    fn child(&self, new_path: PathBuf) -> Self {
-        Job {
-	    ctx: self.ctx.clone(),
-	    wait_group: self.wait_group.clone(),
-            path: new_path,
-            depth: self.depth + 1,
+/// When a match was last modified for `--sort mtime`: its sentinel's
+/// modification time, as `--format long` shows, or the directory's if
+/// the sentinel can't be read.
+fn modified(file_system: &dyn FileSystem, found: &Match) -> Option<SystemTime> {
+    [&found.sentinel, &found.path]
+        .into_iter()
+        .find_map(|path| file_system.metadata(path).ok()?.modified)
+}
+
+/// Prints the matches beneath each root together, in the order the
+/// roots were given.
+fn print_grouped(printer: &Printer, roots: &[PathBuf], matches: Vec<Match>) -> io::Result<()> {
+    let mut groups = vec![Vec::new(); roots.len()];
+    for found in matches {
+        let root = found.path.ancestors().nth(found.depth);
+        if let Some(i) = roots.iter().position(|r| Some(r.as_path()) == root) {
+            groups[i].push(found);
         }
     }
+    let groups = roots
+        .iter()
+        .zip(groups)
+        .filter(|(_, group)| !group.is_empty());
+    for (i, (root, group)) in groups.enumerate() {
+        printer.group(root, &group, i == 0)?;
+    }
+    Ok(())
+}
 
-    fn job(self) {
-        match self.job_impl() {
-            Err(e) => eprintln!("{:?}", e),
-            Ok(_) => {}
+/// Explains what the search couldn't read. Each error is only printed
+/// with --verbose, since permission errors are routine under `~`.
+fn report_skipped(
+    skipped: Vec<Skipped>,
+    strict: bool,
+    verbose: bool,
+    log_format: LogFormat,
+) -> anyhow::Result<()> {
+    if strict {
+        return match skipped.into_iter().next() {
+            Some(first) => Err(first
+                .error
+                .context(format!("Failed to search {}", first.path.display()))),
+            None => Ok(()),
+        };
+    }
+    if skipped.is_empty() {
+        return Ok(());
+    }
+    let (mut denied, others): (Vec<_>, Vec<_>) =
+        (skipped.iter()).partition(|skipped| skipped.is_permission_denied());
+    denied.sort_by(|a, b| a.path.cmp(&b.path));
+    match log_format {
+        LogFormat::Json => tracing::warn!(
+            target: log::SUMMARY,
+            skipped = skipped.len(),
+            permission_denied = denied.len(),
+            "Skipped unreadable directories"
+        ),
+        // Everything has already been logged as it happened, but the
+        // directories we weren't allowed into are easy to lose among
+        // the rest, and the easiest to do something about.
+        LogFormat::Text if verbose => {
+            if !denied.is_empty() {
+                eprintln!(
+                    "Skipped {} {} without permission to read {}:",
+                    denied.len(),
+                    directories(denied.len()),
+                    if denied.len() == 1 { "it" } else { "them" }
+                );
+                for skipped in denied {
+                    eprintln!("  {}", to_display(&skipped.path).display());
+                }
+            }
+        }
+        LogFormat::Text => {
+            let mut counts = Vec::new();
+            if !denied.is_empty() {
+                counts.push(format!(
+                    "{} {} without permission to read {}",
+                    denied.len(),
+                    directories(denied.len()),
+                    if denied.len() == 1 { "it" } else { "them" }
+                ));
+            }
+            if !others.is_empty() {
+                counts.push(format!(
+                    "{} {}unreadable {}",
+                    others.len(),
+                    if denied.is_empty() { "" } else { "other " },
+                    directories(others.len())
+                ));
+            }
+            eprintln!(
+                "Skipped {}, rerun with -v for details",
+                counts.join(" and ")
+            );
         }
-	drop(self.wait_group);
     }
+    Ok(())
+}
 
-    fn job_impl(&self) -> anyhow::Result<()> {
-	let should_enqueue = !self.ctx.exceeds_max_depth(self.depth + 1);
-
-        let mut found_paths = Vec::new();
-        let mut found_sentinel = false;
-        for dir_entry in self.path.read_dir()?.filter_map(Result::ok) {
-            let file_name = dir_entry.file_name();
-            let file_name = file_name
-                .to_str()
-                .ok_or_else(|| anyhow!("Cannot convert file_name {:?} to str", file_name))?;
+fn directories(count: usize) -> &'static str {
+    if count == 1 {
+        "directory"
+    } else {
+        "directories"
+    }
+}
 
-	    if self.ctx.should_ignore(file_name) {
-		continue
-	    }
+/// Explains which `--remote` searches failed, or with `--strict` fails
+/// with the first of them.
+fn report_remote_failures(
+    failures: Vec<(Remote, anyhow::Error)>,
+    strict: bool,
+    log_format: LogFormat,
+) -> anyhow::Result<()> {
+    for (remote, error) in failures {
+        if strict {
+            return Err(error.context(format!("Failed to search {}", remote)));
+        }
+        match log_format {
+            LogFormat::Text => eprintln!("Failed to search {}: {:#}", remote, error),
+            LogFormat::Json => tracing::error!(
+                target: log::SUMMARY,
+                remote = %remote,
+                "Failed to search: {:#}",
+                error
+            ),
+        }
+    }
+    Ok(())
+}
 
-            if self.ctx.is_match(file_name) {
-                println!(
-                    "{}",
-                    self.path
-                        .to_str()
-                        .ok_or_else(|| anyhow!("Cannot convert path {:?} to str", self.path))?
-                );
-                found_sentinel = true;
-                break;
-            }
+/// Prints `--stats` to stderr, so they don't mix with the results.
+fn print_stats(stats: &Stats, log_format: LogFormat) {
+    if log_format == LogFormat::Json {
+        tracing::info!(
+            target: log::SUMMARY,
+            dirs_scanned = stats.dirs_scanned,
+            entries_examined = stats.entries_examined,
+            errors = stats.errors,
+            matches = stats.matches,
+            wall_time_secs = stats.wall_time.as_secs_f64(),
+            cpu_time_secs = stats.cpu_time.as_secs_f64(),
+            max_queued = stats.max_queued,
+            queue_waits = stats.queue_waits,
+            queue_wait_secs = stats.queue_wait_time.as_secs_f64(),
+            "Stats"
+        );
+        return;
+    }
+    eprintln!("Directories scanned: {}", stats.dirs_scanned);
+    eprintln!("Entries examined:    {}", stats.entries_examined);
+    eprintln!("Errors skipped:      {}", stats.errors);
+    eprintln!("Matches found:       {}", stats.matches);
+    eprintln!("Wall time:           {:.2?}", stats.wall_time);
+    eprintln!("CPU time:            {:.2?}", stats.cpu_time);
+    eprintln!("Peak queue length:   {}", stats.max_queued);
+    eprintln!("Jobs queued:         {}", stats.queue_waits);
+    eprintln!("Queue wait time:     {:.2?}", stats.queue_wait_time);
+}
 
-	    if !should_enqueue {
-		continue;
-	    }
+fn pick(find: FindArgs, fzf: bool, rank: Option<Rank>) -> anyhow::Result<()> {
+    let bookmarks = Bookmarks::load(&bookmarks_path()?)?;
+    let bookmarked: Vec<PathBuf> = bookmarks.iter().map(|(_, dir)| dir.to_path_buf()).collect();
+    let matches = build_finder(find.make_builder()?)?.run();
+    // Bookmarks come first, so anything the search finds which is
+    // already bookmarked would just be listed twice.
+    let skip: HashSet<PathBuf> = bookmarked.iter().cloned().collect();
+    let found = (matches.map(|found| found.path))
+        .filter(move |path| path::absolute(path).map_or(true, |path| !skip.contains(&path)));
+    let candidates = bookmarked.into_iter().chain(found);
+    let candidates: Box<dyn Iterator<Item = PathBuf> + Send> = match rank {
+        // Ranking needs every match, so they can't stream in.
+        Some(rank) => {
+            let mut candidates: Vec<PathBuf> = candidates.collect();
+            sort_by_rank(&mut candidates, rank, |path| path)?;
+            Box::new(candidates.into_iter())
+        }
+        None => Box::new(candidates),
+    };
+    let selected = if fzf {
+        fzf::pick(candidates)?
+    } else {
+        pick::pick(candidates)?
+    };
+    let Some(selected) = selected else {
+        process::exit(1);
+    };
+    println!("{}", to_display(&selected).display());
+    // Remembering the choice is a nicety, so it mustn't stop the
+    // selection from being used.
+    let visited = frecency_path().and_then(|path| {
+        let mut frecency = Frecency::load(&path)?;
+        frecency.visit(&selected)?;
+        frecency.save(&path)
+    });
+    if let Err(e) = visited {
+        tracing::warn!(target: log::SUMMARY, "Failed to record the visit: {:#}", e);
+    }
+    Ok(())
+}
 
-            // TODO: make this not loop forever when there are recursive symlinks?
-            let mut path = dir_entry.path();
-            while path.is_symlink() {
-                path = fs::read_link(path)?;
-            }
-            if path.is_dir() {
-                found_paths.push(dir_entry.path());
-            }
+/// Sorts `items` by `rank`, best first. Ties keep their order.
+fn sort_by_rank<T>(items: &mut [T], rank: Rank, path: impl Fn(&T) -> &Path) -> anyhow::Result<()> {
+    match rank {
+        Rank::Frecency => {
+            let frecency = Frecency::load(&frecency_path()?)?;
+            // Scores are never negative, so their bits sort the same
+            // way they do.
+            items.sort_by_cached_key(|item| Reverse(frecency.score(path(item)).to_bits()));
         }
+    }
+    Ok(())
+}
 
-        if !found_sentinel {
-            for found_path in found_paths {
-                let child = self.child(found_path);
-                self.ctx.pool.spawn(move || child.job());
-            }
+fn index_build(mut find: FindArgs) -> anyhow::Result<()> {
+    // The index can be queried from anywhere, so it shouldn't depend
+    // on the directory it was built from.
+    find.root_dirs = find
+        .root_dirs
+        .iter()
+        .map(path::absolute)
+        .collect::<Result<_, _>>()?;
+    for root in find.roots.iter_mut() {
+        root.path = path::absolute(&root.path)?;
+    }
+    let index = Index::build(find.make_builder()?)?;
+    index.save(&index_path()?)?;
+    eprintln!("Indexed {} projects", index.projects.len());
+    Ok(())
+}
+
+fn index_watch(printer: Printer) -> anyhow::Result<()> {
+    let index_path = index_path()?;
+    let mut index = Index::load(&index_path)?
+        .ok_or_else(|| anyhow!("No index found, run `pj index build` first"))?;
+    index.watch(&index_path, |event| match event {
+        WatchEvent::Added(found) => Ok(printer.added(found)?),
+        WatchEvent::Removed(path) => Ok(printer.removed(path)?),
+    })
+}
+
+fn index_query(
+    mut printer: Printer,
+    filter: Option<String>,
+    root: Option<PathBuf>,
+) -> anyhow::Result<()> {
+    let query = IndexQuery {
+        filter,
+        root: root.map(path::absolute).transpose()?,
+    };
+    printer.set_lists_results(true);
+
+    #[cfg(unix)]
+    if let Some(projects) = pj::daemon::query(&socket_path()?, &query)? {
+        for project in projects {
+            printer.path(&project)?;
         }
+        return printer.finish();
+    }
 
-        Ok(())
+    let index = Index::load(&index_path()?)?
+        .ok_or_else(|| anyhow!("No index found, run `pj index build` first"))?;
+    for project in index.query(&query)? {
+        printer.path(project)?;
+    }
+    printer.finish()
+}
+
+fn print_presets() {
+    for preset in preset::PRESETS {
+        println!(
+            "{:8} {:30} {}",
+            preset.name,
+            preset.description,
+            preset.patterns.join(" ")
+        );
     }
 }
 
-#[derive(StructOpt)]
-#[structopt(name = "pj", about = "A fast sentinel file finder.")]
+fn frecency_path() -> anyhow::Result<PathBuf> {
+    Frecency::default_path()
+        .ok_or_else(|| anyhow!("Cannot find a data directory for the visit history"))
+}
+
+fn bookmarks_path() -> anyhow::Result<PathBuf> {
+    Bookmarks::default_path()
+        .ok_or_else(|| anyhow!("Cannot find a data directory for the bookmarks"))
+}
+
+fn index_path() -> anyhow::Result<PathBuf> {
+    Index::default_path().ok_or_else(|| anyhow!("Cannot find a cache directory for the index"))
+}
+
+#[cfg(unix)]
+fn socket_path() -> anyhow::Result<PathBuf> {
+    pj::daemon::default_socket_path()
+        .ok_or_else(|| anyhow!("Cannot find a directory for the daemon's socket"))
+}
+
+/// Parses any of `names` with `T`'s `FromStr`, listing them in
+/// `--help`.
+fn one_of<T>(names: &'static [&'static str]) -> impl TypedValueParser<Value = T>
+where
+    T: FromStr<Err = anyhow::Error> + Clone + Send + Sync + 'static,
+{
+    PossibleValuesParser::new(names).try_map(|name| name.parse::<T>())
+}
+
+#[derive(Parser)]
+#[command(name = "pj", about = "A fast sentinel file finder.", version)]
 struct Opt {
-    sentinel_pattern: String,
+    #[command(flatten)]
+    global: GlobalArgs,
+
+    // Without a subcommand, pj searches, the same as `pj find`.
+    #[command(flatten)]
+    find: FindCommand,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+/// Flags which apply to every subcommand.
+#[derive(Args)]
+struct GlobalArgs {
+    /// Log more to stderr: `-v` for each directory which couldn't be
+    /// read, rather than just how many there were, `-vv` for why each
+    /// directory was searched or skipped, and `-vvv` for every entry
+    /// checked.
+    #[arg(short, long, global = true, action = ArgAction::Count)]
+    verbose: u8,
+
+    /// How to log to stderr: as `text`, or as `json` with one object
+    /// per line for other programs to read. With `json`, --stats and
+    /// the summary of skipped directories are logged too.
+    #[arg(long, global = true, default_value = "text", value_parser = one_of::<LogFormat>(LogFormat::NAMES))]
+    log_format: LogFormat,
+
+    /// When to colorize output. `auto` colorizes when stdout is a
+    /// terminal and `NO_COLOR` isn't set.
+    #[arg(long, global = true, default_value = "auto", value_parser = one_of::<ColorChoice>(ColorChoice::NAMES))]
+    color: ColorChoice,
+
+    /// How to print results. `long` and `json` include the project type
+    /// and the modification time, owner and size of each match's
+    /// sentinel. `csv` and `tsv` print a table with a header row, with
+    /// each match's path, sentinel, type, depth and modification time,
+    /// and `markdown` prints a table of the path, type and modification
+    /// time for pasting into documents.
+    /// Defaults to `format` from the config file, then `plain`.
+    #[arg(long, global = true, value_parser = one_of::<Format>(Format::NAMES))]
+    format: Option<Format>,
+
+    /// Write results to this file instead of stdout. It's only
+    /// replaced once the search has finished successfully.
+    #[arg(short, long, global = true)]
+    output: Option<PathBuf>,
+}
+
+/// Flags for `pj find`, which searches and prints what it finds.
+// The flags in the `mode` group replace printing the list of matches
+// with something else, so flags which change that list conflict with
+// the whole group. `watching` and `picking` are the parts of it which
+// stream matches as they're found.
+#[derive(Args)]
+#[command(group(ArgGroup::new("mode").multiple(true)))]
+#[command(group(ArgGroup::new("watching").multiple(true)))]
+#[command(group(ArgGroup::new("picking").multiple(true)))]
+struct FindCommand {
+    #[command(flatten)]
+    find: FindArgs,
+
+    /// After the initial scan, keep watching the roots and print new
+    /// matches as they're created.
+    #[arg(short, long, groups = ["mode", "watching"], conflicts_with = "output")]
+    watch: bool,
+
+    /// Like --watch, but also report directories which stop matching.
+    /// Every line is prefixed with `+ ` or `- `.
+    #[arg(long, groups = ["mode", "watching"], conflicts_with = "output")]
+    watch_removals: bool,
+
+    /// Choose one of the matches in an interactive fuzzy finder, and
+    /// print it. Same as `pj pick`.
+    #[arg(long, groups = ["mode", "picking"], conflicts_with = "output")]
+    interactive: bool,
+
+    /// Like --interactive, but choose in fzf.
+    #[arg(long, groups = ["mode", "picking"], conflicts_with_all = ["interactive", "output"])]
+    fzf: bool,
+
+    /// Print nothing, and exit as soon as anything matches. The exit
+    /// status is 0 if something matched, 1 otherwise, and 124 if
+    /// `--timeout` ran out first.
+    #[arg(short, long, group = "mode", conflicts_with_all = ["watching", "picking", "output"])]
+    quiet: bool,
+
+    /// Instead of searching beneath each root, print the nearest
+    /// directory at or above it which contains a sentinel, like `git
+    /// rev-parse --show-toplevel`. The exit status is 1 if there isn't
+    /// one.
+    #[arg(long, group = "mode", conflicts_with_all = ["watching", "picking"])]
+    up: bool,
+
+    /// Print how many directories matched instead of the directories.
+    #[arg(short = 'c', long, conflicts_with = "mode")]
+    count: bool,
+
+    /// After searching, print statistics such as how many directories
+    /// were scanned to stderr.
+    #[arg(long, conflicts_with = "mode")]
+    stats: bool,
+
+    /// Don't show how far the search has got on stderr. By default
+    /// it's shown if stderr is a terminal and the search is slow.
+    #[arg(long)]
+    no_progress: bool,
+
+    /// With --count, print a count for each root directory.
+    #[arg(long, requires = "count")]
+    per_root: bool,
+
+    /// Print each root followed by the matches beneath it, once the
+    /// search has finished. In JSON, each root is one object with its
+    /// matches in an array.
+    #[arg(long, conflicts_with_all = ["mode", "count"])]
+    group_by_root: bool,
+
+    /// Print how much space each match's contents take up before its
+    /// path, like `du -sh`. Sizes are added up on the same threads as
+    /// the search.
+    #[arg(long, conflicts_with_all = ["mode", "count", "group_by_root"], required_if_eq("sort", "size"))]
+    du: bool,
+
+    /// Print matches sorted by `path`, by `mtime`, most recently
+    /// modified first, or by `size` with --du, largest first, once the
+    /// search has finished. --max-results then keeps the first ones in
+    /// that order.
+    #[arg(long, value_parser = one_of::<Sort>(Sort::NAMES), conflicts_with_all = ["mode", "count", "rank"])]
+    sort: Option<Sort>,
+
+    /// With `--format long` or `json`, also show the current branch
+    /// and the `origin` URL of each git repository.
+    #[arg(long)]
+    git_info: bool,
+
+    /// Print matches sorted by path once the search has finished, so
+    /// that the output only changes when the filesystem does.
+    #[arg(long, conflicts_with = "mode")]
+    deterministic: bool,
+
+    /// Print matches in this order once the search has finished, e.g.
+    /// `frecency` for the projects you visit most first.
+    #[arg(long, value_parser = one_of::<Rank>(Rank::NAMES), conflicts_with_all = ["watching", "quiet", "up", "count"])]
+    rank: Option<Rank>,
+
+    /// Also search a directory on another machine over SSH, written
+    /// like `user@host:~/src`. pj does the search there if it's
+    /// installed, otherwise it's done from here over SFTP.
+    #[arg(long = "remote", conflicts_with_all = ["mode", "count", "group_by_root", "du"])]
+    remotes: Vec<Remote>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Search the roots and print each directory containing a
+    /// sentinel. This is what pj does without a subcommand.
+    #[command(visible_alias = "list")]
+    Find(FindCommand),
+    /// Save matches to disk so they can be queried without scanning.
+    #[command(subcommand)]
+    Index(IndexCommand),
+    /// Choose one of the matches in an interactive fuzzy finder, and
+    /// print it.
+    Pick {
+        #[command(flatten)]
+        find: FindArgs,
+
+        /// Use fzf instead of the built-in fuzzy finder.
+        #[arg(long)]
+        fzf: bool,
+
+        /// List the matches in this order, e.g. `frecency` for the
+        /// projects you visit most first.
+        #[arg(long, value_parser = one_of::<Rank>(Rank::NAMES))]
+        rank: Option<Rank>,
+    },
+    /// Run a command in each matched directory, e.g.
+    /// `pj exec '\.git' ~/src -- git fetch`. `{}` in the command is
+    /// replaced with the directory, which is also in `$PJ_DIR`.
+    Exec {
+        #[command(flatten)]
+        find: FindArgs,
+
+        /// Run up to this many commands at once. Each command's output
+        /// is printed in one piece once it finishes, followed at the
+        /// end by a table of which passed and failed.
+        #[arg(long)]
+        jobs: Option<NonZeroUsize>,
+
+        #[arg(last = true, required = true)]
+        command: Vec<String>,
+    },
+    /// Name a project so that `pj pick` always offers it, even if it's
+    /// outside the roots being searched.
+    #[command(subcommand)]
+    Bookmark(BookmarkCommand),
+    /// Record a visit to a project, for `--rank frecency`. Choosing a
+    /// project with `pj pick` records one too.
+    Visit { dir: PathBuf },
+    /// Tools for benchmarking pj.
+    #[command(subcommand)]
+    Bench(BenchCommand),
+    /// List the presets which can be passed to `--preset`.
+    Presets,
+    /// Print a shell function which picks a project and cd's into it.
+    /// Add `eval "$(pj shell-init zsh)"` to your shell's rc file.
+    ShellInit {
+        #[arg(value_parser = one_of::<Shell>(Shell::NAMES))]
+        shell: Shell,
+
+        /// Name of the generated function.
+        #[arg(long, default_value = "pjcd")]
+        name: String,
+    },
+    /// Keep the index in memory and answer `pj index query` over a
+    /// Unix socket.
+    #[cfg(unix)]
+    Daemon,
+    /// Answer queries from the index over HTTP, for editor plugins and
+    /// other tools. `GET /projects` lists every project, and
+    /// `GET /query?pattern=REGEX&root=DIR` works like `pj index query`.
+    /// Responses are JSON.
+    Serve {
+        /// The address to listen on. Anyone who can connect can list
+        /// your projects, so keep it on localhost.
+        #[arg(long, default_value = "127.0.0.1:7777")]
+        listen: String,
+
+        /// How long a client gets to send its request and read the
+        /// response.
+        #[arg(long, value_parser = duration::parse, default_value = "10s")]
+        timeout: Duration,
+    },
+    /// Run a search for `--remote` on another machine.
+    #[command(hide = true)]
+    RemoteSearch,
+}
+
+#[derive(Subcommand)]
+enum BookmarkCommand {
+    /// Bookmark a directory, replacing any bookmark with the same name.
+    Add { name: String, dir: PathBuf },
+    /// Print each bookmark's name and directory.
+    List,
+    /// Delete a bookmark.
+    Remove { name: String },
+}
+
+#[derive(Subcommand)]
+enum BenchCommand {
+    /// Build a synthetic directory tree to search.
+    Generate {
+        /// Where to build the tree. Must not exist yet.
+        dir: PathBuf,
+
+        /// Subdirectories in each directory.
+        #[arg(long, default_value = "8")]
+        breadth: usize,
+
+        /// Levels of directories below `dir`.
+        #[arg(long, default_value = "4")]
+        depth: usize,
+
+        /// Plain files in each directory.
+        #[arg(long, default_value = "4")]
+        files: usize,
+
+        /// The chance, from 0 to 1, that a directory contains a
+        /// sentinel.
+        #[arg(long, default_value = "0.1")]
+        density: f64,
+
+        /// Name of the sentinel file.
+        #[arg(long, default_value = ".git")]
+        sentinel: String,
+
+        /// Seed for choosing which directories get sentinels.
+        #[arg(long, default_value = "0")]
+        seed: u64,
+    },
+}
+
+// Parsed once at startup, so the size of the variants doesn't matter.
+#[allow(clippy::large_enum_variant)]
+#[derive(Subcommand)]
+enum IndexCommand {
+    /// Scan the roots and replace the index with the results.
+    Build {
+        #[command(flatten)]
+        find: FindArgs,
+    },
+    /// Watch the indexed roots and update the index as projects are
+    /// created or removed, printing each change, until interrupted.
+    /// Run `pj index build` first.
+    Watch,
+    /// Print projects from the index.
+    Query {
+        /// Only print projects whose path matches this regex.
+        filter: Option<String>,
+
+        /// Only print projects beneath this directory.
+        #[arg(long)]
+        root: Option<PathBuf>,
+    },
+}
 
+/// Flags which control a search.
+#[derive(Args, Clone)]
+struct FindArgs {
+    /// Regex matched against file names, optionally followed by a path
+    /// which has to exist beneath them, like `.git/HEAD`. Defaults to
+    /// `sentinel` from the config file.
+    sentinel_pattern: Option<String>,
+
+    /// Directories to search beneath. Defaults to `PJ_DEFAULT_ROOTS`,
+    /// then `roots` from the config file, then the current directory.
     root_dirs: Vec<PathBuf>,
 
-    #[structopt(short, long)]
+    /// Another root directory, optionally with its own `--depth`
+    /// written after a colon, e.g. `--root ~/src:4 --root /opt:1`.
+    #[arg(long = "root")]
+    roots: Vec<RootSpec>,
+
+    /// Another sentinel pattern. Directories match if they contain a
+    /// sentinel for any of the patterns, unless `--all` is passed.
+    #[arg(long = "sentinel")]
+    extra_patterns: Vec<String>,
+
+    /// Add the sentinel patterns from a named preset, e.g. `vcs` or
+    /// `rust`. See `pj presets` for the full list. With a preset, every
+    /// positional argument is a root directory.
+    #[arg(long = "preset")]
+    presets: Vec<&'static Preset>,
+
+    /// Only match directories which contain a sentinel for every
+    /// pattern, e.g. `package.json --sentinel tsconfig.json --all`.
+    #[arg(long)]
+    all: bool,
+
+    /// Skip directories containing an entry matching this regex, e.g.
+    /// `.pj-skip` or `CACHEDIR.TAG`, along with everything beneath them.
+    #[arg(long)]
+    exclude_sentinel: Vec<String>,
+
+    /// Don't descend into directories matching this regex, e.g.
+    /// `target` or `build`. Unlike `--exclude-sentinel`, the directory
+    /// containing them can still match.
+    #[arg(long)]
+    prune: Vec<String>,
+
+    /// Match the sentinel pattern case-insensitively.
+    #[arg(short = 'i', long)]
+    ignore_case: bool,
+
+    /// Convert the sentinel patterns and file names to Unicode NFC
+    /// before matching them, so that names stored decomposed (NFD), as
+    /// macOS may do, still match. On by default on macOS.
+    #[arg(long)]
+    normalize_unicode: bool,
+
+    /// Match names exactly as they're stored, even on macOS.
+    #[arg(long, conflicts_with = "normalize_unicode")]
+    no_normalize_unicode: bool,
+
+    /// Only let directories be sentinels.
+    #[arg(long)]
+    match_dirs: bool,
+
+    /// Only let files (anything other than directories) be sentinels.
+    #[arg(long, conflicts_with = "match_dirs")]
+    match_files: bool,
+
+    /// Only let this type of entry be a sentinel: `f` for files, `d`
+    /// for directories, `l` for symlinks or `x` for executables, e.g.
+    /// `configure --sentinel-type x`.
+    #[arg(long, value_parser = one_of::<SentinelKind>(SentinelKind::NAMES), conflicts_with_all = ["match_dirs", "match_files"])]
+    sentinel_type: Option<SentinelKind>,
+
+    /// Only count well-known sentinels for this type of project, e.g.
+    /// `pj '.*' --type rust` finds directories with a `Cargo.toml`.
+    #[arg(long = "type", value_parser = one_of::<ProjectType>(ProjectType::NAMES))]
+    types: Vec<ProjectType>,
+
+    /// Only count a sentinel if its contents match this regex, e.g.
+    /// `Cargo.toml --contains '\[workspace\]'`. Only the first megabyte
+    /// of each sentinel is checked, and `-v` notes any which were cut
+    /// short.
+    #[arg(long)]
+    contains: Option<String>,
+
+    /// Only count sentinels modified within this long, e.g. `2weeks`
+    /// or `1d12h`.
+    #[arg(long, value_parser = duration::parse)]
+    changed_within: Option<Duration>,
+
+    /// Stop searching after this long, e.g. `5s`, and print whatever
+    /// was found. The exit status is then 124 rather than 0.
+    #[arg(long, value_parser = duration::parse)]
+    timeout: Option<Duration>,
+
+    #[arg(short, long)]
     depth: Option<usize>,
 
-    #[structopt(long)]
+    /// Don't report matches fewer than this many levels below a root.
+    #[arg(long)]
+    min_depth: Option<usize>,
+
+    #[arg(long, num_args = 1..)]
     ignore: Vec<String>,
+
+    /// Number of worker threads. Defaults to the number of CPUs.
+    #[arg(short = 'j', long)]
+    threads: Option<usize>,
+
+    /// Let the number of threads searching at once adapt to the file
+    /// system, from --threads up to this many. Threads are added while
+    /// they're mostly waiting on directory reads, as on NFS, and taken
+    /// away again while they aren't.
+    #[arg(long)]
+    max_threads: Option<usize>,
+
+    /// Stop scanning once this many matches have been printed.
+    #[arg(long)]
+    max_results: Option<usize>,
+
+    /// Stop searching each root as soon as something beneath it
+    /// matches, so at most one match is printed per root, e.g. to check
+    /// which roots really are projects.
+    #[arg(long)]
+    first_per_root: bool,
+
+    /// Keep searching beneath directories which matched the sentinel.
+    #[arg(long)]
+    no_prune: bool,
+
+    /// Keep searching beneath directories which matched, but only this
+    /// many levels further, e.g. for the crates in a workspace.
+    #[arg(long, conflicts_with = "no_prune")]
+    nested: Option<usize>,
+
+    /// Search hidden directories too, like `.cache` or `.local`.
+    /// Hidden entries can be sentinels either way.
+    #[arg(long)]
+    hidden: bool,
+
+    /// Don't descend into directories on a different filesystem from
+    /// their root, like network mounts. Unix only.
+    #[arg(long)]
+    same_file_system: bool,
+
+    /// Don't descend into network mounts, like NFS, SMB or FUSE, which
+    /// can hang the search when they're slow. Each one skipped is
+    /// noted with -v. Unix only.
+    #[arg(long)]
+    skip_network_fs: bool,
+
+    /// Fail as soon as a directory can't be read, instead of skipping
+    /// it.
+    #[arg(long)]
+    strict: bool,
+
+    /// Don't honor `.gitignore`, `.ignore` and `.fdignore` files.
+    #[arg(long)]
+    no_ignore_files: bool,
+
+    /// Descend into symlinked directories. Symlinks passed as root
+    /// directories are always followed.
+    #[arg(short = 'L', long)]
+    follow: bool,
+
+    /// Print each match's canonical path, with symlinks and `..`
+    /// resolved, rather than the path it was found through.
+    #[arg(long)]
+    canonical: bool,
+
+    /// Don't report git submodules, e.g. vendored dependencies. What's
+    /// beneath them is still searched with `--no-prune`.
+    #[arg(long)]
+    no_submodules: bool,
+
+    /// Only report git repositories which are `dirty` (have
+    /// uncommitted changes), `clean`, or `unpushed` (have commits which
+    /// aren't on any remote). Runs git for each match.
+    #[arg(long, value_parser = one_of::<GitStatus>(GitStatus::NAMES))]
+    git_status: Option<GitStatus>,
+
+    /// Scan directories depth first (`dfs`), breadth first (`bfs`) to
+    /// find shallow projects sooner, or strictly `shallowest` first
+    /// (unless `--max-queue` fills up).
+    #[arg(long, default_value = "dfs", value_parser = one_of::<Order>(Order::NAMES))]
+    order: Order,
+
+    /// Cap how many directories can be waiting to be scanned. Past
+    /// that, workers scan what they find themselves, depth first,
+    /// which bounds memory use on huge trees but loosens `--order`.
+    #[arg(long)]
+    max_queue: Option<usize>,
+
+    /// Print why each directory was searched, skipped or reported to
+    /// stderr, e.g. to find out why a project is missing.
+    #[arg(long)]
+    explain: bool,
+
+    /// Search inside .tar, .tar.gz and .zip files too, as if each were
+    /// a directory next to it named with a trailing `!`.
+    #[cfg(feature = "archives")]
+    #[arg(long)]
+    archives: bool,
 }
 
-impl Opt {
-    fn make_sentinel_regex(&self) -> anyhow::Result<Regex> {
-        // Regex doesn't have a is_full_match function.
-        // We ensure the regex starts with `^` and ends with `$`
-        // so that any match is a full match.
-        let prefix = if self.sentinel_pattern.starts_with("^") {
-            ""
-        } else {
-            "^"
-        };
-        let suffix = if self.sentinel_pattern.ends_with("$") {
-            ""
-        } else {
-            "$"
-        };
-        let sentinel_str = format!("{prefix}{}{suffix}", self.sentinel_pattern);
-        Ok(Regex::new(&sentinel_str)?)
+impl FindArgs {
+    /// Fills in anything not passed on the command line from the
+    /// config file. Root directories come from `PJ_DEFAULT_ROOTS`
+    /// before falling back to the config file. Ignore lists are
+    /// combined rather than replaced.
+    fn apply_config(&mut self, config: Config) -> anyhow::Result<()> {
+        // Presets stand in for the sentinel pattern, so the first
+        // positional argument is really a root.
+        if !self.presets.is_empty() {
+            if let Some(root) = self.sentinel_pattern.take() {
+                // Anything else was probably meant as a pattern.
+                if !Path::new(&root).is_dir() {
+                    return Err(anyhow!(
+                        "With --preset, {:?} is taken as a root directory, but it isn't one. \
+                         Other patterns can be added with --sentinel",
+                        root
+                    ));
+                }
+                self.root_dirs.insert(0, root.into());
+            }
+        }
+        if self.sentinel_pattern.is_none()
+            && self.extra_patterns.is_empty()
+            && self.presets.is_empty()
+        {
+            self.sentinel_pattern = config.sentinel;
+            self.presets = config.preset;
+        }
+        if self.root_dirs.is_empty() && self.roots.is_empty() {
+            self.root_dirs = config::env_roots();
+        }
+        if self.root_dirs.is_empty() && self.roots.is_empty() {
+            self.roots = config.roots;
+        }
+        let mut ignore = config.ignore;
+        ignore.append(&mut self.ignore);
+        self.ignore = ignore;
+        self.depth = self.depth.or(config.depth);
+        self.threads = self.threads.or(config.threads);
+        self.max_threads = self.max_threads.or(config.max_threads);
+        Ok(())
+    }
+
+    /// Searches the current directory if no roots were given, either
+    /// on the command line or by the config.
+    fn default_to_current_dir(&mut self) {
+        if self.root_dirs.is_empty() && self.roots.is_empty() {
+            self.root_dirs = vec![PathBuf::from(".")];
+        }
+    }
+
+    /// Every root directory, whether or not it has its own depth limit.
+    fn root_paths(&self) -> Vec<PathBuf> {
+        (self.root_dirs.iter())
+            .chain(self.roots.iter().map(|root| &root.path))
+            .cloned()
+            .collect()
+    }
+
+    fn make_builder(self) -> anyhow::Result<FinderBuilder> {
+        let preset_patterns = self
+            .presets
+            .iter()
+            .flat_map(|preset| preset.patterns)
+            .map(|pattern| pattern.to_string());
+        let mut patterns = self
+            .sentinel_pattern
+            .into_iter()
+            .chain(self.extra_patterns)
+            .chain(preset_patterns);
+        let sentinel_pattern = patterns.next().ok_or_else(|| {
+            anyhow!("No sentinel pattern given, and none is set in the config file")
+        })?;
+        let builder = self.roots.into_iter().fold(
+            patterns.fold(FinderBuilder::new(sentinel_pattern), FinderBuilder::pattern),
+            |builder, root| match root.max_depth {
+                Some(max_depth) => builder.root_with_max_depth(root.path, max_depth),
+                None => builder.root(root.path),
+            },
+        );
+        let builder = builder
+            .require_all(self.all)
+            .excludes(self.exclude_sentinel)
+            .prunes(self.prune)
+            .ignore_case(self.ignore_case)
+            .normalize_unicode(
+                self.normalize_unicode || (cfg!(target_os = "macos") && !self.no_normalize_unicode),
+            )
+            .sentinel_kind(
+                match (self.sentinel_type, self.match_files, self.match_dirs) {
+                    (Some(sentinel_type), _, _) => sentinel_type,
+                    (None, true, _) => SentinelKind::File,
+                    (None, _, true) => SentinelKind::Dir,
+                    (None, false, false) => SentinelKind::Any,
+                },
+            )
+            .types(self.types)
+            .contains(self.contains)
+            .changed_within(self.changed_within)
+            .roots(self.root_dirs)
+            .max_depth(self.depth)
+            .min_depth(self.min_depth)
+            .ignore(self.ignore)
+            .threads(self.threads)
+            .max_threads(self.max_threads)
+            .max_results(self.max_results)
+            .first_per_root(self.first_per_root)
+            .timeout(self.timeout)
+            .no_prune(self.no_prune)
+            .nested(self.nested)
+            .no_ignore_files(self.no_ignore_files)
+            .strict(self.strict)
+            .hidden(self.hidden)
+            .same_file_system(self.same_file_system)
+            .skip_network_fs(self.skip_network_fs)
+            .follow(self.follow)
+            .canonical_paths(self.canonical)
+            .no_submodules(self.no_submodules)
+            .git_status(self.git_status)
+            .order(self.order)
+            .max_queue(self.max_queue);
+        #[cfg(feature = "archives")]
+        let builder = builder.archives(self.archives);
+        if !self.explain {
+            return Ok(builder);
+        }
+        Ok(builder
+            .explain(|path, decision| eprintln!("{}: {}", to_display(path).display(), decision)))
     }
 }
@@ -0,0 +1,504 @@
+use std::borrow::Cow;
+use std::ffi::OsStr;
+use std::path::Path;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+use std::time::SystemTime;
+
+use anyhow::anyhow;
+use regex::bytes;
+use serde::Deserialize;
+use serde::Serialize;
+use unicode_normalization::is_nfc;
+use unicode_normalization::UnicodeNormalization;
+
+use crate::file_system::DirEntry;
+use crate::file_system::FileSystem;
+use crate::file_system::FileType;
+use crate::finder::FinderBuilder;
+use crate::project_type::ProjectType;
+
+/// Only this much of a sentinel is read when checking its contents, so
+/// that a huge file which happens to have the right name doesn't stall
+/// the search.
+const MAX_CONTENTS_LEN: u64 = 1024 * 1024;
+
+/// Which kinds of entries may be sentinels.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub enum SentinelKind {
+    /// Files and directories alike.
+    #[default]
+    Any,
+    /// Anything which isn't a directory.
+    File,
+    /// Only directories.
+    Dir,
+    /// Only symlinks, whatever they point at.
+    Symlink,
+    /// Files which someone is allowed to execute.
+    Executable,
+}
+
+impl SentinelKind {
+    /// The names for `--sentinel-type`, like `fd --type`.
+    pub const NAMES: &'static [&'static str] = &["f", "d", "l", "x"];
+
+    /// Checks the entry at `path`, whose own type is `file_type`.
+    /// Symlinks are judged by what they point at, unless we're looking
+    /// for symlinks.
+    fn matches(self, fs: &dyn FileSystem, path: &Path, file_type: FileType) -> bool {
+        let is_dir = || match file_type {
+            FileType::Symlink => fs.metadata(path).is_ok_and(|m| m.file_type.is_dir()),
+            _ => file_type.is_dir(),
+        };
+        match self {
+            SentinelKind::Any => true,
+            SentinelKind::File => !is_dir(),
+            SentinelKind::Dir => is_dir(),
+            SentinelKind::Symlink => file_type.is_symlink(),
+            SentinelKind::Executable => fs.metadata(path).is_ok_and(|m| {
+                !m.file_type.is_dir() && m.permissions.is_some_and(|mode| mode & 0o111 != 0)
+            }),
+        }
+    }
+}
+
+impl FromStr for SentinelKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "f" => Ok(SentinelKind::File),
+            "d" => Ok(SentinelKind::Dir),
+            "l" => Ok(SentinelKind::Symlink),
+            "x" => Ok(SentinelKind::Executable),
+            _ => Err(anyhow!("Invalid sentinel type {:?}", s)),
+        }
+    }
+}
+
+/// Decides whether a directory entry is a sentinel, and whether a
+/// directory contains enough sentinels to match.
+pub(crate) struct Matcher {
+    /// The sentinel patterns as they were given, so that matches can
+    /// say which ones they're for.
+    patterns: Vec<String>,
+    /// The regex for the name in each sentinel pattern, all together so
+    /// that each name is only scanned once however many there are.
+    names: bytes::RegexSet,
+    /// The path after the name in each sentinel pattern, e.g. `HEAD` in
+    /// `.git/HEAD`, which has to exist beneath the entry.
+    nested: Vec<Option<PathBuf>>,
+    require_all: bool,
+    /// Entries which disqualify the directory containing them, with
+    /// each pattern as it was given.
+    excludes: Vec<(String, bytes::Regex)>,
+    /// Directories which aren't descended into, with each pattern as
+    /// it was given.
+    prunes: Vec<(String, bytes::Regex)>,
+    kind: SentinelKind,
+    /// If non-empty, only sentinels for these types of project count.
+    types: Vec<ProjectType>,
+    contains: Option<bytes::Regex>,
+    /// Sentinels modified longer ago than this don't count. It's
+    /// checked against the time of each check, since a search can run
+    /// again and again, e.g. while watching.
+    changed_within: Option<Duration>,
+    /// Whether names are converted to NFC before they're matched, like
+    /// the patterns are.
+    normalize_unicode: bool,
+}
+
+impl Matcher {
+    pub(crate) fn new(params: &FinderBuilder) -> anyhow::Result<Self> {
+        if params.patterns.is_empty() {
+            return Err(anyhow!("At least one sentinel pattern is required"));
+        }
+        let normalize = |pattern: &str| match params.normalize_unicode {
+            true => pattern.nfc().collect(),
+            false => pattern.to_string(),
+        };
+        let make_regexes = |patterns: &[String]| {
+            patterns
+                .iter()
+                .map(|pattern| {
+                    let regex = make_sentinel_regex(&normalize(pattern), params.ignore_case)?;
+                    Ok((pattern.clone(), regex))
+                })
+                .collect::<anyhow::Result<_>>()
+        };
+        let (names, nested): (Vec<_>, _) = params.patterns.iter().map(|p| split(p)).unzip();
+        Ok(Matcher {
+            patterns: params.patterns.clone(),
+            names: bytes::RegexSetBuilder::new(names.iter().map(|name| anchor(&normalize(name))))
+                .case_insensitive(params.ignore_case)
+                .build()?,
+            nested,
+            require_all: params.require_all,
+            excludes: make_regexes(&params.excludes)?,
+            prunes: make_regexes(&params.prunes)?,
+            kind: params.sentinel_kind,
+            types: params.types.clone(),
+            contains: params
+                .contains
+                .as_deref()
+                .map(bytes::Regex::new)
+                .transpose()?,
+            changed_within: params.changed_within,
+            normalize_unicode: params.normalize_unicode,
+        })
+    }
+
+    /// The name as it's matched against the patterns. File systems
+    /// like APFS can hand back names in NFD, which wouldn't match a
+    /// pattern typed in NFC, so with `normalize_unicode` both are
+    /// converted to NFC. Names which aren't valid UTF-8 are left as
+    /// they are.
+    fn name_bytes<'a>(&self, file_name: &'a OsStr) -> Cow<'a, [u8]> {
+        match file_name.to_str() {
+            Some(name) if self.normalize_unicode && !is_nfc(name) => {
+                Cow::Owned(name.nfc().collect::<String>().into_bytes())
+            }
+            _ => Cow::Borrowed(file_name.as_encoded_bytes()),
+        }
+    }
+
+    /// Checks only the entry's name. This is all we can check for an
+    /// entry which no longer exists.
+    pub(crate) fn is_match_name(&self, file_name: &OsStr) -> bool {
+        self.names.is_match(&self.name_bytes(file_name)) && self.is_allowed_type(file_name)
+    }
+
+    /// Checks `--type`, which only allows well-known sentinels.
+    fn is_allowed_type(&self, file_name: &OsStr) -> bool {
+        self.types.is_empty()
+            || (file_name.to_str())
+                .and_then(ProjectType::of)
+                .is_some_and(|found| self.types.contains(&found))
+    }
+
+    /// Whether an entry with this name stops its directory from
+    /// matching or being searched.
+    pub(crate) fn is_excluded(&self, file_name: &OsStr) -> bool {
+        self.excluded_by(file_name).is_some()
+    }
+
+    /// The exclusion pattern which matches this name, if any.
+    pub(crate) fn excluded_by(&self, file_name: &OsStr) -> Option<&str> {
+        first_match(&self.excludes, &self.name_bytes(file_name))
+    }
+
+    /// Whether a directory with this name shouldn't be searched.
+    pub(crate) fn is_pruned(&self, file_name: &OsStr) -> bool {
+        self.pruned_by(file_name).is_some()
+    }
+
+    /// The prune pattern which matches this name, if any.
+    pub(crate) fn pruned_by(&self, file_name: &OsStr) -> Option<&str> {
+        first_match(&self.prunes, &self.name_bytes(file_name))
+    }
+
+    /// Whether a directory has to be read in full before we know it
+    /// matches, because an exclusion could turn up anywhere in it.
+    pub(crate) fn has_excludes(&self) -> bool {
+        !self.excludes.is_empty()
+    }
+
+    /// Starts checking the entries of a single directory.
+    pub(crate) fn dir_matcher<'a>(&'a self, fs: &'a dyn FileSystem) -> DirMatcher<'a> {
+        DirMatcher {
+            matcher: self,
+            fs,
+            satisfied: vec![false; self.patterns.len()],
+            remaining: if self.require_all {
+                self.patterns.len()
+            } else {
+                1
+            },
+        }
+    }
+
+    /// Checks the entry at `path`, whose own type is `file_type`. The
+    /// cheap checks go first, so we only look at the entry's contents
+    /// if everything else matches.
+    fn is_match(&self, fs: &dyn FileSystem, path: &Path, file_type: FileType) -> bool {
+        self.kind.matches(fs, path, file_type)
+            && self.is_recent(fs, path)
+            && self.contents_match(fs, path)
+    }
+
+    fn is_recent(&self, fs: &dyn FileSystem, path: &Path) -> bool {
+        let Some(changed_within) = self.changed_within else {
+            return true;
+        };
+        fs.metadata(path).is_ok_and(|metadata| {
+            metadata.modified.is_some_and(|modified| {
+                // Anything modified in the future is as recent as it
+                // gets.
+                !(SystemTime::now().duration_since(modified)).is_ok_and(|age| age > changed_within)
+            })
+        })
+    }
+
+    fn contents_match(&self, fs: &dyn FileSystem, path: &Path) -> bool {
+        let Some(contains) = &self.contains else {
+            return true;
+        };
+        // Directories, devices and unreadable files just don't match.
+        let Ok(contents) = fs.read(path, MAX_CONTENTS_LEN) else {
+            return false;
+        };
+        let is_match = contains.is_match(&contents);
+        if !is_match && contents.len() as u64 == MAX_CONTENTS_LEN {
+            tracing::warn!(
+                path = %path.display(),
+                "Only the first {} bytes were checked for --contains",
+                MAX_CONTENTS_LEN
+            );
+        }
+        is_match
+    }
+}
+
+/// Tracks which sentinel patterns have been satisfied by the entries of
+/// a directory, created by [`Matcher::dir_matcher`].
+pub(crate) struct DirMatcher<'a> {
+    matcher: &'a Matcher,
+    fs: &'a dyn FileSystem,
+    satisfied: Vec<bool>,
+    /// How many more patterns need to be satisfied for the directory
+    /// to match.
+    remaining: usize,
+}
+
+impl DirMatcher<'_> {
+    /// Checks another entry of `dir`, returning the sentinel's path if
+    /// it's one for any of the patterns. That's the entry itself, or
+    /// something beneath it for a pattern like `.git/HEAD`. Paths are
+    /// only built if the entry's name matches, so most entries never
+    /// build one.
+    pub(crate) fn add(&mut self, dir: &Path, dir_entry: &DirEntry) -> Option<PathBuf> {
+        let file_name = dir_entry.file_name.as_os_str();
+        let matched = (self.matcher.names).matches(&self.matcher.name_bytes(file_name));
+        if !matched.matched_any() || !self.matcher.is_allowed_type(file_name) {
+            return None;
+        }
+        let path = dir.join(file_name);
+        // Whether the entry itself passes the other checks, which is
+        // the same for every pattern without a nested path.
+        let mut entry_matches = None;
+        let mut found = None;
+        for i in matched.iter() {
+            let candidate = match &self.matcher.nested[i] {
+                None => {
+                    let entry_matches = *entry_matches.get_or_insert_with(|| {
+                        (self.matcher).is_match(self.fs, &path, dir_entry.file_type)
+                    });
+                    if !entry_matches {
+                        continue;
+                    }
+                    path.clone()
+                }
+                Some(nested) => {
+                    let candidate = path.join(nested);
+                    let Ok(metadata) = self.fs.metadata(&candidate) else {
+                        continue;
+                    };
+                    if !(self.matcher).is_match(self.fs, &candidate, metadata.file_type) {
+                        continue;
+                    }
+                    candidate
+                }
+            };
+            if !self.satisfied[i] {
+                self.satisfied[i] = true;
+                self.remaining = self.remaining.saturating_sub(1);
+            }
+            found.get_or_insert(candidate);
+        }
+        found
+    }
+
+    /// The patterns which the entries so far have been sentinels for.
+    pub(crate) fn patterns(&self) -> Vec<String> {
+        (self.matcher.patterns.iter())
+            .zip(&self.satisfied)
+            .filter(|(_, satisfied)| **satisfied)
+            .map(|(pattern, _)| pattern.clone())
+            .collect()
+    }
+
+    /// Whether the entries so far are enough for the directory to match.
+    pub(crate) fn is_match(&self) -> bool {
+        self.remaining == 0
+    }
+}
+
+/// Splits a sentinel pattern at its first `/`, into a regex for names
+/// and a path which has to exist beneath the entry with that name. The
+/// path isn't a regex, since most names in it will contain a literal
+/// `.`.
+fn split(pattern: &str) -> (&str, Option<PathBuf>) {
+    match pattern.split_once('/') {
+        Some((name, nested)) if !nested.is_empty() => (name, Some(PathBuf::from(nested))),
+        _ => (pattern, None),
+    }
+}
+
+/// The first of `regexes` which matches `name`, as it was given.
+fn first_match<'a>(regexes: &'a [(String, bytes::Regex)], name: &[u8]) -> Option<&'a str> {
+    (regexes.iter())
+        .find(|(_, regex)| regex.is_match(name))
+        .map(|(pattern, _)| pattern.as_str())
+}
+
+/// Builds a regex which matches whole file names. They're matched as
+/// bytes so that names which aren't valid UTF-8 can still be checked.
+fn make_sentinel_regex(sentinel_pattern: &str, ignore_case: bool) -> anyhow::Result<bytes::Regex> {
+    Ok(bytes::RegexBuilder::new(&anchor(sentinel_pattern))
+        .case_insensitive(ignore_case)
+        .build()?)
+}
+
+/// Anchors `pattern` at both ends, so that any match is a match of the
+/// whole name, since regex doesn't have an is_full_match function. The
+/// group keeps an alternation like `a|b` from only being anchored at
+/// one end.
+fn anchor(pattern: &str) -> String {
+    format!("^(?:{})$", pattern)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_system::MemoryFileSystem;
+
+    fn matcher(builder: FinderBuilder) -> Matcher {
+        Matcher::new(&builder).unwrap()
+    }
+
+    /// Runs the entries of `dir` through a [`DirMatcher`], returning
+    /// the sentinels found and the patterns they satisfied.
+    fn check_dir(
+        matcher: &Matcher,
+        fs: &MemoryFileSystem,
+        dir: &str,
+    ) -> (Vec<PathBuf>, Vec<String>, bool) {
+        let dir = Path::new(dir);
+        let mut dir_matcher = matcher.dir_matcher(fs);
+        let sentinels = (fs.read_dir(dir).unwrap())
+            .filter_map(|dir_entry| dir_matcher.add(dir, &dir_entry.unwrap()))
+            .collect();
+        (sentinels, dir_matcher.patterns(), dir_matcher.is_match())
+    }
+
+    #[test]
+    fn patterns_match_whole_names() {
+        let matcher = matcher(FinderBuilder::new("git"));
+        assert!(matcher.is_match_name(OsStr::new("git")));
+        assert!(!matcher.is_match_name(OsStr::new(".git")));
+        assert!(!matcher.is_match_name(OsStr::new("gitignore")));
+    }
+
+    #[test]
+    fn alternations_are_anchored_at_both_ends() {
+        let matcher = matcher(FinderBuilder::new("Cargo.toml|go.mod"));
+        assert!(matcher.is_match_name(OsStr::new("Cargo.toml")));
+        assert!(matcher.is_match_name(OsStr::new("go.mod")));
+        assert!(!matcher.is_match_name(OsStr::new("Cargo.toml.bak")));
+        assert!(!matcher.is_match_name(OsStr::new("old.go.mod")));
+    }
+
+    #[test]
+    fn explicit_anchors_are_allowed() {
+        let matcher = matcher(FinderBuilder::new(r"^\.git$"));
+        assert!(matcher.is_match_name(OsStr::new(".git")));
+        assert!(!matcher.is_match_name(OsStr::new(".github")));
+    }
+
+    #[test]
+    fn excludes_and_prunes_match_whole_names() {
+        let matcher = matcher(
+            (FinderBuilder::new("Cargo.toml"))
+                .excludes(["node_modules"])
+                .prunes(["target|build"]),
+        );
+        assert_eq!(
+            matcher.excluded_by(OsStr::new("node_modules")),
+            Some("node_modules")
+        );
+        assert_eq!(
+            matcher.pruned_by(OsStr::new("target")),
+            Some("target|build")
+        );
+        assert!(!matcher.is_excluded(OsStr::new("node_modules2")));
+        assert!(matcher.is_pruned(OsStr::new("build")));
+        assert!(!matcher.is_pruned(OsStr::new("targets")));
+    }
+
+    #[test]
+    fn nested_sentinels_must_exist() {
+        let mut fs = MemoryFileSystem::new();
+        fs.add_file("/a/.git/HEAD", "");
+        fs.add_dir("/b/.git");
+        let matcher = matcher(FinderBuilder::new(r"\.git/HEAD"));
+        let (sentinels, _, is_match) = check_dir(&matcher, &fs, "/a");
+        assert_eq!(sentinels, [PathBuf::from("/a/.git/HEAD")]);
+        assert!(is_match);
+        let (sentinels, _, is_match) = check_dir(&matcher, &fs, "/b");
+        assert!(sentinels.is_empty());
+        assert!(!is_match);
+    }
+
+    #[test]
+    fn records_which_patterns_matched() {
+        let mut fs = MemoryFileSystem::new();
+        fs.add_file("/a/go.mod", "");
+        fs.add_file("/a/README.md", "");
+        let matcher = matcher(
+            (FinderBuilder::new("Cargo.toml"))
+                .pattern("go.mod")
+                .pattern(".*\\.md"),
+        );
+        let (sentinels, patterns, is_match) = check_dir(&matcher, &fs, "/a");
+        assert_eq!(sentinels.len(), 2);
+        assert_eq!(patterns, ["go.mod", ".*\\.md"]);
+        assert!(is_match);
+    }
+
+    #[test]
+    fn require_all_needs_every_pattern() {
+        let mut fs = MemoryFileSystem::new();
+        fs.add_file("/a/Cargo.toml", "");
+        fs.add_file("/a/go.mod", "");
+        fs.add_file("/b/Cargo.toml", "");
+        let matcher = matcher(
+            (FinderBuilder::new("Cargo.toml"))
+                .pattern("go.mod")
+                .require_all(true),
+        );
+        let (_, patterns, is_match) = check_dir(&matcher, &fs, "/a");
+        assert_eq!(patterns, ["Cargo.toml", "go.mod"]);
+        assert!(is_match);
+        let (_, patterns, is_match) = check_dir(&matcher, &fs, "/b");
+        assert_eq!(patterns, ["Cargo.toml"]);
+        assert!(!is_match);
+    }
+
+    #[test]
+    fn one_name_can_satisfy_several_patterns() {
+        let mut fs = MemoryFileSystem::new();
+        fs.add_file("/a/.git/HEAD", "");
+        let matcher = matcher(
+            (FinderBuilder::new(r"\.git"))
+                .pattern(r"\.git/HEAD")
+                .pattern(r"\.git/config")
+                .require_all(true),
+        );
+        let (sentinels, patterns, is_match) = check_dir(&matcher, &fs, "/a");
+        assert_eq!(sentinels, [PathBuf::from("/a/.git")]);
+        assert_eq!(patterns, [r"\.git", r"\.git/HEAD"]);
+        assert!(!is_match);
+    }
+}
@@ -0,0 +1,148 @@
+//! Finding network mounts from the mount table, for
+//! [`FinderBuilder::skip_network_fs`](crate::FinderBuilder::skip_network_fs).
+//! The table is read rather than each directory being statted, since
+//! statting an unresponsive NFS mount is exactly what hangs.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// File system types which live on another machine. FUSE file systems
+/// are included since so many of them (sshfs, rclone, ...) are remote,
+/// but `fuseblk` is backed by a local disk. `autofs` is the placeholder
+/// for something which gets mounted when it's first looked at, which is
+/// usually NFS.
+const NETWORK_FS_TYPES: &[&str] = &[
+    "9p",
+    "afpfs",
+    "afs",
+    "autofs",
+    "ceph",
+    "cifs",
+    "davfs",
+    "fuse",
+    "glusterfs",
+    "gpfs",
+    "lustre",
+    "ncpfs",
+    "nfs",
+    "nfs4",
+    "smb3",
+    "smbfs",
+    "sshfs",
+    "webdav",
+];
+
+fn is_network_fs_type(fs_type: &str) -> bool {
+    NETWORK_FS_TYPES.contains(&fs_type)
+        || fs_type.starts_with("fuse.")
+        || fs_type.starts_with("macfuse")
+        || fs_type.starts_with("osxfuse")
+}
+
+/// The mount points of network file systems, by their absolute path.
+#[derive(Debug, Default)]
+pub(crate) struct NetworkMounts {
+    fs_types: HashMap<PathBuf, String>,
+}
+
+impl NetworkMounts {
+    /// Reads the current mount table. If it can't be read, nothing
+    /// counts as a network mount, and a warning says so.
+    pub(crate) fn load() -> Self {
+        let mounts = match read_mount_table() {
+            Ok(mounts) => mounts,
+            Err(e) => {
+                tracing::warn!("Can't tell which file systems are network mounts: {}", e);
+                Vec::new()
+            }
+        };
+        NetworkMounts {
+            fs_types: (mounts.into_iter())
+                .filter(|(_, fs_type)| is_network_fs_type(fs_type))
+                .collect(),
+        }
+    }
+
+    /// The type of network file system mounted at `dir`, if any.
+    pub(crate) fn fs_type(&self, dir: &Path) -> Option<&str> {
+        if self.fs_types.is_empty() {
+            return None;
+        }
+        let dir = std::path::absolute(dir).ok()?;
+        self.fs_types.get(&dir).map(String::as_str)
+    }
+}
+
+/// Each mount point with its file system type, from
+/// `/proc/self/mountinfo`.
+#[cfg(target_os = "linux")]
+fn read_mount_table() -> std::io::Result<Vec<(PathBuf, String)>> {
+    let mountinfo = std::fs::read("/proc/self/mountinfo")?;
+    // Mount points which aren't valid UTF-8 come out mangled, so they
+    // just won't be recognized.
+    let mountinfo = String::from_utf8_lossy(&mountinfo);
+    // Each line is `id parent major:minor root mount-point options
+    // [optional fields...] - type source super-options`.
+    Ok(mountinfo
+        .lines()
+        .filter_map(|line| {
+            let (fields, rest) = line.split_once(" - ")?;
+            let mount_point = fields.split(' ').nth(4)?;
+            let fs_type = rest.split(' ').next()?;
+            Some((unescape(mount_point), fs_type.to_string()))
+        })
+        .collect())
+}
+
+/// Each mount point with its file system type, from the output of
+/// `mount`, whose lines look like `host:/export on /mnt (nfs, ...)`.
+#[cfg(all(unix, not(target_os = "linux")))]
+fn read_mount_table() -> std::io::Result<Vec<(PathBuf, String)>> {
+    let output = std::process::Command::new("mount").output()?;
+    let output = String::from_utf8_lossy(&output.stdout);
+    Ok(output
+        .lines()
+        .filter_map(|line| {
+            let (_, rest) = line.split_once(" on ")?;
+            let (mount_point, details) = rest.rsplit_once(" (")?;
+            let fs_type = details.split([',', ')']).next()?;
+            Some((PathBuf::from(mount_point), fs_type.to_string()))
+        })
+        .collect())
+}
+
+/// Windows shares are reached through `\\server\share` paths rather
+/// than mounted inside other directories, so there's nothing to skip.
+#[cfg(not(unix))]
+fn read_mount_table() -> std::io::Result<Vec<(PathBuf, String)>> {
+    Ok(Vec::new())
+}
+
+/// Undoes the octal escapes mountinfo uses for spaces and other
+/// awkward characters in paths, like `\040`.
+#[cfg(target_os = "linux")]
+fn unescape(path: &str) -> PathBuf {
+    use std::ffi::OsString;
+    use std::os::unix::ffi::OsStringExt;
+
+    let bytes = path.as_bytes();
+    let mut unescaped = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escape = bytes.get(i + 1..i + 4).filter(|_| bytes[i] == b'\\');
+        match escape
+            .and_then(|digits| u8::from_str_radix(std::str::from_utf8(digits).ok()?, 8).ok())
+        {
+            Some(byte) => {
+                unescaped.push(byte);
+                i += 4;
+            }
+            None => {
+                unescaped.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    PathBuf::from(OsString::from_vec(unescaped))
+}
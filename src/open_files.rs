@@ -0,0 +1,87 @@
+//! Keeping the directories a search holds open under the process's
+//! file descriptor limit. Each worker only reads one directory at a
+//! time, so this only matters with more threads than the limit allows
+//! for, e.g. `--threads 512` under macOS's default `ulimit -n` of 256.
+//! Without it, reads past the limit fail with "Too many open files".
+
+use std::sync::Condvar;
+use std::sync::Mutex;
+
+/// File descriptors left for everything besides reading directories:
+/// stdio, the output file, ignore files and sentinels being read, the
+/// watcher, and so on.
+const RESERVED: u64 = 32;
+
+/// A semaphore for reading directories, with a permit for each one
+/// which can be open at once.
+pub(crate) struct OpenDirLimit {
+    available: Mutex<usize>,
+    released: Condvar,
+}
+
+impl OpenDirLimit {
+    /// The limit for a search on `threads` threads, or None if they
+    /// couldn't reach the file descriptor limit anyway.
+    pub(crate) fn for_threads(threads: usize) -> Option<Self> {
+        let max_open = max_open_files()?.saturating_sub(RESERVED).max(1);
+        let max_open = usize::try_from(max_open).unwrap_or(usize::MAX);
+        if max_open >= threads {
+            return None;
+        }
+        tracing::debug!(
+            "Reading at most {} directories at once, to stay under the open file limit",
+            max_open
+        );
+        Some(OpenDirLimit {
+            available: Mutex::new(max_open),
+            released: Condvar::new(),
+        })
+    }
+
+    /// Waits until another directory can be opened. It counts as open
+    /// until the guard is dropped.
+    pub(crate) fn acquire(&self) -> OpenDir<'_> {
+        let mut available = self.available.lock().unwrap();
+        while *available == 0 {
+            available = self.released.wait(available).unwrap();
+        }
+        *available -= 1;
+        OpenDir(self)
+    }
+}
+
+/// A directory being read, from [`OpenDirLimit::acquire`].
+pub(crate) struct OpenDir<'a>(&'a OpenDirLimit);
+
+impl Drop for OpenDir<'_> {
+    fn drop(&mut self) {
+        *self.0.available.lock().unwrap() += 1;
+        self.0.released.notify_one();
+    }
+}
+
+/// How many files the process may have open at once: the soft limit
+/// from `ulimit -n`.
+#[cfg(unix)]
+fn max_open_files() -> Option<u64> {
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    // SAFETY: getrlimit only writes to `limit`, which is valid.
+    if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } != 0 {
+        return None;
+    }
+    match limit.rlim_cur {
+        libc::RLIM_INFINITY => None,
+        // rlim_t is only 32 bits on some platforms.
+        #[allow(clippy::unnecessary_cast)]
+        max_open => Some(max_open as u64),
+    }
+}
+
+/// Windows has no comparable limit on handles.
+#[cfg(not(unix))]
+fn max_open_files() -> Option<u64> {
+    None
+}
@@ -0,0 +1,861 @@
+use std::borrow::Cow;
+use std::env;
+use std::fs;
+use std::io;
+use std::io::IsTerminal;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::UNIX_EPOCH;
+
+use anyhow::anyhow;
+use crossterm::queue;
+use crossterm::style;
+use crossterm::style::Stylize;
+use pj::atomic_file::AtomicFile;
+use pj::file_system::FileSystem;
+use pj::file_system::RealFileSystem;
+use pj::long_path::to_display;
+use pj::GitInfo;
+use pj::Match;
+use pj::ProjectType;
+
+use crate::progress::ProgressLine;
+
+/// Whether to colorize output, from `--color`.
+#[derive(Clone, Copy, Debug)]
+pub enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    pub const NAMES: &'static [&'static str] = &["auto", "always", "never"];
+
+    /// Colors are used in auto mode when writing to stdout and it's a
+    /// terminal, unless the user has opted out by setting `NO_COLOR`
+    /// (https://no-color.org).
+    fn should_color(self, to_stdout: bool) -> bool {
+        match self {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => {
+                to_stdout
+                    && io::stdout().is_terminal()
+                    && env::var_os("NO_COLOR").is_none_or(|v| v.is_empty())
+            }
+        }
+    }
+}
+
+impl FromStr for ColorChoice {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "auto" => Ok(ColorChoice::Auto),
+            "always" => Ok(ColorChoice::Always),
+            "never" => Ok(ColorChoice::Never),
+            _ => Err(anyhow!("Invalid color choice {:?}", s)),
+        }
+    }
+}
+
+/// How to print each result, from `--format`.
+#[derive(Clone, Copy, Debug, Default)]
+pub enum Format {
+    /// Just the path.
+    #[default]
+    Plain,
+    /// The sentinel's modification time, owner and size, then the path.
+    Long,
+    /// One JSON object per line, with the same metadata as `Long`.
+    Json,
+    /// A table of comma-separated values, after a header row.
+    Csv,
+    /// Like `Csv`, but separated by tabs.
+    Tsv,
+    /// A GitHub-flavored Markdown table of each match's path, type and
+    /// modification time.
+    Markdown,
+}
+
+impl Format {
+    pub const NAMES: &'static [&'static str] = &["plain", "long", "json", "csv", "tsv", "markdown"];
+}
+
+impl FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "plain" => Ok(Format::Plain),
+            "long" => Ok(Format::Long),
+            "json" => Ok(Format::Json),
+            "csv" => Ok(Format::Csv),
+            "tsv" => Ok(Format::Tsv),
+            "markdown" => Ok(Format::Markdown),
+            _ => Err(anyhow!("Invalid output format {:?}", s)),
+        }
+    }
+}
+
+/// What to sort results by once the search has finished, from
+/// `--sort`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Sort {
+    Path,
+    /// Largest `--du` first.
+    Size,
+    /// Most recently modified first.
+    Mtime,
+}
+
+impl Sort {
+    pub const NAMES: &'static [&'static str] = &["path", "size", "mtime"];
+}
+
+impl FromStr for Sort {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "path" => Ok(Sort::Path),
+            "size" => Ok(Sort::Size),
+            "mtime" => Ok(Sort::Mtime),
+            _ => Err(anyhow!("Invalid sort order {:?}", s)),
+        }
+    }
+}
+
+/// Writes results to stdout, or to the file from `--output`.
+pub struct Printer {
+    color: bool,
+    format: Format,
+    progress: Option<Arc<ProgressLine>>,
+    file: Option<Mutex<AtomicFile>>,
+    /// Where the details of matches are looked up.
+    file_system: Arc<dyn FileSystem>,
+    git_info: bool,
+    /// Whether a table gets its header row even if it has no rows.
+    lists_results: bool,
+    /// Whether the header row of a table has been written.
+    wrote_header: AtomicBool,
+}
+
+impl Printer {
+    pub fn new(color: ColorChoice, format: Format, output: Option<&Path>) -> anyhow::Result<Self> {
+        let color = color.should_color(output.is_none());
+        // crossterm checks NO_COLOR on its own, which would stop
+        // `--color always` from overriding it.
+        style::force_color_output(color);
+        Ok(Printer {
+            color,
+            format,
+            progress: None,
+            file: output.map(AtomicFile::create).transpose()?.map(Mutex::new),
+            file_system: Arc::new(RealFileSystem),
+            git_info: false,
+            lists_results: false,
+            wrote_header: AtomicBool::new(false),
+        })
+    }
+
+    /// Moves the `--output` file into place once everything has been
+    /// written. If this isn't called, e.g. because the search failed,
+    /// the file is left as it was.
+    pub fn finish(self) -> anyhow::Result<()> {
+        // A table with no rows still gets its header, unless it was
+        // never going to have any.
+        if let (Format::Csv | Format::Tsv | Format::Markdown, true) =
+            (self.format, self.lists_results)
+        {
+            let columns = self.columns(None, &Details::default());
+            self.with_out(|mut out| self.write_header(&mut out, &columns))?;
+        }
+        match self.file {
+            Some(file) => file.into_inner().unwrap().commit(),
+            None => Ok(()),
+        }
+    }
+
+    /// Writes `text` as is, for output other than results.
+    pub fn text(&self, text: &str) -> io::Result<()> {
+        self.with_out(|out| out.write_all(text.as_bytes()))
+    }
+
+    /// Clears `progress` out of the way of each result.
+    pub fn set_progress(&mut self, progress: Option<Arc<ProgressLine>>) {
+        self.progress = progress;
+    }
+
+    /// Says that this printer lists results, so that a table with none
+    /// still gets its header row. Anything else, like `--count`, only
+    /// writes text.
+    pub fn set_lists_results(&mut self, lists_results: bool) {
+        self.lists_results = lists_results;
+    }
+
+    /// Looks up the details of matches on `file_system`, which should be
+    /// the one they were found on.
+    pub fn set_file_system(&mut self, file_system: Arc<dyn FileSystem>) {
+        self.file_system = file_system;
+    }
+
+    /// Shows the branch and origin of git repositories in the long and
+    /// JSON formats.
+    pub fn set_git_info(&mut self, git_info: bool) {
+        self.git_info = git_info;
+    }
+
+    /// Prints a matched directory, highlighting its name.
+    pub fn found(&self, found: &Match) -> io::Result<()> {
+        self.print(None, &found.path, Some(found))
+    }
+
+    /// Prints a matched directory after the size of its contents, from
+    /// `--du`, or a `-` if it couldn't be worked out.
+    pub fn found_with_size(&self, found: &Match, size: Option<u64>) -> io::Result<()> {
+        let details = Details {
+            disk_usage: Some(size),
+            ..self.details(Some(found))
+        };
+        self.print_details(None, &found.path, Some(found), details)
+    }
+
+    /// Prints a directory we only know the path of, e.g. from the
+    /// index, so there's no metadata to show.
+    pub fn path(&self, path: &Path) -> io::Result<()> {
+        self.print(None, path, None)
+    }
+
+    /// Prints an addition to the set of matches while watching.
+    pub fn added(&self, found: &Match) -> io::Result<()> {
+        self.print(Some(Event::Added), &found.path, Some(found))
+    }
+
+    /// Prints a removal from the set of matches while watching.
+    pub fn removed(&self, path: &Path) -> io::Result<()> {
+        self.print(Some(Event::Removed), path, None)
+    }
+
+    /// Prints `root` followed by the matches beneath it. In JSON that's
+    /// a single object, with the matches in an array.
+    pub fn group(&self, root: &Path, matches: &[Match], first: bool) -> io::Result<()> {
+        let root = self.display(root);
+        self.with_out(|out| match self.format {
+            Format::Plain | Format::Long => {
+                if !first {
+                    writeln!(out)?;
+                }
+                out.write_all(&path_bytes(&root))?;
+                writeln!(out, ":")?;
+                for found in matches {
+                    let details = self.details(Some(found));
+                    let path = self.display(&found.path);
+                    self.write(&mut *out, None, &path, Some(found), details)?;
+                }
+                Ok(())
+            }
+            Format::Json => {
+                let matches: Vec<serde_json::Value> = matches
+                    .iter()
+                    .map(|found| {
+                        let details = self.details(Some(found));
+                        let path = self.display(&found.path);
+                        let object = json_object(None, &path, Some(found), details);
+                        serde_json::Value::Object(object)
+                    })
+                    .collect();
+                let mut object = serde_json::Map::new();
+                object.insert("root".into(), root.to_string_lossy().into());
+                object.insert("matches".into(), matches.into());
+                writeln!(out, "{}", serde_json::Value::Object(object))
+            }
+            // A table can only have one header, so the groups are just
+            // one after another.
+            Format::Csv | Format::Tsv | Format::Markdown => {
+                for found in matches {
+                    let details = self.details(Some(found));
+                    let path = self.display(&found.path);
+                    self.write(&mut *out, None, &path, Some(found), details)?;
+                }
+                Ok(())
+            }
+        })
+    }
+
+    fn print(&self, event: Option<Event>, path: &Path, found: Option<&Match>) -> io::Result<()> {
+        self.print_details(event, path, found, self.details(found))
+    }
+
+    fn print_details(
+        &self,
+        event: Option<Event>,
+        path: &Path,
+        found: Option<&Match>,
+        details: Details,
+    ) -> io::Result<()> {
+        let path = self.display(path);
+        let write = || self.with_out(|out| self.write(out, event, &path, found, details));
+        match &self.progress {
+            Some(progress) => progress.suspend(write),
+            None => write(),
+        }
+    }
+
+    /// Converts a path to the form it's shown in.
+    fn display<'a>(&self, path: &'a Path) -> Cow<'a, Path> {
+        match self.file_system.display(path) {
+            Cow::Borrowed(path) => to_display(path),
+            Cow::Owned(path) => Cow::Owned(to_display(&path).into_owned()),
+        }
+    }
+
+    /// Looks up what the format shows about a match besides its path.
+    fn details(&self, found: Option<&Match>) -> Details {
+        let (metadata, git) = match (self.format, found) {
+            (Format::Plain, _) | (_, None) => (None, None),
+            (
+                Format::Long | Format::Json | Format::Csv | Format::Tsv | Format::Markdown,
+                Some(found),
+            ) => (
+                Some(Metadata::of(&*self.file_system, &found.sentinel)),
+                // Anything which isn't a repository gets empty columns.
+                (self.git_info).then(|| GitInfo::read(&found.path).unwrap_or_default()),
+            ),
+        };
+        Details {
+            sentinel: found.map(|found| self.display(&found.sentinel).into_owned()),
+            metadata,
+            project_type: found.and_then(Match::project_type),
+            git,
+            disk_usage: None,
+        }
+    }
+
+    fn with_out<T>(&self, f: impl FnOnce(&mut dyn Write) -> io::Result<T>) -> io::Result<T> {
+        match &self.file {
+            Some(file) => f(&mut *file.lock().unwrap()),
+            None => f(&mut io::stdout().lock()),
+        }
+    }
+
+    fn write(
+        &self,
+        mut out: impl Write,
+        event: Option<Event>,
+        path: &Path,
+        found: Option<&Match>,
+        details: Details,
+    ) -> io::Result<()> {
+        match self.format {
+            Format::Plain => {
+                write!(out, "{}", self.prefix(event))?;
+                if let Some(size) = details.disk_usage {
+                    write!(out, "{:>6}  ", size.map_or_else(|| "-".into(), format_size))?;
+                }
+                self.write_path(&mut out, path)?;
+                writeln!(out)
+            }
+            Format::Long => {
+                let prefix = self.prefix(event);
+                let project_type = details.project_type.map_or("-", ProjectType::name);
+                let columns = match &details.metadata {
+                    Some(metadata) => metadata.columns(),
+                    None => format!("{:16} {:>8} {:>10}", "-", "-", "-"),
+                };
+                write!(out, "{}{:8} {} ", prefix, project_type, columns)?;
+                if self.git_info {
+                    let git = details.git.unwrap_or_default();
+                    write!(
+                        out,
+                        "{:16} {:40} ",
+                        git.branch.as_deref().unwrap_or("-"),
+                        git.origin.as_deref().unwrap_or("-")
+                    )?;
+                }
+                if let Some(size) = details.disk_usage {
+                    write!(out, "{:>6} ", size.map_or_else(|| "-".into(), format_size))?;
+                }
+                self.write_path(&mut out, path)?;
+                writeln!(out)
+            }
+            Format::Json => {
+                let object = json_object(event, path, found, details);
+                writeln!(out, "{}", serde_json::Value::Object(object))
+            }
+            Format::Csv | Format::Tsv | Format::Markdown => {
+                let columns = self.columns(event, &details);
+                self.write_header(&mut out, &columns)?;
+                let cells = (columns.iter()).map(|column| {
+                    Cow::Owned(column.value(self.format, event, path, found, &details))
+                });
+                self.write_row(&mut out, cells)
+            }
+        }
+    }
+
+    /// The columns of the table formats. They're the same for every
+    /// row of a search, which is what lets them share a header.
+    fn columns(&self, event: Option<Event>, details: &Details) -> Vec<Column> {
+        let mut columns = Vec::new();
+        if event.is_some() {
+            columns.push(Column::Event);
+        }
+        match self.format {
+            // Markdown is for people, who don't need the details.
+            Format::Markdown => columns.extend([Column::Path, Column::Type, Column::Modified]),
+            _ => columns.extend([
+                Column::Path,
+                Column::Sentinel,
+                Column::Type,
+                Column::Depth,
+                Column::Modified,
+            ]),
+        }
+        if self.git_info {
+            columns.extend([Column::Branch, Column::Origin]);
+        }
+        if details.disk_usage.is_some() {
+            columns.push(Column::DiskUsage);
+        }
+        columns
+    }
+
+    /// Writes the header row of a table, unless it's already written.
+    fn write_header(&self, out: &mut impl Write, columns: &[Column]) -> io::Result<()> {
+        if self.wrote_header.swap(true, Ordering::Relaxed) {
+            return Ok(());
+        }
+        if let Format::Markdown = self.format {
+            let titles = columns.iter().map(|column| Cow::Borrowed(column.title()));
+            self.write_row(out, titles)?;
+            return self.write_row(out, columns.iter().map(|_| Cow::Borrowed("---")));
+        }
+        let names = columns.iter().map(|column| Cow::Borrowed(column.name()));
+        self.write_row(out, names)
+    }
+
+    fn write_row<'a>(
+        &self,
+        out: &mut impl Write,
+        cells: impl Iterator<Item = Cow<'a, str>>,
+    ) -> io::Result<()> {
+        let (start, separator, end) = match self.format {
+            Format::Tsv => ("", "\t", ""),
+            Format::Markdown => ("| ", " | ", " |"),
+            _ => ("", ",", ""),
+        };
+        let escape = match self.format {
+            Format::Tsv => escape_tsv,
+            Format::Markdown => escape_markdown,
+            _ => escape_csv,
+        };
+        write!(out, "{}", start)?;
+        for (i, cell) in cells.enumerate() {
+            if i > 0 {
+                out.write_all(separator.as_bytes())?;
+            }
+            out.write_all(escape(&cell).as_bytes())?;
+        }
+        writeln!(out, "{}", end)
+    }
+
+    fn prefix(&self, event: Option<Event>) -> String {
+        match event {
+            None => String::new(),
+            Some(Event::Added) if self.color => "+ ".green().to_string(),
+            Some(Event::Removed) if self.color => "- ".red().to_string(),
+            Some(Event::Added) => "+ ".to_string(),
+            Some(Event::Removed) => "- ".to_string(),
+        }
+    }
+
+    /// Writes `path` as is, even if it isn't valid UTF-8, so that it can
+    /// be passed on to other programs. Its name is highlighted.
+    fn write_path(&self, out: &mut impl Write, path: &Path) -> io::Result<()> {
+        let path = path_bytes(path);
+        if !self.color {
+            return out.write_all(&path);
+        }
+
+        let name_start = path
+            .iter()
+            .rposition(|&byte| std::path::is_separator(byte as char))
+            .map_or(0, |separator| separator + 1);
+        let (parent, name) = path.split_at(name_start);
+        out.write_all(parent)?;
+        queue!(
+            out,
+            style::SetForegroundColor(style::Color::Blue),
+            style::SetAttribute(style::Attribute::Bold)
+        )?;
+        out.write_all(name)?;
+        queue!(out, style::SetAttribute(style::Attribute::Reset))
+    }
+}
+
+fn json_object(
+    event: Option<Event>,
+    path: &Path,
+    found: Option<&Match>,
+    details: Details,
+) -> serde_json::Map<String, serde_json::Value> {
+    let mut object = serde_json::Map::new();
+    if let Some(event) = event {
+        object.insert("event".into(), event.name().into());
+    }
+    object.insert("path".into(), path.to_string_lossy().into());
+    if let Some(found) = found {
+        object.insert(
+            "sentinel".into(),
+            (details.sentinel.as_deref())
+                .unwrap_or(&found.sentinel)
+                .to_string_lossy()
+                .into(),
+        );
+        object.insert("patterns".into(), found.patterns.clone().into());
+        object.insert(
+            "type".into(),
+            details.project_type.map(ProjectType::name).into(),
+        );
+        object.insert("depth".into(), found.depth.into());
+    }
+    if let Some(metadata) = details.metadata {
+        metadata.insert_into(&mut object);
+    }
+    if let Some(git) = details.git {
+        object.insert("branch".into(), git.branch.into());
+        object.insert("origin".into(), git.origin.into());
+    }
+    if let Some(size) = details.disk_usage {
+        object.insert("disk_usage".into(), size.into());
+    }
+    object
+}
+
+/// What the table formats can show about a match, one per column.
+#[derive(Clone, Copy)]
+enum Column {
+    Event,
+    Path,
+    Sentinel,
+    Type,
+    Depth,
+    /// The sentinel's modification time, in UTC.
+    Modified,
+    Branch,
+    Origin,
+    DiskUsage,
+}
+
+impl Column {
+    /// The column's name in the header row.
+    fn name(self) -> &'static str {
+        match self {
+            Column::Event => "event",
+            Column::Path => "path",
+            Column::Sentinel => "sentinel",
+            Column::Type => "type",
+            Column::Depth => "depth",
+            Column::Modified => "modified",
+            Column::Branch => "branch",
+            Column::Origin => "origin",
+            Column::DiskUsage => "disk_usage",
+        }
+    }
+
+    /// The column's name in the header row of a Markdown table.
+    fn title(self) -> &'static str {
+        match self {
+            Column::Event => "Event",
+            Column::Path => "Path",
+            Column::Sentinel => "Sentinel",
+            Column::Type => "Type",
+            Column::Depth => "Depth",
+            Column::Modified => "Last modified (UTC)",
+            Column::Branch => "Branch",
+            Column::Origin => "Origin",
+            Column::DiskUsage => "Disk usage",
+        }
+    }
+
+    /// The cell in this column for a row. Anything unknown is empty.
+    fn value(
+        self,
+        format: Format,
+        event: Option<Event>,
+        path: &Path,
+        found: Option<&Match>,
+        details: &Details,
+    ) -> String {
+        let git = details.git.as_ref();
+        let value = match self {
+            Column::Event => event.map(|event| event.name().to_string()),
+            Column::Path => Some(path.to_string_lossy().into_owned()),
+            Column::Sentinel => (details.sentinel.as_deref())
+                .map(|sentinel| sentinel.to_string_lossy().into_owned()),
+            Column::Type => details.project_type.map(|t| t.name().to_string()),
+            Column::Depth => found.map(|found| found.depth.to_string()),
+            Column::Modified => (details.metadata.as_ref())
+                .and_then(|metadata| metadata.modified)
+                .map(format_time),
+            Column::Branch => git.and_then(|git| git.branch.clone()),
+            Column::Origin => git.and_then(|git| git.origin.clone()),
+            Column::DiskUsage => (details.disk_usage.flatten()).map(|size| match format {
+                Format::Markdown => format_size(size),
+                _ => size.to_string(),
+            }),
+        };
+        value.unwrap_or_default()
+    }
+}
+
+/// Quotes a CSV cell if it has to be, doubling any quotes inside it,
+/// as RFC 4180 says.
+fn escape_csv(cell: &str) -> Cow<'_, str> {
+    if cell.contains([',', '"', '\r', '\n']) {
+        Cow::Owned(format!("\"{}\"", cell.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(cell)
+    }
+}
+
+/// TSV has no quoting, so tabs and line breaks are written as `\t`,
+/// `\n` and `\r` instead. Backslashes are left alone, since they're
+/// the path separator on Windows.
+fn escape_tsv(cell: &str) -> Cow<'_, str> {
+    if !cell.contains(['\t', '\n', '\r']) {
+        return Cow::Borrowed(cell);
+    }
+    let mut escaped = String::with_capacity(cell.len() + 2);
+    for c in cell.chars() {
+        match c {
+            '\t' => escaped.push_str("\\t"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c),
+        }
+    }
+    Cow::Owned(escaped)
+}
+
+/// Escapes the characters which would break a Markdown table cell, or
+/// be taken as formatting, like the `_` in `my_project`. Line breaks
+/// can't be in a cell at all, so they become `<br>`.
+fn escape_markdown(cell: &str) -> Cow<'_, str> {
+    const SPECIAL: [char; 8] = ['\\', '|', '*', '_', '`', '[', ']', '<'];
+    if !cell.contains(SPECIAL) && !cell.contains(['\n', '\r']) {
+        return Cow::Borrowed(cell);
+    }
+    let mut escaped = String::with_capacity(cell.len() + 2);
+    for c in cell.chars() {
+        match c {
+            '\r' => {}
+            '\n' => escaped.push_str("<br>"),
+            c if SPECIAL.contains(&c) => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            c => escaped.push(c),
+        }
+    }
+    Cow::Owned(escaped)
+}
+
+#[derive(Clone, Copy)]
+enum Event {
+    Added,
+    Removed,
+}
+
+impl Event {
+    fn name(self) -> &'static str {
+        match self {
+            Event::Added => "added",
+            Event::Removed => "removed",
+        }
+    }
+}
+
+/// What the format shows about a match besides its path.
+#[derive(Default)]
+struct Details {
+    /// The match's sentinel, as it's shown.
+    sentinel: Option<PathBuf>,
+    metadata: Option<Metadata>,
+    project_type: Option<ProjectType>,
+    /// Only with `--git-info`.
+    git: Option<GitInfo>,
+    /// Only with `--du`, and `None` inside if it couldn't be worked out.
+    disk_usage: Option<Option<u64>>,
+}
+
+/// What we show about a match's sentinel in the long and JSON formats.
+struct Metadata {
+    /// Seconds since the Unix epoch.
+    modified: Option<u64>,
+    owner: Option<String>,
+    size: Option<u64>,
+}
+
+impl Metadata {
+    /// Looks up `sentinel` on `file_system`, following symlinks where
+    /// possible. Anything we can't find out is left empty rather than
+    /// failing the search.
+    fn of(file_system: &dyn FileSystem, sentinel: &Path) -> Self {
+        // A dangling symlink can still be described, if it's on disk.
+        let metadata = (file_system.metadata(sentinel))
+            .or_else(|_| fs::symlink_metadata(sentinel).map(Into::into));
+        let Ok(metadata) = metadata else {
+            return Metadata {
+                modified: None,
+                owner: None,
+                size: None,
+            };
+        };
+        Metadata {
+            modified: metadata
+                .modified
+                .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                .map(|since_epoch| since_epoch.as_secs()),
+            owner: metadata.uid.and_then(owner),
+            size: metadata.size,
+        }
+    }
+
+    fn columns(&self) -> String {
+        let modified = self.modified.map_or_else(|| "-".to_string(), format_time);
+        let size = self
+            .size
+            .map_or_else(|| "-".to_string(), |size| size.to_string());
+        format!(
+            "{:16} {:>8} {:>10}",
+            modified,
+            self.owner.as_deref().unwrap_or("-"),
+            size
+        )
+    }
+
+    fn insert_into(self, object: &mut serde_json::Map<String, serde_json::Value>) {
+        object.insert("modified".into(), self.modified.into());
+        object.insert("owner".into(), self.owner.into());
+        object.insert("size".into(), self.size.into());
+    }
+}
+
+#[cfg(unix)]
+fn owner(uid: u32) -> Option<String> {
+    Some(match uzers::get_user_by_uid(uid) {
+        Some(user) => user.name().to_string_lossy().into_owned(),
+        None => uid.to_string(),
+    })
+}
+
+#[cfg(not(unix))]
+fn owner(_uid: u32) -> Option<String> {
+    None
+}
+
+#[cfg(unix)]
+pub fn path_bytes(path: &Path) -> Cow<'_, [u8]> {
+    use std::os::unix::ffi::OsStrExt;
+
+    Cow::Borrowed(path.as_os_str().as_bytes())
+}
+
+/// Paths on other platforms aren't bytes, so the best we can do is
+/// replace anything which isn't valid Unicode.
+#[cfg(not(unix))]
+pub fn path_bytes(path: &Path) -> Cow<'_, [u8]> {
+    match path.to_string_lossy() {
+        Cow::Borrowed(path) => Cow::Borrowed(path.as_bytes()),
+        Cow::Owned(path) => Cow::Owned(path.into_bytes()),
+    }
+}
+
+/// Formats a number of bytes the way `du -h` does, e.g. `512`, `4.0K`
+/// or `12G`.
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 6] = ["K", "M", "G", "T", "P", "E"];
+    if bytes < 1024 {
+        return bytes.to_string();
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < UNITS.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if size < 10.0 {
+        format!("{:.1}{}", size, UNITS[unit])
+    } else {
+        format!("{:.0}{}", size, UNITS[unit])
+    }
+}
+
+/// Formats seconds since the Unix epoch as a UTC `YYYY-MM-DD HH:MM`.
+fn format_time(secs: u64) -> String {
+    let days = (secs / 86400) as i64;
+    let minutes = secs % 86400 / 60;
+
+    // Howard Hinnant's days_from_civil, in reverse.
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}",
+        year,
+        month,
+        day,
+        minutes / 60,
+        minutes % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv_quotes_only_when_needed() {
+        assert_eq!(escape_csv("/src/pj"), "/src/pj");
+        assert!(matches!(escape_csv("/src/pj"), Cow::Borrowed(_)));
+        assert_eq!(escape_csv("a,b"), "\"a,b\"");
+        assert_eq!(escape_csv("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(escape_csv("two\nlines"), "\"two\nlines\"");
+        assert_eq!(escape_csv("cr\r"), "\"cr\r\"");
+    }
+
+    #[test]
+    fn tsv_escapes_tabs_and_line_breaks() {
+        assert_eq!(escape_tsv(r"C:\src\pj"), r"C:\src\pj");
+        assert_eq!(escape_tsv("a\tb"), r"a\tb");
+        assert_eq!(escape_tsv("a\r\nb"), r"a\r\nb");
+        assert_eq!(escape_tsv("a,\"b\""), "a,\"b\"");
+    }
+
+    #[test]
+    fn markdown_escapes_table_and_formatting_characters() {
+        assert_eq!(escape_markdown("/src/pj"), "/src/pj");
+        assert_eq!(escape_markdown("my_project"), r"my\_project");
+        assert_eq!(escape_markdown("a|b"), r"a\|b");
+        assert_eq!(escape_markdown(r"C:\src"), r"C:\\src");
+        assert_eq!(escape_markdown("*[x]*`<y>`"), r"\*\[x\]\*\`\<y>\`");
+        assert_eq!(escape_markdown("two\r\nlines"), "two<br>lines");
+    }
+}
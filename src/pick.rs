@@ -0,0 +1,247 @@
+use std::io;
+use std::io::Write;
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+
+use crossbeam::channel;
+use crossbeam::channel::Receiver;
+use crossterm::cursor;
+use crossterm::event;
+use crossterm::event::Event;
+use crossterm::event::KeyCode;
+use crossterm::event::KeyEvent;
+use crossterm::event::KeyEventKind;
+use crossterm::event::KeyModifiers;
+use crossterm::queue;
+use crossterm::style;
+use crossterm::style::Stylize;
+use crossterm::terminal;
+use pj::long_path::to_display;
+
+/// Shows `candidates` in a fuzzy-filterable list as they stream in,
+/// returning the one the user picked, or None if they cancelled.
+///
+/// The UI is drawn on stderr so that stdout is left for the selection.
+pub fn pick<I>(candidates: I) -> anyhow::Result<Option<PathBuf>>
+where
+    I: IntoIterator<Item = PathBuf>,
+    I::IntoIter: Send + 'static,
+{
+    let (sender, receiver) = channel::unbounded();
+    let candidates = candidates.into_iter();
+    thread::spawn(move || {
+        for candidate in candidates {
+            if sender.send(candidate).is_err() {
+                break;
+            }
+        }
+    });
+
+    let _terminal = Terminal::enter()?;
+    Picker::new(receiver).run(&mut io::stderr())
+}
+
+/// Puts stderr's terminal into raw mode on the alternate screen, and
+/// puts it back when dropped, however the picker ends.
+struct Terminal;
+
+impl Terminal {
+    fn enter() -> anyhow::Result<Self> {
+        terminal::enable_raw_mode()?;
+        // From here on, dropping the guard undoes the above even if
+        // the rest fails.
+        let guard = Terminal;
+        queue!(io::stderr(), terminal::EnterAlternateScreen, cursor::Hide)?;
+        Ok(guard)
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        // There's nothing more we can do if these fail.
+        let mut stderr = io::stderr();
+        let _ = queue!(stderr, cursor::Show, terminal::LeaveAlternateScreen);
+        let _ = stderr.flush();
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+struct Picker {
+    receiver: Receiver<PathBuf>,
+    candidates: Vec<(PathBuf, String)>,
+    query: String,
+    /// Indices into `candidates` which match `query`, best first.
+    filtered: Vec<usize>,
+    selected: usize,
+}
+
+impl Picker {
+    fn new(receiver: Receiver<PathBuf>) -> Self {
+        Picker {
+            receiver,
+            candidates: Vec::new(),
+            query: String::new(),
+            filtered: Vec::new(),
+            selected: 0,
+        }
+    }
+
+    fn run(mut self, out: &mut impl Write) -> anyhow::Result<Option<PathBuf>> {
+        let mut dirty = true;
+        loop {
+            if self.receive() {
+                self.refilter();
+                dirty = true;
+            }
+            if dirty {
+                self.draw(out)?;
+                dirty = false;
+            }
+
+            if !event::poll(Duration::from_millis(50))? {
+                continue;
+            }
+            dirty = true;
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            if key.kind == KeyEventKind::Release {
+                continue;
+            }
+            match self.handle_key(key) {
+                Action::Continue => {}
+                Action::Cancel => return Ok(None),
+                Action::Accept => {
+                    let selected = self.filtered.get(self.selected);
+                    return Ok(selected.map(|&i| self.candidates[i].0.clone()));
+                }
+            }
+        }
+    }
+
+    /// Pulls in any candidates found since the last frame.
+    fn receive(&mut self) -> bool {
+        let mut received = false;
+        for candidate in self.receiver.try_iter() {
+            let display = to_display(&candidate).display().to_string();
+            self.candidates.push((candidate, display));
+            received = true;
+        }
+        received
+    }
+
+    fn handle_key(&mut self, key: KeyEvent) -> Action {
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Esc => return Action::Cancel,
+            KeyCode::Char('c') | KeyCode::Char('g') if ctrl => return Action::Cancel,
+            KeyCode::Enter => return Action::Accept,
+            KeyCode::Up => self.move_selection(-1),
+            KeyCode::Char('p') | KeyCode::Char('k') if ctrl => self.move_selection(-1),
+            KeyCode::Down => self.move_selection(1),
+            KeyCode::Char('n') | KeyCode::Char('j') if ctrl => self.move_selection(1),
+            KeyCode::Char('u') if ctrl => {
+                self.query.clear();
+                self.refilter();
+            }
+            KeyCode::Backspace => {
+                self.query.pop();
+                self.refilter();
+            }
+            KeyCode::Char(c) if !ctrl => {
+                self.query.push(c);
+                self.refilter();
+            }
+            _ => {}
+        }
+        Action::Continue
+    }
+
+    fn move_selection(&mut self, delta: isize) {
+        let last = self.filtered.len().saturating_sub(1);
+        self.selected = self.selected.saturating_add_signed(delta).min(last);
+    }
+
+    fn refilter(&mut self) {
+        let mut scored: Vec<(usize, usize)> = self
+            .candidates
+            .iter()
+            .enumerate()
+            .filter_map(|(i, (_, display))| Some((fuzzy_score(&self.query, display)?, i)))
+            .collect();
+        // Stable, so equally good matches stay in the order they were found.
+        scored.sort_by_key(|&(score, _)| score);
+        self.filtered = scored.into_iter().map(|(_, i)| i).collect();
+        self.selected = self.selected.min(self.filtered.len().saturating_sub(1));
+    }
+
+    fn draw(&self, out: &mut impl Write) -> anyhow::Result<()> {
+        let (width, height) = terminal::size()?;
+        let width = width as usize;
+        let rows = (height as usize).saturating_sub(2);
+
+        queue!(out, terminal::Clear(terminal::ClearType::All))?;
+        queue!(
+            out,
+            cursor::MoveTo(0, 0),
+            style::Print(format!("> {}", self.query)),
+            cursor::MoveTo(0, 1),
+            style::Print(
+                format!("  {}/{}", self.filtered.len(), self.candidates.len()).dark_grey()
+            ),
+        )?;
+
+        // Scroll so the selection is always on screen.
+        let offset = (self.selected + 1).saturating_sub(rows);
+        for (row, &i) in self.filtered.iter().skip(offset).take(rows).enumerate() {
+            let display: String = self.candidates[i]
+                .1
+                .chars()
+                .take(width.saturating_sub(2))
+                .collect();
+            queue!(out, cursor::MoveTo(0, row as u16 + 2))?;
+            if offset + row == self.selected {
+                queue!(out, style::Print(format!("> {}", display).reverse()))?;
+            } else {
+                queue!(out, style::Print(format!("  {}", display)))?;
+            }
+        }
+        out.flush()?;
+        Ok(())
+    }
+}
+
+enum Action {
+    Continue,
+    Cancel,
+    Accept,
+}
+
+/// Scores how well `candidate` matches `query` as a case-insensitive
+/// subsequence, lower being better, or None if it doesn't match.
+/// Matches are penalized for the gaps between matched characters and
+/// for how far from the end of the path they start, so that queries
+/// tend to match project names rather than their parent directories.
+fn fuzzy_score(query: &str, candidate: &str) -> Option<usize> {
+    if query.is_empty() {
+        return Some(0);
+    }
+
+    let candidate: Vec<char> = candidate.chars().flat_map(char::to_lowercase).collect();
+    let mut score = 0;
+    let mut first = None;
+    let mut last = 0;
+    let mut position = 0;
+    for q in query.chars().flat_map(char::to_lowercase) {
+        let found = candidate[position..].iter().position(|&c| c == q)? + position;
+        if first.is_none() {
+            first = Some(found);
+        } else {
+            score += found - last - 1;
+        }
+        last = found;
+        position = found + 1;
+    }
+    Some(score + (candidate.len() - first?) / 4)
+}
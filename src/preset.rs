@@ -0,0 +1,66 @@
+use std::str::FromStr;
+
+use anyhow::anyhow;
+
+/// A named set of sentinel patterns for a common kind of project.
+#[derive(Debug)]
+pub struct Preset {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub patterns: &'static [&'static str],
+}
+
+pub const PRESETS: &[Preset] = &[
+    Preset {
+        name: "vcs",
+        description: "Version control repositories",
+        patterns: &[r"\.git", r"\.hg", r"\.svn", r"\.jj"],
+    },
+    Preset {
+        name: "rust",
+        description: "Cargo packages and workspaces",
+        patterns: &[r"Cargo\.toml"],
+    },
+    Preset {
+        name: "node",
+        description: "npm, yarn and pnpm packages",
+        patterns: &[r"package\.json"],
+    },
+    Preset {
+        name: "go",
+        description: "Go modules",
+        patterns: &[r"go\.mod"],
+    },
+    Preset {
+        name: "python",
+        description: "Python packages and projects",
+        patterns: &[
+            r"pyproject\.toml",
+            r"setup\.py",
+            r"setup\.cfg",
+            r"requirements\.txt",
+            r"Pipfile",
+        ],
+    },
+    Preset {
+        name: "ruby",
+        description: "Bundler projects",
+        patterns: &[r"Gemfile"],
+    },
+    Preset {
+        name: "java",
+        description: "Maven and Gradle projects",
+        patterns: &[r"pom\.xml", r"build\.gradle(\.kts)?"],
+    },
+];
+
+impl FromStr for &'static Preset {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        PRESETS
+            .iter()
+            .find(|preset| preset.name == s)
+            .ok_or_else(|| anyhow!("Unknown preset {:?}, see `pj presets`", s))
+    }
+}
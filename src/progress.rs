@@ -0,0 +1,104 @@
+use std::io;
+use std::io::IsTerminal;
+use std::io::Write;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use pj::StatsHandle;
+
+/// Searches which finish quicker than this never show progress, so
+/// that it doesn't flicker.
+const DELAY: Duration = Duration::from_millis(200);
+const INTERVAL: Duration = Duration::from_millis(100);
+
+/// A line on stderr showing how far a search has got, which is cleared
+/// once the search is done.
+pub struct Progress {
+    line: Arc<ProgressLine>,
+    done: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Progress {
+    /// Starts reporting on the search behind `stats`, unless stderr
+    /// isn't a terminal.
+    pub fn start(stats: StatsHandle) -> Option<Self> {
+        if !io::stderr().is_terminal() {
+            return None;
+        }
+        let line = Arc::new(ProgressLine::default());
+        let done = Arc::new(AtomicBool::new(false));
+        let thread = thread::spawn({
+            let line = line.clone();
+            let done = done.clone();
+            move || {
+                thread::park_timeout(DELAY);
+                while !done.load(Ordering::Relaxed) {
+                    let stats = stats.stats();
+                    line.draw(&format!(
+                        "Scanned {} directories, found {} matches",
+                        stats.dirs_scanned, stats.matches
+                    ));
+                    thread::park_timeout(INTERVAL);
+                }
+            }
+        });
+        Some(Progress {
+            line,
+            done,
+            thread: Some(thread),
+        })
+    }
+
+    /// The line, so that whoever prints results can clear it first.
+    pub fn line(&self) -> Arc<ProgressLine> {
+        self.line.clone()
+    }
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        self.done.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            thread.thread().unpark();
+            let _ = thread.join();
+        }
+        self.line.suspend(|| {});
+    }
+}
+
+/// The progress text currently on screen, if any.
+#[derive(Default)]
+pub struct ProgressLine {
+    drawn: Mutex<bool>,
+}
+
+impl ProgressLine {
+    fn draw(&self, text: &str) {
+        let mut drawn = self.drawn.lock().unwrap();
+        let mut stderr = io::stderr().lock();
+        // \r and "erase line" rather than crossterm, so this works the
+        // same however the terminal is set up.
+        let _ = write!(stderr, "\r\x1b[2K{}", text);
+        let _ = stderr.flush();
+        *drawn = true;
+    }
+
+    /// Erases the line, if it's drawn, and keeps it off the screen while
+    /// `f` prints something else. It's redrawn on the next update.
+    pub fn suspend<R>(&self, f: impl FnOnce() -> R) -> R {
+        let mut drawn = self.drawn.lock().unwrap();
+        if *drawn {
+            let mut stderr = io::stderr().lock();
+            let _ = write!(stderr, "\r\x1b[2K");
+            let _ = stderr.flush();
+            *drawn = false;
+        }
+        f()
+    }
+}
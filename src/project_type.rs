@@ -0,0 +1,221 @@
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::anyhow;
+use serde::Deserialize;
+use serde::Serialize;
+
+/// What kind of project a directory is, judged by its sentinel.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(try_from = "String", into = "&'static str")]
+pub enum ProjectType {
+    Rust,
+    Node,
+    Go,
+    Python,
+    Ruby,
+    Java,
+    Dotnet,
+    Php,
+    Elixir,
+    Haskell,
+    Cmake,
+    Make,
+    Nix,
+    Git,
+    Hg,
+    Svn,
+    Jj,
+}
+
+/// Everything we know about a project type.
+struct TypeInfo {
+    project_type: ProjectType,
+    name: &'static str,
+    /// The names of its sentinels.
+    file_names: &'static [&'static str],
+    /// Endings of sentinel names, for sentinels named after the project.
+    suffixes: &'static [&'static str],
+}
+
+/// Every project type. Anything about a type goes here, so that adding
+/// one is a matter of adding its entry.
+const TYPES: &[TypeInfo] = &[
+    TypeInfo {
+        project_type: ProjectType::Rust,
+        name: "rust",
+        file_names: &["Cargo.toml"],
+        suffixes: &[],
+    },
+    TypeInfo {
+        project_type: ProjectType::Node,
+        name: "node",
+        file_names: &["package.json"],
+        suffixes: &[],
+    },
+    TypeInfo {
+        project_type: ProjectType::Go,
+        name: "go",
+        file_names: &["go.mod"],
+        suffixes: &[],
+    },
+    TypeInfo {
+        project_type: ProjectType::Python,
+        name: "python",
+        file_names: &[
+            "pyproject.toml",
+            "setup.py",
+            "setup.cfg",
+            "requirements.txt",
+            "Pipfile",
+        ],
+        suffixes: &[],
+    },
+    TypeInfo {
+        project_type: ProjectType::Ruby,
+        name: "ruby",
+        file_names: &["Gemfile"],
+        suffixes: &[],
+    },
+    TypeInfo {
+        project_type: ProjectType::Java,
+        name: "java",
+        file_names: &["pom.xml", "build.gradle", "build.gradle.kts"],
+        suffixes: &[],
+    },
+    TypeInfo {
+        project_type: ProjectType::Dotnet,
+        name: "dotnet",
+        file_names: &[],
+        suffixes: &[".sln", ".csproj"],
+    },
+    TypeInfo {
+        project_type: ProjectType::Php,
+        name: "php",
+        file_names: &["composer.json"],
+        suffixes: &[],
+    },
+    TypeInfo {
+        project_type: ProjectType::Elixir,
+        name: "elixir",
+        file_names: &["mix.exs"],
+        suffixes: &[],
+    },
+    TypeInfo {
+        project_type: ProjectType::Haskell,
+        name: "haskell",
+        file_names: &["stack.yaml"],
+        suffixes: &[".cabal"],
+    },
+    TypeInfo {
+        project_type: ProjectType::Cmake,
+        name: "cmake",
+        file_names: &["CMakeLists.txt"],
+        suffixes: &[],
+    },
+    TypeInfo {
+        project_type: ProjectType::Make,
+        name: "make",
+        file_names: &["Makefile", "makefile", "GNUmakefile"],
+        suffixes: &[],
+    },
+    TypeInfo {
+        project_type: ProjectType::Nix,
+        name: "nix",
+        file_names: &["flake.nix", "default.nix", "shell.nix"],
+        suffixes: &[],
+    },
+    TypeInfo {
+        project_type: ProjectType::Git,
+        name: "git",
+        file_names: &[".git"],
+        suffixes: &[],
+    },
+    TypeInfo {
+        project_type: ProjectType::Hg,
+        name: "hg",
+        file_names: &[".hg"],
+        suffixes: &[],
+    },
+    TypeInfo {
+        project_type: ProjectType::Svn,
+        name: "svn",
+        file_names: &[".svn"],
+        suffixes: &[],
+    },
+    TypeInfo {
+        project_type: ProjectType::Jj,
+        name: "jj",
+        file_names: &[".jj"],
+        suffixes: &[],
+    },
+];
+
+/// The name of every project type, in the order of [`TYPES`].
+const NAMES: [&str; TYPES.len()] = {
+    let mut names = [""; TYPES.len()];
+    let mut i = 0;
+    while i < TYPES.len() {
+        names[i] = TYPES[i].name;
+        i += 1;
+    }
+    names
+};
+
+impl ProjectType {
+    pub const NAMES: &'static [&'static str] = &NAMES;
+
+    /// Recognizes the well-known sentinel `file_name`. Exact names are
+    /// checked before suffixes.
+    pub fn of(file_name: &str) -> Option<Self> {
+        let info = (TYPES.iter())
+            .find(|info| info.file_names.contains(&file_name))
+            .or_else(|| {
+                TYPES
+                    .iter()
+                    .find(|info| (info.suffixes.iter()).any(|suffix| file_name.ends_with(suffix)))
+            })?;
+        Some(info.project_type)
+    }
+
+    pub fn name(self) -> &'static str {
+        self.info().name
+    }
+
+    fn info(self) -> &'static TypeInfo {
+        (TYPES.iter())
+            .find(|info| info.project_type == self)
+            .expect("every project type is in TYPES")
+    }
+}
+
+impl fmt::Display for ProjectType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for ProjectType {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        (TYPES.iter())
+            .find(|info| info.name == s)
+            .map(|info| info.project_type)
+            .ok_or_else(|| anyhow!("Unknown project type {:?}", s))
+    }
+}
+
+impl TryFrom<String> for ProjectType {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> anyhow::Result<Self> {
+        s.parse()
+    }
+}
+
+impl From<ProjectType> for &'static str {
+    fn from(project_type: ProjectType) -> Self {
+        project_type.name()
+    }
+}
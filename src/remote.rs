@@ -0,0 +1,512 @@
+//! Searching other machines over SSH, for `--remote`. If pj is
+//! installed on the other end, it runs the search there and sends back
+//! what it finds. Otherwise we search from here over SFTP.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fmt;
+use std::io;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Read;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Child;
+use std::process::Command;
+use std::process::Stdio;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+
+use anyhow::anyhow;
+use anyhow::Context as _;
+use crossbeam::channel;
+use crossbeam::channel::Receiver;
+use crossbeam::channel::Sender;
+use pj::file_system::FileSystem;
+use pj::file_system::FileType;
+use pj::file_system::Metadata;
+use pj::file_system::ReadDir;
+use pj::file_system::ReadSeek;
+use pj::FinderBuilder;
+use pj::Match;
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::log;
+
+/// The exit status ssh uses when it couldn't connect, as opposed to the
+/// remote command failing.
+const SSH_FAILED: i32 = 255;
+
+/// A match from a remote, with its sentinel's metadata if the remote
+/// sent it, since it can't be looked up from here.
+type Found = (Match, Option<Metadata>);
+
+/// A directory on another machine, written like scp's `USER@HOST:PATH`.
+/// The path is relative to the home directory unless it's absolute,
+/// and may start with `~/` to say so explicitly.
+#[derive(Clone, Debug)]
+pub struct Remote {
+    pub host: String,
+    pub path: PathBuf,
+}
+
+impl FromStr for Remote {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s.split_once(':') {
+            // A leading `-` would be taken as an option by ssh.
+            Some((host, path)) if !host.is_empty() && !host.starts_with('-') => {
+                // Nothing expands `~` on the other end, but searches
+                // start from the home directory anyway.
+                let path = match path.strip_prefix("~/") {
+                    Some(path) => path,
+                    None if path == "~" => "",
+                    None => path,
+                };
+                Ok(Remote {
+                    host: host.to_string(),
+                    path: PathBuf::from(if path.is_empty() { "." } else { path }),
+                })
+            }
+            _ => Err(anyhow!(
+                "Expected a remote like user@host:path, got {:?}",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Remote {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.host, self.path.display())
+    }
+}
+
+impl Remote {
+    /// Runs `builder`, whose only root should be this remote's path,
+    /// passing on each match as it's found.
+    fn search(
+        &self,
+        builder: FinderBuilder,
+        found: &Sender<Found>,
+        processes: &Processes,
+    ) -> anyhow::Result<()> {
+        if self.search_with_pj(&builder, found, processes)? {
+            return Ok(());
+        }
+        self.search_with_sftp(builder, found, processes)
+    }
+
+    /// Runs the search with pj on the remote. Returns false if that's
+    /// not possible, e.g. because pj isn't installed there.
+    fn search_with_pj(
+        &self,
+        builder: &FinderBuilder,
+        found: &Sender<Found>,
+        processes: &Processes,
+    ) -> anyhow::Result<bool> {
+        let mut ssh = Command::new("ssh")
+            .arg(&self.host)
+            .arg("pj remote-search")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .context("Failed to run ssh")?;
+        let mut stdin = ssh.stdin.take().unwrap();
+        let stdout = ssh.stdout.take().unwrap();
+        let mut stderr = ssh.stderr.take().unwrap();
+        let ssh = processes.add(ssh);
+        // Whatever goes wrong is only worth showing once we know
+        // whether we're falling back to SFTP.
+        let errors = thread::spawn(move || {
+            let mut errors = Vec::new();
+            let _ = stderr.read_to_end(&mut errors);
+            errors
+        });
+        // If pj isn't there, nothing reads the search, which is fine.
+        let _ = serde_json::to_writer(&mut stdin, builder);
+        drop(stdin);
+
+        let mut any_output = false;
+        for line in BufReader::new(stdout).lines() {
+            any_output = true;
+            let remote_match: RemoteMatch =
+                serde_json::from_str(&line?).context("Invalid response from the remote pj")?;
+            if found.send(self.locate_match(remote_match)).is_err() {
+                // Nothing wants the rest, so there's no point letting
+                // the search go on.
+                let _ = ssh.lock().unwrap().kill();
+                break;
+            }
+        }
+        let status = ssh.lock().unwrap().wait()?;
+        if processes.is_cancelled() {
+            return Ok(true);
+        }
+        let errors = errors.join().unwrap();
+        let errors = String::from_utf8_lossy(&errors);
+        if status.success() {
+            for line in errors.lines() {
+                tracing::warn!(target: log::SUMMARY, "{}: {}", self.host, line);
+            }
+            return Ok(true);
+        }
+        match status.code() {
+            Some(SSH_FAILED) => Err(anyhow!("ssh failed: {}", errors.trim_end())),
+            _ if any_output => Err(anyhow!("{}", errors.trim_end())),
+            _ => {
+                tracing::debug!("Falling back to SFTP for {}: {}", self, errors.trim_end());
+                Ok(false)
+            }
+        }
+    }
+
+    #[cfg(unix)]
+    fn search_with_sftp(
+        &self,
+        builder: FinderBuilder,
+        found: &Sender<Found>,
+        processes: &Processes,
+    ) -> anyhow::Result<()> {
+        let file_system = crate::sftp::SftpFileSystem::connect(&self.host)?;
+        processes.register(file_system.ssh());
+        let finder = builder.file_system(file_system).build()?;
+        if let Some(invalid) = finder.invalid_roots().first() {
+            return Err(anyhow!("{:#}", invalid.error));
+        }
+        for remote_match in finder.run() {
+            if processes.is_cancelled() {
+                break;
+            }
+            let metadata = finder.file_system().metadata(&remote_match.sentinel).ok();
+            let located = Match {
+                path: self.locate(&remote_match.path),
+                sentinel: self.locate(&remote_match.sentinel),
+                patterns: remote_match.patterns,
+                depth: remote_match.depth,
+            };
+            if found.send((located, metadata)).is_err() {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    fn search_with_sftp(
+        &self,
+        _builder: FinderBuilder,
+        _found: &Sender<Found>,
+        _processes: &Processes,
+    ) -> anyhow::Result<()> {
+        Err(anyhow!("pj isn't installed on {}", self.host))
+    }
+
+    /// How a path on this remote is shown, scp style.
+    fn locate(&self, path: &Path) -> PathBuf {
+        let mut located = OsString::from(format!("{}:", self.host));
+        located.push(path);
+        PathBuf::from(located)
+    }
+
+    fn locate_match(&self, remote_match: RemoteMatch) -> Found {
+        // Only a sentinel's type is always known.
+        let metadata = (remote_match.sentinel_type).map(|file_type| Metadata {
+            file_type: match file_type {
+                RemoteFileType::Dir => FileType::Dir,
+                RemoteFileType::File => FileType::File,
+            },
+            modified: (remote_match.modified)
+                .map(|modified| SystemTime::UNIX_EPOCH + Duration::from_secs(modified)),
+            device: None,
+            inode: None,
+            size: remote_match.size,
+            permissions: None,
+            uid: None,
+        });
+        let found = Match {
+            path: self.locate(Path::new(&remote_match.path)),
+            sentinel: self.locate(Path::new(&remote_match.sentinel)),
+            patterns: remote_match.patterns,
+            depth: remote_match.depth,
+        };
+        (found, metadata)
+    }
+}
+
+/// A match as the remote pj sends it back, one per line.
+#[derive(Deserialize, Serialize)]
+struct RemoteMatch {
+    path: String,
+    sentinel: String,
+    /// Missing when the remote pj is older than this one.
+    #[serde(default)]
+    patterns: Vec<String>,
+    depth: usize,
+    /// The rest is about the sentinel, and missing when the remote pj
+    /// is older than this one or couldn't stat it.
+    #[serde(default)]
+    sentinel_type: Option<RemoteFileType>,
+    /// Seconds since the Unix epoch.
+    #[serde(default)]
+    modified: Option<u64>,
+    #[serde(default)]
+    size: Option<u64>,
+}
+
+#[derive(Clone, Copy, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum RemoteFileType {
+    Dir,
+    File,
+}
+
+/// Searches running on remotes in the background. Iterating over it
+/// gives their matches as they're found.
+pub struct RemoteSearch {
+    receiver: Receiver<Found>,
+    /// The metadata of each sentinel found so far, by where it's shown.
+    sentinels: Arc<Mutex<HashMap<PathBuf, Metadata>>>,
+    searches: Vec<(Remote, JoinHandle<anyhow::Result<()>>)>,
+    processes: Processes,
+    close: Sender<()>,
+    closed: Receiver<()>,
+    deadline: Option<Instant>,
+    timed_out: bool,
+}
+
+impl RemoteSearch {
+    /// Starts `searches`, which stop once `timeout` has passed.
+    pub fn start(searches: Vec<(Remote, FinderBuilder)>, timeout: Option<Duration>) -> Self {
+        let (sender, receiver) = channel::unbounded();
+        let (close, closed) = channel::bounded(1);
+        let processes = Processes::default();
+        let searches = (searches.into_iter())
+            .map(|(remote, builder)| {
+                let sender = sender.clone();
+                let thread_remote = remote.clone();
+                let processes = processes.clone();
+                let search =
+                    thread::spawn(move || thread_remote.search(builder, &sender, &processes));
+                (remote, search)
+            })
+            .collect();
+        RemoteSearch {
+            receiver,
+            sentinels: Arc::default(),
+            searches,
+            processes,
+            close,
+            closed,
+            deadline: timeout.map(|timeout| Instant::now() + timeout),
+            timed_out: false,
+        }
+    }
+
+    /// A handle which stops the searches from another thread, e.g. on
+    /// Ctrl-C.
+    pub fn cancel_handle(&self) -> RemoteCancelHandle {
+        RemoteCancelHandle {
+            processes: self.processes.clone(),
+            close: self.close.clone(),
+        }
+    }
+
+    /// Looks up the sentinels of remote matches from what the remotes
+    /// sent back, and everything else on `local`.
+    pub fn file_system(&self, local: Arc<dyn FileSystem>) -> Arc<dyn FileSystem> {
+        Arc::new(WithRemoteSentinels {
+            local,
+            sentinels: self.sentinels.clone(),
+        })
+    }
+
+    /// Whether the searches were stopped because the timeout passed.
+    pub fn timed_out(&self) -> bool {
+        self.timed_out
+    }
+
+    /// Waits for every search to finish, returning the ones which
+    /// failed. Searches which were cancelled are left to stop on their
+    /// own, since they may be stuck waiting for ssh to connect.
+    pub fn finish(self) -> Vec<(Remote, anyhow::Error)> {
+        drop(self.receiver);
+        let cancelled = self.processes.is_cancelled();
+        (self.searches.into_iter())
+            .filter(|(_, search)| !cancelled || search.is_finished())
+            .filter_map(|(remote, search)| Some((remote, search.join().unwrap().err()?)))
+            .collect()
+    }
+}
+
+impl Iterator for RemoteSearch {
+    type Item = Match;
+
+    fn next(&mut self) -> Option<Match> {
+        let (found, metadata) = if self.processes.is_cancelled() {
+            self.receiver.try_recv().ok()?
+        } else {
+            let deadline = self.deadline.map_or_else(channel::never, channel::at);
+            channel::select! {
+                recv(self.receiver) -> found => found.ok()?,
+                recv(self.closed) -> _ => self.receiver.try_recv().ok()?,
+                recv(deadline) -> _ => {
+                    self.timed_out = true;
+                    self.processes.cancel();
+                    return None;
+                }
+            }
+        };
+        if let Some(metadata) = metadata {
+            let mut sentinels = self.sentinels.lock().unwrap();
+            sentinels.insert(found.sentinel.clone(), metadata);
+        }
+        Some(found)
+    }
+}
+
+/// A file system which knows the sentinels of remote matches.
+#[derive(Debug)]
+struct WithRemoteSentinels {
+    local: Arc<dyn FileSystem>,
+    sentinels: Arc<Mutex<HashMap<PathBuf, Metadata>>>,
+}
+
+impl FileSystem for WithRemoteSentinels {
+    fn read_dir(&self, path: &Path) -> io::Result<ReadDir<'_>> {
+        self.local.read_dir(path)
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        match self.sentinels.lock().unwrap().get(path) {
+            Some(metadata) => Ok(metadata.clone()),
+            None => self.local.metadata(path),
+        }
+    }
+
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf> {
+        self.local.read_link(path)
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        self.local.canonicalize(path)
+    }
+
+    fn read(&self, path: &Path, limit: u64) -> io::Result<Vec<u8>> {
+        self.local.read(path, limit)
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn ReadSeek>> {
+        self.local.open(path)
+    }
+
+    fn display<'a>(&self, path: &'a Path) -> Cow<'a, Path> {
+        self.local.display(path)
+    }
+}
+
+/// Stops remote searches, from [`RemoteSearch::cancel_handle`].
+#[derive(Clone)]
+pub struct RemoteCancelHandle {
+    processes: Processes,
+    close: Sender<()>,
+}
+
+impl RemoteCancelHandle {
+    pub fn cancel(&self) {
+        self.processes.cancel();
+        // If it's full, the iterator has already been told.
+        let _ = self.close.try_send(());
+    }
+}
+
+/// The ssh processes remote searches run over. Cancelling kills them,
+/// which stops searches that would otherwise wait on the network.
+#[derive(Clone, Default)]
+struct Processes(Arc<Mutex<ProcessesState>>);
+
+#[derive(Default)]
+struct ProcessesState {
+    cancelled: bool,
+    ssh: Vec<Arc<Mutex<Child>>>,
+}
+
+impl Processes {
+    fn add(&self, ssh: Child) -> Arc<Mutex<Child>> {
+        let ssh = Arc::new(Mutex::new(ssh));
+        self.register(ssh.clone());
+        ssh
+    }
+
+    /// Kills `ssh` when the searches are cancelled, or straight away if
+    /// they already have been.
+    fn register(&self, ssh: Arc<Mutex<Child>>) {
+        let mut state = self.0.lock().unwrap();
+        if state.cancelled {
+            let _ = ssh.lock().unwrap().kill();
+        }
+        state.ssh.push(ssh);
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.0.lock().unwrap().cancelled
+    }
+
+    fn cancel(&self) {
+        let mut state = self.0.lock().unwrap();
+        state.cancelled = true;
+        for ssh in &state.ssh {
+            // If it's locked, it's already being waited for, once the
+            // search has read everything it sent.
+            if let Ok(mut ssh) = ssh.try_lock() {
+                let _ = ssh.kill();
+            }
+        }
+    }
+}
+
+/// Runs on the other end of `--remote`: reads a search from stdin, and
+/// writes each match to stdout as a line of JSON.
+pub fn serve() -> anyhow::Result<()> {
+    let builder: FinderBuilder = serde_json::from_reader(io::stdin().lock())?;
+    let finder = crate::build_finder(builder)?;
+    let file_system = finder.file_system();
+    let mut stdout = io::stdout().lock();
+    for found in finder.run() {
+        let metadata = file_system.metadata(&found.sentinel).ok();
+        let remote_match = RemoteMatch {
+            path: found.path.to_string_lossy().into_owned(),
+            sentinel: found.sentinel.to_string_lossy().into_owned(),
+            patterns: found.patterns,
+            depth: found.depth,
+            sentinel_type: metadata.as_ref().map(|metadata| match metadata.file_type {
+                FileType::Dir => RemoteFileType::Dir,
+                _ => RemoteFileType::File,
+            }),
+            modified: (metadata.as_ref())
+                .and_then(|metadata| {
+                    metadata
+                        .modified?
+                        .duration_since(SystemTime::UNIX_EPOCH)
+                        .ok()
+                })
+                .map(|since_epoch| since_epoch.as_secs()),
+            size: metadata.and_then(|metadata| metadata.size),
+        };
+        serde_json::to_writer(&mut stdout, &remote_match)?;
+        writeln!(stdout)?;
+        stdout.flush()?;
+    }
+    Ok(())
+}
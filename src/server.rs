@@ -0,0 +1,356 @@
+//! Serves index queries over HTTP, so that editor plugins and other
+//! tools can ask pj for projects without spawning a process, and from
+//! languages without Unix sockets.
+//!
+//! There are two endpoints, both answering with JSON:
+//!
+//! - `GET /projects` lists every project in the index.
+//! - `GET /query?pattern=REGEX&root=DIR` lists the projects whose path
+//!   matches `pattern` and which are beneath `root`, either of which
+//!   may be left out, like `pj index query`.
+//!
+//! Successful responses are `{"projects": [...]}`, and failures are
+//! `{"error": "..."}` with a 4xx or 5xx status. Requests whose `Host`
+//! isn't this machine are refused, so that a web page can't reach the
+//! server through a domain name it points at us (DNS rebinding).
+
+use std::io;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Read;
+use std::io::Write;
+use std::net::IpAddr;
+use std::net::TcpListener;
+use std::net::TcpStream;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+use anyhow::Context as _;
+use crossbeam::channel;
+
+use crate::index::IndexCache;
+use crate::index::IndexQuery;
+
+/// Requests with a longer request line or headers than this are
+/// refused, so that a misbehaving client can't use up our memory.
+const MAX_REQUEST_LEN: u64 = 16 * 1024;
+
+/// How many connections are handled at once. Any more wait to be
+/// accepted until one finishes.
+const WORKERS: usize = 8;
+
+/// Listens on `addr`, e.g. `127.0.0.1:7777`, forever, answering
+/// queries from the index at `index_path`. Connections are handled by
+/// a fixed number of threads, and the index is reloaded whenever it
+/// changes on disk. A client gets `timeout` to send its request and to
+/// read the response before it's disconnected.
+pub fn serve(addr: &str, index_path: &Path, timeout: Duration) -> anyhow::Result<()> {
+    let listener =
+        TcpListener::bind(addr).with_context(|| format!("Failed to listen on {}", addr))?;
+    let cache = Arc::new(IndexCache::new(index_path));
+    cache.get()?;
+    eprintln!("Listening on http://{}", listener.local_addr()?);
+
+    // Nothing is queued, so each connection is only accepted once a
+    // worker is ready for it.
+    let (sender, receiver) = channel::bounded::<TcpStream>(0);
+    for _ in 0..WORKERS {
+        let receiver = receiver.clone();
+        let cache = cache.clone();
+        thread::spawn(move || {
+            for stream in receiver {
+                if let Err(e) = handle_client(stream, &cache, timeout) {
+                    tracing::warn!("Failed to answer a request: {:#}", e);
+                }
+            }
+        });
+    }
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => sender.send(stream)?,
+            Err(e) => tracing::warn!("Failed to accept a connection: {}", e),
+        }
+    }
+    Ok(())
+}
+
+fn handle_client(stream: TcpStream, cache: &IndexCache, timeout: Duration) -> anyhow::Result<()> {
+    stream.set_write_timeout(Some(timeout))?;
+    let deadline = Instant::now() + timeout;
+    let mut reader = BufReader::new((&stream).take(MAX_REQUEST_LEN));
+    let local = stream.local_addr()?.ip();
+    let response = match read_request(&mut reader, &stream, deadline) {
+        Ok(request)
+            if !request
+                .host
+                .as_deref()
+                .is_none_or(|host| is_local_host(host, local)) =>
+        {
+            Response::error(
+                403,
+                "Forbidden",
+                "Requests must be addressed to this machine",
+            )
+        }
+        Ok(request) => answer(&request.target, cache),
+        Err(e)
+            if matches!(
+                e.kind(),
+                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+            ) =>
+        {
+            Response::error(408, "Request Timeout", "Timed out reading the request")
+        }
+        Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+            Response::error(400, "Bad Request", &e.to_string())
+        }
+        Err(e) => return Err(e.into()),
+    };
+    response.write_to(&stream)
+}
+
+/// What we use from a request.
+struct Request {
+    /// e.g. `/query?pattern=pj`.
+    target: String,
+    /// The `Host` header, which only HTTP/1.0 clients leave out.
+    host: Option<String>,
+}
+
+/// Reads the request line and headers. Only GET requests are supported,
+/// and any body is ignored.
+fn read_request(
+    reader: &mut impl BufRead,
+    stream: &TcpStream,
+    deadline: Instant,
+) -> io::Result<Request> {
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message);
+    let mut lines = Vec::new();
+    loop {
+        // Each read gets whatever is left of the time for the whole
+        // request, so a client can't trickle it in forever.
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(io::ErrorKind::TimedOut.into());
+        }
+        stream.set_read_timeout(Some(remaining))?;
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(invalid("The request ended early or was too long"));
+        }
+        let line = line.trim_end_matches(['\r', '\n']).to_string();
+        if line.is_empty() {
+            break;
+        }
+        lines.push(line);
+    }
+    let request_line = lines.first().ok_or_else(|| invalid("Empty request"))?;
+    let host = lines[1..].iter().find_map(|header| {
+        let (name, value) = header.split_once(':')?;
+        name.eq_ignore_ascii_case("host")
+            .then(|| value.trim().to_string())
+    });
+    let mut parts = request_line.split(' ');
+    match (parts.next(), parts.next()) {
+        (Some("GET"), Some(target)) => Ok(Request {
+            target: target.to_string(),
+            host,
+        }),
+        (Some(_), Some(_)) => Err(invalid("Only GET requests are supported")),
+        _ => Err(invalid("Malformed request line")),
+    }
+}
+
+/// Whether a `Host` header names this machine: `localhost`, a loopback
+/// address, or `local`, the address the request arrived on. Any other
+/// name could be pointed at us by someone else's DNS.
+fn is_local_host(host: &str, local: IpAddr) -> bool {
+    // The port, if any, comes after the last colon, except inside the
+    // brackets around an IPv6 address.
+    let name = match host.strip_prefix('[') {
+        Some(bracketed) => bracketed
+            .split_once(']')
+            .map_or(bracketed, |(name, _)| name),
+        None => host.split_once(':').map_or(host, |(name, _)| name),
+    };
+    if name.eq_ignore_ascii_case("localhost") {
+        return true;
+    }
+    name.parse::<IpAddr>()
+        .is_ok_and(|ip| ip.is_loopback() || ip == local)
+}
+
+fn answer(target: &str, cache: &IndexCache) -> Response {
+    let (path, query_string) = target.split_once('?').unwrap_or((target, ""));
+    let query = match path {
+        "/projects" => IndexQuery::default(),
+        "/query" => match parse_query(query_string) {
+            Ok(query) => query,
+            Err(e) => return Response::error(400, "Bad Request", &e),
+        },
+        _ => return Response::error(404, "Not Found", &format!("No endpoint {:?}", path)),
+    };
+    let projects = cache.get().and_then(|index| {
+        Ok(index
+            .query(&query)?
+            .into_iter()
+            .cloned()
+            .collect::<Vec<_>>())
+    });
+    match projects {
+        Ok(projects) => Response::ok(serde_json::json!({ "projects": projects })),
+        Err(e) => Response::error(500, "Internal Server Error", &format!("{:#}", e)),
+    }
+}
+
+/// Parses the `pattern` and `root` parameters of `/query`.
+fn parse_query(query_string: &str) -> Result<IndexQuery, String> {
+    let mut query = IndexQuery::default();
+    for param in query_string.split('&').filter(|param| !param.is_empty()) {
+        let (name, value) = param.split_once('=').unwrap_or((param, ""));
+        let value = percent_decode(value).ok_or_else(|| format!("Invalid {:?} parameter", name))?;
+        match name {
+            "pattern" => {
+                regex::Regex::new(&value).map_err(|e| e.to_string())?;
+                query.filter = Some(value);
+            }
+            "root" => query.root = Some(PathBuf::from(value)),
+            _ => return Err(format!("Unknown parameter {:?}", name)),
+        }
+    }
+    Ok(query)
+}
+
+/// Decodes a query string value, where `+` is a space and `%XX` is a
+/// byte. Returns None if it isn't valid UTF-8 once decoded.
+fn percent_decode(value: &str) -> Option<String> {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => decoded.push(b' '),
+            b'%' => {
+                let hex = bytes.get(i + 1..i + 3)?;
+                if !hex.iter().all(u8::is_ascii_hexdigit) {
+                    return None;
+                }
+                decoded.push(u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok()?);
+                i += 2;
+            }
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8(decoded).ok()
+}
+
+struct Response {
+    status: u16,
+    reason: &'static str,
+    body: serde_json::Value,
+}
+
+impl Response {
+    fn ok(body: serde_json::Value) -> Self {
+        Response {
+            status: 200,
+            reason: "OK",
+            body,
+        }
+    }
+
+    fn error(status: u16, reason: &'static str, message: &str) -> Self {
+        Response {
+            status,
+            reason,
+            body: serde_json::json!({ "error": message }),
+        }
+    }
+
+    fn write_to(&self, mut stream: &TcpStream) -> anyhow::Result<()> {
+        let body = serde_json::to_vec(&self.body)?;
+        write!(
+            stream,
+            "HTTP/1.1 {} {}\r\n\
+             Content-Type: application/json\r\n\
+             Content-Length: {}\r\n\
+             Connection: close\r\n\
+             \r\n",
+            self.status,
+            self.reason,
+            body.len()
+        )?;
+        stream.write_all(&body)?;
+        stream.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+
+    #[test]
+    fn percent_decode_decodes_escapes() {
+        assert_eq!(percent_decode("a+b%20c").as_deref(), Some("a b c"));
+        assert_eq!(percent_decode("%2Fsrc%2f").as_deref(), Some("/src/"));
+        assert_eq!(percent_decode("caf%C3%A9").as_deref(), Some("café"));
+        assert_eq!(percent_decode("").as_deref(), Some(""));
+    }
+
+    #[test]
+    fn percent_decode_rejects_bad_escapes() {
+        assert_eq!(percent_decode("%"), None);
+        assert_eq!(percent_decode("%2"), None);
+        assert_eq!(percent_decode("%zz"), None);
+        assert_eq!(percent_decode("%+1"), None);
+        // Not UTF-8 once decoded.
+        assert_eq!(percent_decode("%FF"), None);
+    }
+
+    #[test]
+    fn parse_query_reads_parameters() {
+        let query = parse_query("pattern=%5Esrc&root=%2Fhome%2Fme&").unwrap();
+        assert_eq!(query.filter.as_deref(), Some("^src"));
+        assert_eq!(query.root.as_deref(), Some(Path::new("/home/me")));
+        let query = parse_query("").unwrap();
+        assert_eq!(query.filter, None);
+        assert_eq!(query.root, None);
+    }
+
+    #[test]
+    fn parse_query_rejects_bad_parameters() {
+        assert!(parse_query("colour=blue").is_err());
+        assert!(parse_query("pattern=%28").is_err());
+        assert!(parse_query("root=%FF").is_err());
+    }
+
+    #[test]
+    fn only_local_hosts_are_allowed() {
+        let local = IpAddr::V4(Ipv4Addr::new(192, 168, 1, 2));
+        for host in [
+            "localhost",
+            "LOCALHOST:8080",
+            "127.0.0.1:80",
+            "[::1]:80",
+            "192.168.1.2",
+        ] {
+            assert!(is_local_host(host, local), "{}", host);
+        }
+        for host in [
+            "evil.example",
+            "evil.example:80",
+            "10.0.0.1",
+            "localhost.evil.example",
+        ] {
+            assert!(!is_local_host(host, local), "{}", host);
+        }
+    }
+}
@@ -0,0 +1,412 @@
+//! Just enough of an SFTP (version 3) client to search another machine
+//! through [`FileSystem`], for `--remote` when pj isn't installed
+//! there. It talks to the SFTP server through `ssh -s HOST sftp`.
+
+use std::ffi::OsString;
+use std::io;
+use std::io::BufReader;
+use std::io::BufWriter;
+use std::io::Read;
+use std::io::Write;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::ffi::OsStringExt;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Child;
+use std::process::ChildStdin;
+use std::process::ChildStdout;
+use std::process::Command;
+use std::process::Stdio;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::SystemTime;
+
+use anyhow::anyhow;
+use anyhow::Context as _;
+use pj::file_system::DirEntry;
+use pj::file_system::FileSystem;
+use pj::file_system::FileType;
+use pj::file_system::Metadata;
+use pj::file_system::ReadDir;
+
+const SSH_FXP_INIT: u8 = 1;
+const SSH_FXP_VERSION: u8 = 2;
+const SSH_FXP_OPEN: u8 = 3;
+const SSH_FXP_CLOSE: u8 = 4;
+const SSH_FXP_READ: u8 = 5;
+const SSH_FXP_OPENDIR: u8 = 11;
+const SSH_FXP_READDIR: u8 = 12;
+const SSH_FXP_REALPATH: u8 = 16;
+const SSH_FXP_STAT: u8 = 17;
+const SSH_FXP_READLINK: u8 = 19;
+const SSH_FXP_STATUS: u8 = 101;
+const SSH_FXP_HANDLE: u8 = 102;
+const SSH_FXP_DATA: u8 = 103;
+const SSH_FXP_NAME: u8 = 104;
+const SSH_FXP_ATTRS: u8 = 105;
+
+const SSH_FX_EOF: u32 = 1;
+const SSH_FX_NO_SUCH_FILE: u32 = 2;
+const SSH_FX_PERMISSION_DENIED: u32 = 3;
+
+const SSH_FXF_READ: u32 = 0x1;
+
+const SSH_FILEXFER_ATTR_SIZE: u32 = 0x1;
+const SSH_FILEXFER_ATTR_UIDGID: u32 = 0x2;
+const SSH_FILEXFER_ATTR_PERMISSIONS: u32 = 0x4;
+const SSH_FILEXFER_ATTR_ACMODTIME: u32 = 0x8;
+const SSH_FILEXFER_ATTR_EXTENDED: u32 = 0x8000_0000;
+
+const S_IFMT: u32 = 0o170000;
+const S_IFDIR: u32 = 0o040000;
+const S_IFLNK: u32 = 0o120000;
+
+/// The most we ask for in one read, which every server allows.
+const MAX_READ: u32 = 32 * 1024;
+
+/// The longest packet we accept, the same limit OpenSSH's server has.
+/// Anything longer is a broken or hostile server, which shouldn't get
+/// to decide how much we allocate.
+const MAX_PACKET: u32 = 256 * 1024;
+
+/// A file system on another machine, reached over SFTP. Requests are
+/// sent one at a time, so searches are only as parallel as the
+/// connection's round trips allow.
+#[derive(Debug)]
+pub struct SftpFileSystem {
+    connection: Mutex<Connection>,
+}
+
+#[derive(Debug)]
+struct Connection {
+    ssh: Arc<Mutex<Child>>,
+    stdin: BufWriter<ChildStdin>,
+    stdout: BufReader<ChildStdout>,
+    next_id: u32,
+}
+
+impl SftpFileSystem {
+    /// Starts an SFTP session with `host`, which is anything ssh takes,
+    /// e.g. `user@host`.
+    pub fn connect(host: &str) -> anyhow::Result<Self> {
+        let mut ssh = Command::new("ssh")
+            .arg("-s")
+            .arg(host)
+            .arg("sftp")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .context("Failed to run ssh")?;
+        let mut connection = Connection {
+            stdin: BufWriter::new(ssh.stdin.take().unwrap()),
+            stdout: BufReader::new(ssh.stdout.take().unwrap()),
+            ssh: Arc::new(Mutex::new(ssh)),
+            next_id: 0,
+        };
+        let mut init = vec![SSH_FXP_INIT];
+        put_u32(&mut init, 3);
+        connection.send(&init)?;
+        match connection.receive()?.first() {
+            Some(&SSH_FXP_VERSION) => Ok(SftpFileSystem {
+                connection: Mutex::new(connection),
+            }),
+            _ => Err(anyhow!("{} didn't start an SFTP session", host)),
+        }
+    }
+
+    /// The ssh process the session runs over. Killing it makes every
+    /// request fail straight away, rather than wait for the server.
+    pub fn ssh(&self) -> Arc<Mutex<Child>> {
+        self.connection.lock().unwrap().ssh.clone()
+    }
+
+    /// Sends a request and waits for the response, which is returned
+    /// if it's of the `expected` type.
+    fn request(&self, kind: u8, body: &[u8], expected: u8) -> io::Result<Vec<u8>> {
+        let mut connection = self.connection.lock().unwrap();
+        let id = connection.next_id;
+        connection.next_id = connection.next_id.wrapping_add(1);
+        let mut packet = vec![kind];
+        put_u32(&mut packet, id);
+        packet.extend_from_slice(body);
+        connection.send(&packet)?;
+        let response = connection.receive()?;
+        drop(connection);
+
+        let mut reader = Reader(&response);
+        let response_kind = reader.u8()?;
+        if reader.u32()? != id {
+            return Err(invalid_data("Response to the wrong request"));
+        }
+        match response_kind {
+            kind if kind == expected => Ok(reader.0.to_vec()),
+            SSH_FXP_STATUS => Err(status_error(&mut reader)?),
+            _ => Err(invalid_data("Unexpected response")),
+        }
+    }
+
+    /// Opens the file or directory at `path`, returning its handle.
+    fn open(&self, kind: u8, path: &Path) -> io::Result<Vec<u8>> {
+        let mut body = Vec::new();
+        put_string(&mut body, path.as_os_str().as_bytes());
+        if kind == SSH_FXP_OPEN {
+            put_u32(&mut body, SSH_FXF_READ);
+            // No attributes, since we aren't creating anything.
+            put_u32(&mut body, 0);
+        }
+        let response = self.request(kind, &body, SSH_FXP_HANDLE)?;
+        Ok(Reader(&response).string()?.to_vec())
+    }
+
+    fn close(&self, handle: &[u8]) {
+        let mut body = Vec::new();
+        put_string(&mut body, handle);
+        // The handle's no use to us anymore whatever happens.
+        let _ = self.request(SSH_FXP_CLOSE, &body, SSH_FXP_STATUS);
+    }
+
+    /// Sends a request about `path` which is answered with a single
+    /// name, like `SSH_FXP_REALPATH`.
+    fn name(&self, kind: u8, path: &Path) -> io::Result<PathBuf> {
+        let mut body = Vec::new();
+        put_string(&mut body, path.as_os_str().as_bytes());
+        let response = self.request(kind, &body, SSH_FXP_NAME)?;
+        let mut reader = Reader(&response);
+        if reader.u32()? == 0 {
+            return Err(invalid_data("No name in the response"));
+        }
+        Ok(PathBuf::from(OsString::from_vec(reader.string()?.to_vec())))
+    }
+
+    fn read_dir_entries(&self, handle: &[u8]) -> io::Result<Vec<io::Result<DirEntry>>> {
+        let mut body = Vec::new();
+        put_string(&mut body, handle);
+        let mut entries = Vec::new();
+        loop {
+            let response = match self.request(SSH_FXP_READDIR, &body, SSH_FXP_NAME) {
+                Ok(response) => response,
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(entries),
+                Err(e) => return Err(e),
+            };
+            let mut reader = Reader(&response);
+            for _ in 0..reader.u32()? {
+                let file_name = reader.string()?;
+                let _long_name = reader.string()?;
+                let attrs = Attrs::read(&mut reader)?;
+                if file_name == b"." || file_name == b".." {
+                    continue;
+                }
+                entries.push(Ok(DirEntry {
+                    file_name: OsString::from_vec(file_name.to_vec()),
+                    file_type: attrs.file_type(),
+                }));
+            }
+        }
+    }
+
+    fn read_file(&self, handle: &[u8], limit: u64) -> io::Result<Vec<u8>> {
+        let mut contents = Vec::new();
+        while (contents.len() as u64) < limit {
+            let remaining = limit - contents.len() as u64;
+            let mut body = Vec::new();
+            put_string(&mut body, handle);
+            put_u64(&mut body, contents.len() as u64);
+            put_u32(&mut body, remaining.min(MAX_READ.into()) as u32);
+            let response = match self.request(SSH_FXP_READ, &body, SSH_FXP_DATA) {
+                Ok(response) => response,
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e),
+            };
+            let data = Reader(&response).string()?;
+            if data.is_empty() {
+                break;
+            }
+            contents.extend_from_slice(data);
+        }
+        Ok(contents)
+    }
+}
+
+impl FileSystem for SftpFileSystem {
+    fn read_dir(&self, path: &Path) -> io::Result<ReadDir<'_>> {
+        let handle = self.open(SSH_FXP_OPENDIR, path)?;
+        let entries = self.read_dir_entries(&handle);
+        self.close(&handle);
+        Ok(Box::new(entries?.into_iter()))
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        let mut body = Vec::new();
+        put_string(&mut body, path.as_os_str().as_bytes());
+        let response = self.request(SSH_FXP_STAT, &body, SSH_FXP_ATTRS)?;
+        let attrs = Attrs::read(&mut Reader(&response))?;
+        Ok(Metadata {
+            file_type: attrs.file_type(),
+            modified: (attrs.modified)
+                .map(|modified| SystemTime::UNIX_EPOCH + Duration::from_secs(modified.into())),
+            device: None,
+            inode: None,
+            size: attrs.size.filter(|_| !attrs.file_type().is_dir()),
+            permissions: attrs.permissions.map(|permissions| permissions & 0o7777),
+            // The other machine's users aren't ours.
+            uid: None,
+        })
+    }
+
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf> {
+        self.name(SSH_FXP_READLINK, path)
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        self.name(SSH_FXP_REALPATH, path)
+    }
+
+    fn read(&self, path: &Path, limit: u64) -> io::Result<Vec<u8>> {
+        let handle = self.open(SSH_FXP_OPEN, path)?;
+        let contents = self.read_file(&handle, limit);
+        self.close(&handle);
+        contents
+    }
+}
+
+impl Connection {
+    fn send(&mut self, packet: &[u8]) -> io::Result<()> {
+        self.stdin.write_all(&(packet.len() as u32).to_be_bytes())?;
+        self.stdin.write_all(packet)?;
+        self.stdin.flush()
+    }
+
+    fn receive(&mut self) -> io::Result<Vec<u8>> {
+        let mut len = [0; 4];
+        self.stdout.read_exact(&mut len)?;
+        let len = u32::from_be_bytes(len);
+        if len > MAX_PACKET {
+            return Err(invalid_data("Packet too long"));
+        }
+        let mut packet = vec![0; len as usize];
+        self.stdout.read_exact(&mut packet)?;
+        Ok(packet)
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        let mut ssh = self.ssh.lock().unwrap();
+        let _ = ssh.kill();
+        let _ = ssh.wait();
+    }
+}
+
+/// The attributes we use from an `ATTRS` structure.
+struct Attrs {
+    size: Option<u64>,
+    permissions: Option<u32>,
+    /// Seconds since the Unix epoch.
+    modified: Option<u32>,
+}
+
+impl Attrs {
+    fn read(reader: &mut Reader) -> io::Result<Self> {
+        let flags = reader.u32()?;
+        let size = match flags & SSH_FILEXFER_ATTR_SIZE {
+            0 => None,
+            _ => Some(reader.u64()?),
+        };
+        if flags & SSH_FILEXFER_ATTR_UIDGID != 0 {
+            reader.u32()?;
+            reader.u32()?;
+        }
+        let permissions = match flags & SSH_FILEXFER_ATTR_PERMISSIONS {
+            0 => None,
+            _ => Some(reader.u32()?),
+        };
+        let modified = match flags & SSH_FILEXFER_ATTR_ACMODTIME {
+            0 => None,
+            _ => {
+                reader.u32()?;
+                Some(reader.u32()?)
+            }
+        };
+        if flags & SSH_FILEXFER_ATTR_EXTENDED != 0 {
+            for _ in 0..reader.u32()? {
+                reader.string()?;
+                reader.string()?;
+            }
+        }
+        Ok(Attrs {
+            size,
+            permissions,
+            modified,
+        })
+    }
+
+    fn file_type(&self) -> FileType {
+        match self.permissions.map(|permissions| permissions & S_IFMT) {
+            Some(S_IFDIR) => FileType::Dir,
+            Some(S_IFLNK) => FileType::Symlink,
+            _ => FileType::File,
+        }
+    }
+}
+
+/// Reads the fields of a packet in order.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        if self.0.len() < len {
+            return Err(invalid_data("Truncated packet"));
+        }
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        Ok(u64::from_be_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn string(&mut self) -> io::Result<&'a [u8]> {
+        let len = self.u32()?;
+        self.take(len as usize)
+    }
+}
+
+fn put_u32(packet: &mut Vec<u8>, n: u32) {
+    packet.extend_from_slice(&n.to_be_bytes());
+}
+
+fn put_u64(packet: &mut Vec<u8>, n: u64) {
+    packet.extend_from_slice(&n.to_be_bytes());
+}
+
+fn put_string(packet: &mut Vec<u8>, s: &[u8]) {
+    put_u32(packet, s.len() as u32);
+    packet.extend_from_slice(s);
+}
+
+/// Turns an `SSH_FXP_STATUS` response into an error. End of file is
+/// `UnexpectedEof`, so that it can be told apart.
+fn status_error(reader: &mut Reader) -> io::Result<io::Error> {
+    let code = reader.u32()?;
+    let message = String::from_utf8_lossy(reader.string()?).into_owned();
+    Ok(match code {
+        SSH_FX_EOF => io::Error::from(io::ErrorKind::UnexpectedEof),
+        SSH_FX_NO_SUCH_FILE => io::Error::new(io::ErrorKind::NotFound, message),
+        SSH_FX_PERMISSION_DENIED => io::Error::new(io::ErrorKind::PermissionDenied, message),
+        _ => io::Error::other(message),
+    })
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
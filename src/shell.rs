@@ -0,0 +1,64 @@
+use std::env;
+use std::str::FromStr;
+
+use anyhow::anyhow;
+
+#[derive(Clone, Copy, Debug)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+impl Shell {
+    pub const NAMES: &'static [&'static str] = &["bash", "zsh", "fish"];
+}
+
+impl FromStr for Shell {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "bash" => Ok(Shell::Bash),
+            "zsh" => Ok(Shell::Zsh),
+            "fish" => Ok(Shell::Fish),
+            _ => Err(anyhow!("Unsupported shell {:?}", s)),
+        }
+    }
+}
+
+/// Generates a shell function called `name` which picks a project with
+/// `pj pick` and changes into it. Any arguments to the function are
+/// passed along to `pj pick`.
+///
+/// The function calls this executable by its full path, so it always
+/// understands the same flags as the `pj` which generated it.
+pub fn init_script(shell: Shell, name: &str) -> anyhow::Result<String> {
+    let exe = env::current_exe()?;
+    let exe = exe
+        .to_str()
+        .ok_or_else(|| anyhow!("Cannot convert path {:?} to str", exe))?;
+    let exe = quote(exe);
+
+    Ok(match shell {
+        Shell::Bash | Shell::Zsh => format!(
+            r#"{name}() {{
+    local dir
+    dir="$({exe} pick "$@")" && cd -- "$dir"
+}}
+"#
+        ),
+        Shell::Fish => format!(
+            r#"function {name}
+    set -l dir ({exe} pick $argv); and cd -- $dir
+end
+"#
+        ),
+    })
+}
+
+/// Single-quotes `s`, which means the same thing in every shell we
+/// support.
+fn quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
@@ -0,0 +1,173 @@
+use std::cell::Cell;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use std::time::Instant;
+
+use crossbeam::utils::CachePadded;
+
+/// What a search did, from [`Matches::stats`](crate::Matches::stats).
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Stats {
+    pub dirs_scanned: u64,
+    pub entries_examined: u64,
+    /// Directories and entries which couldn't be read, and were
+    /// skipped.
+    pub errors: u64,
+    pub matches: u64,
+    /// Time since the search started.
+    pub wall_time: Duration,
+    /// CPU time the workers spent scanning, summed across all of them.
+    /// Always zero on platforms without per-thread CPU clocks.
+    pub cpu_time: Duration,
+    /// The most jobs which were ever waiting in the queue at once.
+    pub max_queued: u64,
+    /// Jobs which went through the queue. With `--max-queue`, the rest
+    /// were run in place by the worker which found them.
+    pub queue_waits: u64,
+    /// Time jobs spent in the queue before a worker picked them up,
+    /// including any wait for a turn with adaptive threads, summed
+    /// across all of them.
+    pub queue_wait_time: Duration,
+}
+
+/// The counters for a single worker thread.
+#[derive(Default)]
+pub(crate) struct WorkerCounters {
+    pub(crate) dirs_scanned: AtomicU64,
+    pub(crate) entries_examined: AtomicU64,
+    pub(crate) errors: AtomicU64,
+    pub(crate) matches: AtomicU64,
+    cpu_nanos: AtomicU64,
+    max_queued: AtomicU64,
+    queue_waits: AtomicU64,
+    queue_wait_nanos: AtomicU64,
+}
+
+impl WorkerCounters {
+    pub(crate) fn add(counter: &AtomicU64, n: u64) {
+        counter.fetch_add(n, Ordering::Relaxed);
+    }
+
+    /// Records that a job was queued behind `queued - 1` others.
+    pub(crate) fn add_queued(&self, queued: u64) {
+        // Most jobs don't set a new high-water mark, so this rarely
+        // needs to write.
+        if queued > self.max_queued.load(Ordering::Relaxed) {
+            self.max_queued.fetch_max(queued, Ordering::Relaxed);
+        }
+    }
+
+    /// Records how long a job spent in the queue.
+    pub(crate) fn add_queue_wait(&self, elapsed: Duration) {
+        Self::add(&self.queue_waits, 1);
+        Self::add(&self.queue_wait_nanos, elapsed.as_nanos() as u64);
+    }
+}
+
+/// Counters for a whole search. Each worker thread gets its own, so
+/// they never contend with each other, and they're only added up when
+/// someone asks for the stats.
+pub(crate) struct Counters {
+    started: Instant,
+    workers: Vec<CachePadded<WorkerCounters>>,
+}
+
+impl Counters {
+    pub(crate) fn new(threads: usize) -> Self {
+        Counters {
+            started: Instant::now(),
+            // One extra for anything which runs outside the pool.
+            workers: (0..threads + 1).map(|_| CachePadded::default()).collect(),
+        }
+    }
+
+    /// The counters for the current worker thread.
+    pub(crate) fn local(&self) -> &WorkerCounters {
+        let index = rayon::current_thread_index()
+            .filter(|&index| index + 1 < self.workers.len())
+            .unwrap_or(self.workers.len() - 1);
+        &self.workers[index]
+    }
+
+    pub(crate) fn stats(&self) -> Stats {
+        let sum = |counter: fn(&WorkerCounters) -> &AtomicU64| {
+            self.workers
+                .iter()
+                .map(|worker| counter(worker).load(Ordering::Relaxed))
+                .sum()
+        };
+        Stats {
+            dirs_scanned: sum(|worker| &worker.dirs_scanned),
+            entries_examined: sum(|worker| &worker.entries_examined),
+            errors: sum(|worker| &worker.errors),
+            matches: sum(|worker| &worker.matches),
+            wall_time: self.started.elapsed(),
+            cpu_time: Duration::from_nanos(sum(|worker| &worker.cpu_nanos)),
+            max_queued: (self.workers.iter())
+                .map(|worker| worker.max_queued.load(Ordering::Relaxed))
+                .max()
+                .unwrap_or(0),
+            queue_waits: sum(|worker| &worker.queue_waits),
+            queue_wait_time: Duration::from_nanos(sum(|worker| &worker.queue_wait_nanos)),
+        }
+    }
+}
+
+thread_local! {
+    /// Whether a [`CpuTimer`] is running on this thread.
+    static TIMING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Measures the CPU time a job takes on the current thread. Jobs run in
+/// place by another job are already covered by its timer, so only the
+/// outermost one on a thread gets one.
+pub(crate) struct CpuTimer {
+    started: Option<Duration>,
+}
+
+impl CpuTimer {
+    /// Starts timing, unless an enclosing job on this thread already is.
+    pub(crate) fn start() -> Option<Self> {
+        if TIMING.get() {
+            return None;
+        }
+        TIMING.set(true);
+        Some(CpuTimer {
+            started: thread_cpu_time(),
+        })
+    }
+
+    /// Adds the CPU time used since [`CpuTimer::start`] to `counters`.
+    pub(crate) fn finish(self, counters: &WorkerCounters) {
+        if let (Some(started), Some(now)) = (self.started, thread_cpu_time()) {
+            let used = now.saturating_sub(started);
+            WorkerCounters::add(&counters.cpu_nanos, used.as_nanos() as u64);
+        }
+    }
+}
+
+impl Drop for CpuTimer {
+    fn drop(&mut self) {
+        TIMING.set(false);
+    }
+}
+
+/// The CPU time used by the current thread so far.
+#[cfg(unix)]
+fn thread_cpu_time() -> Option<Duration> {
+    let mut time = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    // SAFETY: clock_gettime only writes to `time`, which is valid.
+    if unsafe { libc::clock_gettime(libc::CLOCK_THREAD_CPUTIME_ID, &mut time) } != 0 {
+        return None;
+    }
+    Some(Duration::new(time.tv_sec as u64, time.tv_nsec as u32))
+}
+
+#[cfg(not(unix))]
+fn thread_cpu_time() -> Option<Duration> {
+    None
+}
@@ -0,0 +1,418 @@
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::collections::VecDeque;
+use std::ffi::OsStr;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::mpsc;
+use std::sync::mpsc::Receiver;
+use std::sync::mpsc::RecvTimeoutError;
+use std::time::Instant;
+
+use notify::event::ModifyKind;
+use notify::event::RenameMode;
+use notify::Event;
+use notify::EventKind;
+use notify::RecommendedWatcher;
+use notify::RecursiveMode;
+use notify::Watcher;
+
+use crate::finder::Finder;
+use crate::finder::Match;
+use crate::finder::Matches;
+
+/// A change to the set of matching directories, reported by
+/// [`Finder::watch`].
+#[derive(Clone, Debug)]
+pub enum WatchEvent {
+    /// A directory matched, either during the initial scan or because
+    /// it was created afterwards.
+    Added(Match),
+    /// A directory which was previously reported no longer matches.
+    Removed(PathBuf),
+}
+
+impl Finder {
+    /// Searches every root like [`Finder::run`], and then keeps
+    /// watching the roots for matching directories being created or
+    /// removed. The iterator only ends if the watcher shuts down.
+    pub fn watch(&self) -> anyhow::Result<Watch<'_>> {
+        // Start watching before the initial scan so that nothing
+        // created while it's running gets missed.
+        let mut watch = self.watch_changes(Vec::new())?;
+        watch.initial = Some(self.run());
+        Ok(watch)
+    }
+
+    /// Watches the roots like [`Finder::watch`], but without the
+    /// initial scan. `known` are taken to be the matches so far, e.g.
+    /// from an [`Index`](crate::Index), and only changes to them are
+    /// reported.
+    pub fn watch_changes<I>(&self, known: I) -> anyhow::Result<Watch<'_>>
+    where
+        I: IntoIterator<Item = PathBuf>,
+    {
+        let (sender, receiver) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(sender)?;
+        for root in self.roots.iter() {
+            watcher.watch(root, RecursiveMode::Recursive)?;
+        }
+        let roots = (self.roots.iter())
+            .map(|root| {
+                (
+                    fs::canonicalize(root).unwrap_or_else(|_| root.clone()),
+                    root.clone(),
+                )
+            })
+            .collect();
+
+        Ok(Watch {
+            finder: self,
+            _watcher: watcher,
+            roots,
+            receiver,
+            initial: None,
+            known: known.into_iter().collect(),
+            canonical: HashMap::new(),
+            pending: VecDeque::new(),
+        })
+    }
+}
+
+/// Iterator over changes to the set of matching directories, returned
+/// by [`Finder::watch`].
+pub struct Watch<'a> {
+    finder: &'a Finder,
+    _watcher: RecommendedWatcher,
+    /// Each root canonicalized, with the root as it was given.
+    roots: Vec<(PathBuf, PathBuf)>,
+    receiver: Receiver<notify::Result<Event>>,
+    initial: Option<Matches>,
+    known: HashSet<PathBuf>,
+    /// The path each match was reported by, keyed by its canonical
+    /// path, so a directory isn't reported again through a symlink.
+    canonical: HashMap<PathBuf, PathBuf>,
+    pending: VecDeque<WatchEvent>,
+}
+
+impl Iterator for Watch<'_> {
+    type Item = WatchEvent;
+
+    fn next(&mut self) -> Option<WatchEvent> {
+        self.next_before(None).ok()
+    }
+}
+
+impl Watch<'_> {
+    /// Like [`Iterator::next`], but gives up waiting for a change at
+    /// `deadline`.
+    pub(crate) fn next_before(
+        &mut self,
+        deadline: Option<Instant>,
+    ) -> Result<WatchEvent, RecvTimeoutError> {
+        while let Some(initial) = &mut self.initial {
+            let Some(found) = initial.next() else {
+                self.initial = None;
+                break;
+            };
+            if !self.is_reported_elsewhere(&found.path) && self.known.insert(found.path.clone()) {
+                return Ok(WatchEvent::Added(found));
+            }
+        }
+
+        loop {
+            if let Some(event) = self.pending.pop_front() {
+                return Ok(event);
+            }
+            let received = match deadline {
+                Some(deadline) => (self.receiver)
+                    .recv_timeout(deadline.saturating_duration_since(Instant::now()))?,
+                None => (self.receiver.recv()).map_err(|_| RecvTimeoutError::Disconnected)?,
+            };
+            match received {
+                Ok(event) => self.handle_event(event),
+                Err(e) => tracing::error!("Watching failed: {}", e),
+            }
+        }
+    }
+
+    fn handle_event(&mut self, event: Event) {
+        let paths: Vec<PathBuf> = (event.paths.iter())
+            .filter_map(|path| self.given_path(path))
+            .collect();
+        match event.kind {
+            EventKind::Create(_) => paths.iter().for_each(|path| self.created(path)),
+            EventKind::Remove(_) => paths.iter().for_each(|path| self.removed(path)),
+            EventKind::Modify(ModifyKind::Name(mode)) => match (mode, paths.as_slice()) {
+                (RenameMode::From, paths) => paths.iter().for_each(|path| self.removed(path)),
+                (RenameMode::To, paths) => paths.iter().for_each(|path| self.created(path)),
+                (RenameMode::Both, [from, to]) => {
+                    self.removed(from);
+                    self.created(to);
+                }
+                // We don't know which side of the rename we're looking
+                // at, so go by whether the path still exists.
+                (_, paths) => {
+                    for path in paths {
+                        if self.exists(path) {
+                            self.created(path);
+                        } else {
+                            self.removed(path);
+                        }
+                    }
+                }
+            },
+            _ => {}
+        }
+    }
+
+    fn created(&mut self, path: &Path) {
+        let Some(depth) = self.depth(path) else {
+            return;
+        };
+        if self.finder.is_ignored_by_files(path, depth) {
+            return;
+        }
+        if let Some(parent) = path.parent().filter(|_| self.is_excluded_name(path)) {
+            self.forget(|known| known.starts_with(parent));
+            return;
+        }
+        if self.is_pruned(path) || self.is_excluded(path, depth) {
+            return;
+        }
+        let options = &self.finder.options;
+
+        if let (Some(parent), Some(parent_depth)) = (path.parent(), depth.checked_sub(1)) {
+            let found = (self.is_sentinel_name(path)
+                && !self.finder.exceeds_max_depth(parent, parent_depth)
+                && !options.below_min_depth(parent_depth)
+                && (options.unreported_because(&*self.finder.fs, parent)).is_none())
+            .then(|| self.finder.sentinel_in(parent))
+            .flatten();
+            if let Some((_, patterns)) = found {
+                self.added(Match {
+                    path: parent.to_path_buf(),
+                    sentinel: path.to_path_buf(),
+                    patterns,
+                    depth: parent_depth,
+                });
+            }
+        }
+
+        // Whole trees can show up at once (e.g. a project being moved
+        // in), so search new directories just like the initial scan.
+        let is_dir = self.finder.is_dir(path) && (options.follow || !self.is_symlink(path));
+        let is_skipped = path
+            .file_name()
+            .is_some_and(|file_name| self.finder.skips_dir_name(file_name));
+        if is_dir && !is_skipped && !self.finder.exceeds_max_depth(path, depth) {
+            for found in self.finder.search([(path.to_path_buf(), depth)]) {
+                self.added(found);
+            }
+        }
+    }
+
+    fn removed(&mut self, path: &Path) {
+        let parent_depth = self.depth(path).and_then(|depth| depth.checked_sub(1));
+        if let (Some(parent), Some(parent_depth)) = (path.parent(), parent_depth) {
+            // Removing an exclusion uncovers everything it was hiding.
+            if self.is_excluded_name(path)
+                && !self.is_excluded(path, parent_depth + 1)
+                && !self.finder.exceeds_max_depth(parent, parent_depth)
+            {
+                for found in self.finder.search([(parent.to_path_buf(), parent_depth)]) {
+                    self.added(found);
+                }
+            }
+        }
+        if let Some(parent) = path.parent() {
+            // The directory might contain more than one entry which
+            // matches the sentinel.
+            if self.is_sentinel_name(path) && !self.finder.dir_matches(parent) {
+                self.forget(|known| known == parent);
+            }
+        }
+        self.forget(|known| known.starts_with(path));
+    }
+
+    fn added(&mut self, found: Match) {
+        if !self.is_pruned(&found.path)
+            && !self.is_root_done(&found.path)
+            && !self.is_reported_elsewhere(&found.path)
+            && self.known.insert(found.path.clone())
+        {
+            self.pending.push_back(WatchEvent::Added(found));
+        }
+    }
+
+    /// Whether --first-per-root has already reported a match beneath
+    /// the root `path` is in. Once that match goes away, another can
+    /// take its place.
+    fn is_root_done(&self, path: &Path) -> bool {
+        if !self.finder.options.first_per_root {
+            return false;
+        }
+        let Some(root) = (self.finder.roots.iter()).find(|root| path.starts_with(root)) else {
+            return false;
+        };
+        self.known.iter().any(|known| known.starts_with(root))
+    }
+
+    /// Whether the directory at `path` is already known through another
+    /// path, like a search only reports it once. Otherwise, `path` is
+    /// recorded as the way it's reported.
+    fn is_reported_elsewhere(&mut self, path: &Path) -> bool {
+        let Ok(canonical) = self.finder.fs.canonicalize(path) else {
+            return false;
+        };
+        match self.canonical.entry(canonical) {
+            Entry::Occupied(entry) if entry.get() != path && self.known.contains(entry.get()) => {
+                true
+            }
+            Entry::Occupied(mut entry) => {
+                entry.insert(path.to_path_buf());
+                false
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(path.to_path_buf());
+                false
+            }
+        }
+    }
+
+    /// Whether `path` is inside a known match, and so wouldn't have
+    /// been searched by the initial scan. With --nested, that's only
+    /// once it's too far below the match.
+    fn is_pruned(&self, path: &Path) -> bool {
+        let options = &self.finder.options;
+        if options.no_prune {
+            return false;
+        }
+        // The closest match above is the one whose limit applies.
+        let below = (path.ancestors().skip(1))
+            .position(|ancestor| self.known.contains(ancestor))
+            .map(|i| i + 1);
+        below.is_some_and(|below| options.nested.is_none_or(|nested| below > nested))
+    }
+
+    fn forget<F>(&mut self, mut predicate: F)
+    where
+        F: FnMut(&Path) -> bool,
+    {
+        let pending = &mut self.pending;
+        self.known.retain(|known| {
+            if predicate(known) {
+                pending.push_back(WatchEvent::Removed(known.clone()));
+                false
+            } else {
+                true
+            }
+        });
+    }
+
+    /// Converts a path from the watcher to the same path beneath the
+    /// root as it was given, so that it lines up with what searches
+    /// find. Depending on the platform, the watcher reports paths
+    /// beneath the root either as given or canonicalized. None if it
+    /// isn't beneath a root.
+    fn given_path(&self, path: &Path) -> Option<PathBuf> {
+        let beneath = |root: &Path| path.strip_prefix(root).ok();
+        self.roots.iter().find_map(|(canonical, given)| {
+            let relative = beneath(given).or_else(|| beneath(canonical))?;
+            Some(match relative.as_os_str().is_empty() {
+                true => given.clone(),
+                false => given.join(relative),
+            })
+        })
+    }
+
+    /// Finds how many levels below a root `path` is, or None if it
+    /// isn't beneath any root or passes through an ignored directory.
+    fn depth(&self, path: &Path) -> Option<usize> {
+        let relative = self
+            .finder
+            .roots
+            .iter()
+            .find_map(|root| path.strip_prefix(root).ok())?;
+        let components: Vec<&OsStr> = relative
+            .components()
+            .map(|component| component.as_os_str())
+            .collect();
+        let options = &self.finder.options;
+        for (i, component) in components.iter().enumerate() {
+            // Hidden and pruned directories aren't searched, but the
+            // entry at the end of the path can still be a sentinel.
+            let is_last = i + 1 == components.len();
+            if options.should_ignore(component)
+                || (!is_last && self.finder.skips_dir_name(component))
+            {
+                return None;
+            }
+        }
+        Some(components.len())
+    }
+
+    /// Whether any directory from the parent of `path` up to its root,
+    /// `depth` levels up, is excluded.
+    fn is_excluded(&self, path: &Path, depth: usize) -> bool {
+        path.ancestors()
+            .skip(1)
+            .take(depth)
+            .any(|dir| self.finder.is_excluded_dir(dir))
+    }
+
+    fn is_excluded_name(&self, path: &Path) -> bool {
+        path.file_name()
+            .is_some_and(|file_name| self.finder.is_excluded_name(file_name))
+    }
+
+    /// Whether there's anything at `path`, even a broken symlink.
+    fn exists(&self, path: &Path) -> bool {
+        self.finder.fs.metadata(path).is_ok() || self.is_symlink(path)
+    }
+
+    fn is_symlink(&self, path: &Path) -> bool {
+        self.finder.fs.read_link(path).is_ok()
+    }
+
+    fn is_sentinel_name(&self, path: &Path) -> bool {
+        path.file_name()
+            .is_some_and(|file_name| self.finder.is_sentinel_name(file_name))
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::env;
+    use std::os::unix::fs::symlink;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::FinderBuilder;
+
+    #[test]
+    fn reports_projects_beneath_a_symlinked_root() {
+        let dir = env::temp_dir().join(format!("pj-watch-test-{}", std::process::id()));
+        let target = dir.join("target");
+        let root = dir.join("link");
+        fs::create_dir_all(&target).unwrap();
+        symlink(&target, &root).unwrap();
+
+        let finder = FinderBuilder::new("Cargo.toml")
+            .root(&root)
+            .build()
+            .unwrap();
+        let mut watch = finder.watch().unwrap();
+        fs::create_dir(target.join("project")).unwrap();
+        fs::write(target.join("project/Cargo.toml"), "").unwrap();
+        let event = watch.next_before(Some(Instant::now() + Duration::from_secs(5)));
+        fs::remove_dir_all(&dir).unwrap();
+
+        match event {
+            Ok(WatchEvent::Added(found)) => assert_eq!(found.path, root.join("project")),
+            event => panic!("Expected the new project, got {:?}", event),
+        }
+    }
+}